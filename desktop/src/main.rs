@@ -1,13 +1,16 @@
 extern crate gba_emulator;
 
-use std::{collections::HashMap, env, fs, sync::Arc};
+use std::{cell::Cell, collections::HashMap, env, fs, path::Path, rc::Rc, sync::Arc};
 
-use gba_emulator::{cpu::{CPU, registers::key_input_register::KeyInputRegister}, gpu::{SCREEN_WIDTH, SCREEN_HEIGHT, CYCLES_PER_FRAME}, apu::APU};
+use gba_emulator::{cpu::{CPU, registers::key_input_register::KeyInputRegister}, cartridge::{gyro_sensor::MAX_ROTATION_RATE, motion_input::Motion, save_format::SaveFormat, solar_sensor::MAX_SOLAR_LEVEL}, gpu::{SCREEN_WIDTH, SCREEN_HEIGHT, CYCLES_PER_FRAME}, apu::APU, sio::{socket_link::SocketLink, dolphin_link::DolphinLink}, linked_system::LinkedSystem};
 use ringbuf::{storage::Heap, traits::{Consumer, Split}, wrap::caching::Caching, HeapRb, SharedRb};
-use sdl2::{pixels::PixelFormatEnum, event::Event, keyboard::Keycode, audio::{AudioSpecDesired, AudioCallback}};
+use sdl2::{pixels::PixelFormatEnum, event::Event, keyboard::Keycode, audio::{AudioSpecDesired, AudioCallback}, rect::Rect, controller::Axis};
 
 const NUM_SAMPLES: usize = 8192 * 2;
 
+// how fast the gyro keys turn the console, in radians per second
+const KEY_ROTATION_RATE: f32 = MAX_ROTATION_RATE / 2.0;
+
 struct GbaAudioCallback {
   consumer: Caching<Arc<SharedRb<Heap<f32>>>, false, true>
 }
@@ -26,29 +29,203 @@ impl AudioCallback for GbaAudioCallback {
   }
 }
 
+enum Emulator {
+  Single(CPU),
+  Linked(LinkedSystem)
+}
+
+impl Emulator {
+  fn consoles(&mut self) -> &mut [CPU] {
+    match self {
+      Emulator::Single(cpu) => std::slice::from_mut(cpu),
+      Emulator::Linked(system) => &mut system.consoles
+    }
+  }
+
+  fn step_frame(&mut self) {
+    match self {
+      Emulator::Single(cpu) => {
+        while !cpu.gpu.frame_finished {
+          cpu.step();
+        }
+
+        cpu.gpu.frame_finished = false;
+      }
+      Emulator::Linked(system) => system.step_frame()
+    }
+  }
+}
 
 fn main() {
 
-  let args: Vec<String> = env::args().collect();
+  let mut args = env::args().skip(1);
+
+  let mut filepath = None;
+  let mut link_host = None;
+  let mut link_connect = None;
+  let mut link_players = 2;
+  let mut local_players = None;
+  let mut dolphin = None;
+  let mut game_db = None;
+  let mut import_save = None;
+  let mut export_save = None;
+  let mut save_format = None;
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--link-host" => link_host = Some(args.next().expect("--link-host needs an address")),
+      "--link-connect" => link_connect = Some(args.next().expect("--link-connect needs an address")),
+      "--link-players" => {
+        link_players = args.next().and_then(|players| players.parse().ok()).expect("--link-players needs a number from 2 to 4")
+      }
+      "--local-players" => {
+        local_players = Some(args.next().and_then(|players| players.parse().ok()).expect("--local-players needs a number from 2 to 4"))
+      }
+      "--dolphin" => dolphin = Some(args.next().expect("--dolphin needs the address dolphin is running on")),
+      "--game-db" => game_db = Some(args.next().expect("--game-db needs a file")),
+      "--import-save" => import_save = Some(args.next().expect("--import-save needs a file")),
+      "--export-save" => export_save = Some(args.next().expect("--export-save needs a file")),
+      "--save-format" => {
+        save_format = Some(
+          args
+            .next()
+            .and_then(|name| SaveFormat::from_name(&name))
+            .expect("--save-format needs one of raw, swapped-eeprom, sharkport or gameshark-sp")
+        )
+      }
+      _ => filepath = Some(arg)
+    }
+  }
 
-  if args.len() != 2 {
+  // fall back to the built in hle bios when no bios dump is present
+  let bios = fs::read("../gba_bios.bin").ok();
+
+  // with no game the bios waits for one to be sent over joy bus, which only the real bios can do
+  if filepath.is_none() && (dolphin.is_none() || bios.is_none()) {
     panic!("please specify a file");
   }
 
-  let filepath = &args[1];
+  let bytes: Vec<u8> = filepath.as_ref().map(|filepath| fs::read(filepath).unwrap()).unwrap_or_default();
+
+  let game_db = game_db.and_then(|path| match fs::read_to_string(&path) {
+    Ok(text) => Some(text),
+    Err(error) => {
+      println!("couldn't read game database {path}: {error}");
+
+      None
+    }
+  });
+
+  let mut consumers = Vec::new();
+  let mut producers = Vec::new();
+
+  for _ in 0..local_players.unwrap_or(1) {
+    let ringbuffer = HeapRb::<f32>::new(NUM_SAMPLES);
+
+    let (producer, consumer) = ringbuffer.split();
+
+    producers.push(producer);
+    consumers.push(consumer);
+  }
+
+  // only the first console's audio is played
+  let consumer = consumers.swap_remove(0);
+
+  let mut emulator = if local_players.is_some() {
+    Emulator::Linked(LinkedSystem::new(producers))
+  } else {
+    Emulator::Single(CPU::new(producers.remove(0)))
+  };
+
+  // the gyro is turned with keys or the right stick, and the rumble motor drives the controller's
+  let motion = Rc::new(Cell::new(Motion::default()));
+  let rumble = Rc::new(Cell::new(false));
+
+  for (i, cpu) in emulator.consoles().iter_mut().enumerate() {
+    // every console runs the same game, but only the first one gets to write the save file
+    let file_path = if i == 0 { filepath.clone() } else { None };
+
+    cpu.cartridge.set_motion_input(Box::new(motion.clone()));
+    cpu.cartridge.set_rumble_output(Box::new(rumble.clone()));
+
+    if let Some(game_db) = &game_db {
+      // a file with a bad line is left out entirely, so the built in database is used as is
+      match cpu.cartridge.game_db.load(game_db) {
+        Err(error) if i == 0 => println!("ignoring game database, {error}"),
+        _ => ()
+      }
+    }
+
+    cpu.load_game(bytes.clone(), file_path);
+
+    if let Some(bios) = &bios {
+      if let Err(error) = cpu.load_bios(bios.clone()) {
+        println!("ignoring bios, {error}");
+      }
+    }
+
+    if filepath.is_some() {
+      cpu.skip_bios();
+    }
+  }
+
+  // saves from other emulators replace the game's save, and exports are converted to whatever
+  // format the file's extension says unless one is picked with --save-format
+  if let Some(path) = &import_save {
+    let cartridge = &mut emulator.consoles()[0].cartridge;
 
-  let bytes: Vec<u8> = fs::read(filepath).unwrap();
+    let imported = fs::read(path)
+      .map_err(|error| error.to_string())
+      .and_then(|contents| cartridge.import_save(&contents, save_format));
 
-  let ringbuffer = HeapRb::<f32>::new(NUM_SAMPLES);
+    if let Err(error) = imported {
+      println!("couldn't import save {path}: {error}");
+    }
+  }
 
-  let (producer, consumer) = ringbuffer.split();
+  if let Some(path) = &export_save {
+    let save_format = save_format
+      .or_else(|| Path::new(path).extension().and_then(|extension| SaveFormat::from_extension(&extension.to_string_lossy())))
+      .unwrap_or(SaveFormat::Raw);
 
-  let mut cpu = CPU::new(producer);
+    let exported = emulator.consoles()[0]
+      .cartridge
+      .export_save(save_format)
+      .and_then(|contents| fs::write(path, contents).map_err(|error| error.to_string()));
 
-  cpu.load_game(bytes, Some(filepath.to_string()));
-  cpu.load_bios(fs::read("../gba_bios.bin").unwrap());
+    if let Err(error) = exported {
+      println!("couldn't export save {path}: {error}");
+    }
 
-  cpu.skip_bios();
+    return;
+  }
+
+  // link addresses are either host:port or unix:/path/to/socket, dolphin only needs a host
+  if let Emulator::Single(cpu) = &mut emulator {
+    let linked = if let Some(address) = link_host {
+      println!("waiting for {} other player(s) to connect to {address}", link_players - 1);
+
+      SocketLink::host(&address, link_players).map(|link| cpu.set_link_transport(Box::new(link)))
+    } else if let Some(address) = link_connect {
+      SocketLink::connect(&address).map(|link| cpu.set_link_transport(Box::new(link)))
+    } else if let Some(address) = dolphin {
+      DolphinLink::connect(&address).map(|link| cpu.set_link_transport(Box::new(link)))
+    } else {
+      Ok(())
+    };
+
+    if let Err(error) = linked {
+      println!("couldn't set up the link cable, playing without it: {error}");
+    }
+  }
+
+  let screens = emulator.consoles().len() as u32;
+
+  // which console the keyboard and controller are playing, picked with the number keys
+  let mut active_console = 0;
+
+  // games missing from the game database have their save type worked out while they run
+  let mut save_type_reported = false;
 
   let sdl_context = sdl2::init().unwrap();
   let video_subsystem = sdl_context.video().unwrap();
@@ -76,7 +253,7 @@ fn main() {
       .num_joysticks()
       .map_err(|e| format!("can't enumerate joysticks: {}", e)).unwrap();
 
-  let _controller = (0..available)
+  let mut controller = (0..available)
     .find_map(|id| {
       match game_controller_subsystem.open(id) {
         Ok(c) => {
@@ -124,8 +301,13 @@ fn main() {
   joypad_map.insert(10, KeyInputRegister::ButtonR);
 
 
+  let title = match emulator.consoles()[0].cartridge.title() {
+    "" => "GBA+".to_string(),
+    game_title => format!("GBA+ - {game_title}")
+  };
+
   let window = video_subsystem
-    .window("GBA+", (SCREEN_WIDTH * 3) as u32, (SCREEN_HEIGHT * 3) as u32)
+    .window(&title, SCREEN_WIDTH as u32 * 3 * screens, (SCREEN_HEIGHT * 3) as u32)
     .position_centered()
     .build()
     .unwrap();
@@ -136,48 +318,130 @@ fn main() {
   let mut event_pump = sdl_context.event_pump().unwrap();
 
   let creator = canvas.texture_creator();
-  let mut texture = creator
-    .create_texture_target(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
-    .unwrap();
+  let mut textures: Vec<_> = (0..screens)
+    .map(|_| creator.create_texture_target(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32).unwrap())
+    .collect();
 
+  let number_keys = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4];
 
   loop {
-    while !cpu.gpu.frame_finished {
-      cpu.step();
-    }
+    emulator.step_frame();
 
-    cpu.gpu.cap_fps();
+    let consoles = emulator.consoles();
+
+    consoles[0].gpu.cap_fps();
 
     // TODO: change this to use opengl.
-    texture.update(None, &cpu.gpu.picture.data, SCREEN_WIDTH as usize * 3).unwrap();
+    for (i, (cpu, texture)) in consoles.iter().zip(&mut textures).enumerate() {
+      texture.update(None, &cpu.gpu.picture.data, SCREEN_WIDTH as usize * 3).unwrap();
+
+      let screen = Rect::new(i as i32 * SCREEN_WIDTH as i32, 0, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
 
-    canvas.copy(&texture, None, None).unwrap();
+      canvas.copy(texture, None, screen).unwrap();
+    }
 
     canvas.present();
 
-    cpu.gpu.frame_finished = false;
+    for cpu in consoles.iter_mut() {
+      if let Some(error) = cpu.cartridge.take_save_error() {
+        println!("save file: {error}");
+      }
+
+      if let Some(error) = cpu.take_link_error() {
+        println!("link cable disconnected: {error}");
+      }
+    }
+
+    if !save_type_reported {
+      if let Some(save_type) = consoles[0].cartridge.detected_save_type() {
+        println!(
+          "detected {} saves, add \"{} {}\" to a --game-db file to skip detection",
+          save_type.name(),
+          consoles[0].cartridge.game_code(),
+          save_type.name()
+        );
+
+        save_type_reported = true;
+      }
+    }
+
+    if rumble.get() {
+      if let Some(controller) = &mut controller {
+        // renewed every frame for as long as the motor is on
+        controller.set_rumble(0xffff, 0xffff, 100).ok();
+      }
+    }
 
     for event in event_pump.poll_iter() {
       match event {
-        Event::Quit { .. } => std::process::exit(0),
+        Event::Quit { .. } => {
+          // saves are written shortly after the game stops writing, so make sure the last one is
+          for cpu in consoles.iter_mut() {
+            if let Err(error) = cpu.cartridge.flush_backup() {
+              println!("couldn't write save file: {error}");
+            }
+          }
+
+          std::process::exit(0)
+        }
+        Event::KeyDown { keycode: Some(keycode), .. } if number_keys[..screens as usize].contains(&keycode) => {
+          active_console = number_keys.iter().position(|key| *key == keycode).unwrap();
+        }
+        // the solar sensor's light level for boktai
+        Event::KeyDown { keycode: Some(keycode @ (Keycode::Minus | Keycode::Equals)), .. } => {
+          let cartridge = &mut consoles[active_console].cartridge;
+
+          if let Some(level) = cartridge.solar_level() {
+            let level = if keycode == Keycode::Minus { level.saturating_sub(1) } else { (level + 1).min(MAX_SOLAR_LEVEL) };
+
+            cartridge.set_solar_level(level);
+
+            println!("solar sensor light level: {level}/{MAX_SOLAR_LEVEL}");
+          }
+        }
+        Event::KeyDown { keycode: Some(keycode @ (Keycode::Q | Keycode::E)), .. } => {
+          let rotation_z = if keycode == Keycode::Q { -KEY_ROTATION_RATE } else { KEY_ROTATION_RATE };
+
+          motion.set(Motion { rotation_z, ..motion.get() });
+        }
+        Event::KeyUp { keycode: Some(Keycode::Q | Keycode::E), .. } => motion.set(Motion { rotation_z: 0.0, ..motion.get() }),
+        Event::ControllerAxisMotion { axis: Axis::RightX, value, .. } => {
+          motion.set(Motion { rotation_z: value as f32 / i16::MAX as f32 * MAX_ROTATION_RATE, ..motion.get() });
+        }
+        // the tilt sensor follows the left stick, or the mouse's distance from the middle of the window
+        Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. } => {
+          motion.set(Motion { tilt_x: value as f32 / i16::MAX as f32, ..motion.get() });
+        }
+        Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. } => {
+          motion.set(Motion { tilt_y: value as f32 / i16::MAX as f32, ..motion.get() });
+        }
+        Event::MouseMotion { x, y, .. } => {
+          let (width, height) = canvas.window().size();
+
+          motion.set(Motion {
+            tilt_x: x as f32 / width as f32 * 2.0 - 1.0,
+            tilt_y: y as f32 / height as f32 * 2.0 - 1.0,
+            ..motion.get()
+          });
+        }
         Event::KeyDown { keycode, .. } => {
           if let Some(button) = key_map.get(&keycode.unwrap_or(Keycode::Return)) {
-            cpu.key_input.set(*button, false);
+            consoles[active_console].key_input.set(*button, false);
           }
         }
         Event::KeyUp { keycode, .. } => {
           if let Some(button) = key_map.get(&keycode.unwrap_or(Keycode::Return)) {
-            cpu.key_input.set(*button, true);
+            consoles[active_console].key_input.set(*button, true);
           }
         }
         Event::JoyButtonDown { button_idx, .. } => {
           if let Some(button) = joypad_map.get(&button_idx){
-            cpu.key_input.set(*button, false);
+            consoles[active_console].key_input.set(*button, false);
           }
         }
         Event::JoyButtonUp { button_idx, .. } => {
           if let Some(button) = joypad_map.get(&button_idx){
-            cpu.key_input.set(*button, true);
+            consoles[active_console].key_input.set(*button, true);
           }
         }
         _ => { /* do nothing */ }
@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{cell::{Cell, RefCell}, rc::Rc, sync::Arc};
 
-use gba_emulator::{apu::NUM_SAMPLES, cartridge::BackupMedia, cpu::{registers::key_input_register::KeyInputRegister, CPU}};
+use gba_emulator::{apu::NUM_SAMPLES, cartridge::{motion_input::Motion, save_format::SaveFormat, save_storage::CallbackStorage}, cpu::{registers::key_input_register::KeyInputRegister, CPU}};
 use ringbuf::{storage::Heap, traits::{Consumer, Split}, wrap::caching::Caching, HeapRb, SharedRb};
 
 extern crate gba_emulator;
@@ -35,17 +35,14 @@ mod ffi {
     #[swift_bridge(swift_name = "loadSave")]
     fn load_save(&mut self, data: &[u8]);
 
-    #[swift_bridge(swift_name = "backupFilePointer")]
-    fn backup_file_pointer(&self) -> *const u8;
+    #[swift_bridge(swift_name = "importSave")]
+    fn import_save(&mut self, data: &[u8], format: &str) -> bool;
 
-    #[swift_bridge(swift_name = "backupFileSize")]
-    fn backup_file_size(&self) -> usize;
+    #[swift_bridge(swift_name = "exportSave")]
+    fn export_save(&self, format: &str) -> Vec<u8>;
 
-    #[swift_bridge(swift_name = "hasSaved")]
-    fn has_saved(&self) -> bool;
-
-    #[swift_bridge(swift_name = "setSaved")]
-    fn set_saved(&mut self, val: bool);
+    #[swift_bridge(swift_name = "takeSave")]
+    fn take_save(&mut self) -> Vec<u8>;
 
     #[swift_bridge(swift_name = "audioBufferPtr")]
     fn audio_buffer_ptr(&mut self) -> *const f32;
@@ -56,7 +53,31 @@ mod ffi {
     fn reload_rom(&mut self, rom: &[u8]);
 
     #[swift_bridge(swift_name = "loadBios")]
-    fn load_bios(&mut self, bios: &[u8]);
+    fn load_bios(&mut self, bios: &[u8]) -> bool;
+
+    #[swift_bridge(swift_name = "gameTitle")]
+    fn game_title(&self) -> String;
+
+    #[swift_bridge(swift_name = "gameCode")]
+    fn game_code(&self) -> String;
+
+    #[swift_bridge(swift_name = "detectedSaveType")]
+    fn detected_save_type(&self) -> String;
+
+    #[swift_bridge(swift_name = "hasSolarSensor")]
+    fn has_solar_sensor(&self) -> bool;
+
+    #[swift_bridge(swift_name = "setSolarLevel")]
+    fn set_solar_level(&mut self, level: u8);
+
+    #[swift_bridge(swift_name = "setRotationRate")]
+    fn set_rotation_rate(&mut self, rotation_z: f32);
+
+    #[swift_bridge(swift_name = "setTilt")]
+    fn set_tilt(&mut self, tilt_x: f32, tilt_y: f32);
+
+    #[swift_bridge(swift_name = "isRumbling")]
+    fn is_rumbling(&self) -> bool;
 
     #[swift_bridge(swift_name = "updateInput")]
     fn update_input(&mut self, index: usize, is_pressed: bool);
@@ -85,7 +106,12 @@ pub struct GBAEmulator {
   cpu: CPU,
   compressed_len: usize,
   consumer: Caching<Arc<SharedRb<Heap<f32>>>, false, true>,
-  audio_buffer: Vec<f32>
+  audio_buffer: Vec<f32>,
+  // the device's gyroscope is fed in through this, and the rumble motor read back out for haptics
+  motion: Rc<Cell<Motion>>,
+  rumble: Rc<Cell<bool>>,
+  // the save as it was last stored, until the app picks it up
+  stored_save: Rc<RefCell<Option<Vec<u8>>>>
 }
 
 impl GBAEmulator {
@@ -95,11 +121,24 @@ impl GBAEmulator {
     let (producer, consumer) = ringbuffer.split();
 
 
+    let mut cpu = CPU::new(producer);
+
+    cpu.set_block_cache_enabled(true);
+
+    let motion = Rc::new(Cell::new(Motion::default()));
+    let rumble = Rc::new(Cell::new(false));
+
+    cpu.cartridge.set_motion_input(Box::new(motion.clone()));
+    cpu.cartridge.set_rumble_output(Box::new(rumble.clone()));
+
     GBAEmulator {
-      cpu: CPU::new(producer),
+      cpu,
       compressed_len: 0,
       consumer,
-      audio_buffer: Vec::new()
+      audio_buffer: Vec::new(),
+      motion,
+      rumble,
+      stored_save: Rc::new(RefCell::new(None))
     }
   }
 
@@ -107,79 +146,43 @@ impl GBAEmulator {
     self.cpu.apu.audio_paused = value;
   }
 
+  /// the save the next game starts with, set before loading it. an empty one starts a blank save
   pub fn load_save(&mut self, data: &[u8]) {
-    match &mut self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.buffer = data.to_vec();
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.buffer = data.to_vec();
-      }
-      BackupMedia::Sram(file) => {
-        file.buffer = data.to_vec();
-      }
-      _ => ()
-    }
-  }
+    let stored_save = self.stored_save.clone();
 
-  pub fn backup_file_pointer(&self) -> *const u8 {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.buffer.as_ptr()
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.buffer.as_ptr()
-      }
-      BackupMedia::Sram(file) => {
-        file.buffer.as_ptr()
-      }
-      _ => [].as_ptr()
-    }
+    let storage = CallbackStorage::new((!data.is_empty()).then(|| data.to_vec()), Box::new(move |data, _| {
+      *stored_save.borrow_mut() = Some(data.to_vec());
+
+      Ok(())
+    }));
+
+    self.cpu.cartridge.set_save_storage(Box::new(storage));
   }
 
-  pub fn backup_file_size(&self) -> usize {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.size
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.size
-      }
-      BackupMedia::Sram(file) => {
-        file.size
+  /// replaces the save with one from another emulator or backup device, in the format with the
+  /// given name or extension, or a guessed one if it's empty. returns false if it couldn't be read
+  pub fn import_save(&mut self, data: &[u8], format: &str) -> bool {
+    let save_format = match format {
+      "" => None,
+      format => match SaveFormat::from_name(format) {
+        Some(save_format) => Some(save_format),
+        None => return false
       }
-      _ => 0
-    }
+    };
+
+    self.cpu.cartridge.import_save(data, save_format).is_ok()
   }
 
-  pub fn has_saved(&self) -> bool {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.has_saved
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.has_saved
-      }
-      BackupMedia::Sram(file) => {
-        file.has_saved
-      }
-      _ => false
-    }
+  /// the save converted to the format with the given name or extension, or nothing if it can't be
+  pub fn export_save(&self, format: &str) -> Vec<u8> {
+    SaveFormat::from_name(format)
+      .and_then(|save_format| self.cpu.cartridge.export_save(save_format).ok())
+      .unwrap_or_default()
   }
 
-  pub fn set_saved(&mut self, val: bool) {
-    match &mut self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.has_saved = val;
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.has_saved = val;
-      }
-      BackupMedia::Sram(file) => {
-        file.has_saved = val;
-      }
-      _ => ()
-    }
+  /// the whole save if the game has written to it since the last call, otherwise empty
+  pub fn take_save(&mut self) -> Vec<u8> {
+    self.stored_save.borrow_mut().take().unwrap_or_default()
   }
 
   pub fn create_save_state(&mut self) -> *const u8 {
@@ -254,8 +257,45 @@ impl GBAEmulator {
     self.cpu.reload_game(rom.to_vec());
   }
 
-  pub fn load_bios(&mut self, bios: &[u8]) {
-    self.cpu.load_bios(bios.to_vec());
+  pub fn game_title(&self) -> String {
+    self.cpu.cartridge.title().to_string()
+  }
+
+  pub fn game_code(&self) -> String {
+    self.cpu.cartridge.game_code().to_string()
+  }
+
+  /// the save type found by watching the game when it isn't in the game database, as named in
+  /// game database files, or empty while it's still unknown
+  pub fn detected_save_type(&self) -> String {
+    self.cpu.cartridge.detected_save_type().map_or(String::new(), |save_type| save_type.name().to_string())
+  }
+
+  pub fn has_solar_sensor(&self) -> bool {
+    self.cpu.cartridge.solar_level().is_some()
+  }
+
+  pub fn set_solar_level(&mut self, level: u8) {
+    self.cpu.cartridge.set_solar_level(level);
+  }
+
+  /// the device's rotation around the axis going through the screen, in radians per second
+  pub fn set_rotation_rate(&mut self, rotation_z: f32) {
+    self.motion.set(Motion { rotation_z, ..self.motion.get() });
+  }
+
+  /// the device's tilt from its accelerometer, from -1.0 to 1.0 on each axis where 1.0 is 90 degrees
+  pub fn set_tilt(&mut self, tilt_x: f32, tilt_y: f32) {
+    self.motion.set(Motion { tilt_x, tilt_y, ..self.motion.get() });
+  }
+
+  pub fn is_rumbling(&self) -> bool {
+    self.rumble.get()
+  }
+
+  /// returns false if the image isn't a bios, in which case the current one is kept
+  pub fn load_bios(&mut self, bios: &[u8]) -> bool {
+    self.cpu.load_bios(bios.to_vec()).is_ok()
   }
 
   pub fn set_paused(&mut self, paused: bool) {
@@ -1,22 +1,89 @@
-use std::path::Path;
+use std::{collections::HashMap, io, path::Path};
 
 use serde::{Deserialize, Serialize};
 
-use self::{eeprom_controller::EepromController, flash::{Flash, FlashSize}, backup_file::BackupFile};
+use crate::number::Number;
+
+use self::{
+  eeprom_controller::{EepromController, EepromType},
+  flash::{Flash, FlashChip, FlashSize},
+  backup_file::BackupFile,
+  game_db::{CartridgeHardware, GameDb, GameOverrides, SaveType},
+  gpio::Gpio,
+  gyro_sensor::GyroSensor,
+  motion_input::MotionInput,
+  rom_header::RomHeader,
+  rtc::{Rtc, TRAILER_SIZE},
+  rumble::{Rumble, RumbleOutput},
+  save_format::SaveFormat,
+  save_storage::{FileStorage, SaveStorage},
+  solar_sensor::SolarSensor,
+  tilt_sensor::TiltSensor
+};
 
 pub mod eeprom_controller;
 pub mod flash;
 pub mod backup_file;
+pub mod game_db;
+pub mod gpio;
+pub mod gyro_sensor;
+pub mod motion_input;
+pub mod rom_header;
+pub mod rtc;
+pub mod rumble;
+pub mod save_format;
+pub mod save_storage;
+pub mod solar_sensor;
+pub mod tilt_sensor;
+pub mod time_source;
 
 #[derive(Serialize, Deserialize)]
 pub struct Cartridge {
   #[serde(skip_deserializing)]
   #[serde(skip_serializing)]
   pub rom: Vec<u8>,
+  // parsed from the rom, so like the rom it isn't part of save states
+  #[serde(skip_deserializing)]
+  #[serde(skip_serializing)]
+  pub header: Option<RomHeader>,
+  #[serde(skip_deserializing)]
+  #[serde(skip_serializing)]
+  pub game_db: GameDb,
+  pub overrides: GameOverrides,
   pub backup: BackupMedia,
+  pub gpio: Gpio,
+  pub tilt_sensor: Option<TiltSensor>,
+  #[serde(skip_deserializing)]
+  #[serde(skip_serializing)]
+  pub save_storage: Option<Box<dyn SaveStorage>>,
+  pub save_type_detection: SaveTypeDetection,
+  // how many times the game has accessed its save in a way that points to each save type, while
+  // the save type is still being watched for
+  pub backup_accesses: HashMap<SaveType, u8>,
+  // how much of a flash unlock sequence the game's last writes were, while the save type is still
+  // being watched for
+  pub flash_unlock: u8,
   pub file_path: Option<String>
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SaveTypeDetection {
+  /// the save type came from the game database
+  Known,
+  /// the save type is a guess from the rom, or unknown, until the game's accesses to its save
+  /// confirm it. nothing is written back to storage in the meantime, short of exiting
+  Watching,
+  /// the game's accesses to its save settled the save type
+  Detected
+}
+
+/// the ways a game can touch its save, which give away what kind it is
+pub enum BackupAccess {
+  Read,
+  Write(u32, u8),
+  EepromDma
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum BackupMedia {
   Eeprom(EepromController),
@@ -25,34 +92,601 @@ pub enum BackupMedia {
   Undetected
 }
 
-const BACKUP_MEDIA: &[&str] = &["EEPROM", "SRAM", "FLASH_", "FLASH512_", "FLASH1M_"];
+impl BackupMedia {
+  pub fn backup_file(&self) -> Option<&BackupFile> {
+    match self {
+      BackupMedia::Eeprom(eeprom_controller) => Some(&eeprom_controller.chip.memory),
+      BackupMedia::Flash(flash) => Some(&flash.memory),
+      BackupMedia::Sram(sram) => Some(sram),
+      BackupMedia::Undetected => None
+    }
+  }
+
+  pub fn backup_file_mut(&mut self) -> Option<&mut BackupFile> {
+    match self {
+      BackupMedia::Eeprom(eeprom_controller) => Some(&mut eeprom_controller.chip.memory),
+      BackupMedia::Flash(flash) => Some(&mut flash.memory),
+      BackupMedia::Sram(sram) => Some(sram),
+      BackupMedia::Undetected => None
+    }
+  }
+}
+
+// a fallback for games missing from the game database, since most games are built with
+// nintendo's save library which leaves its name in the rom
+const BACKUP_MEDIA: &[(&str, SaveType)] = &[
+  ("EEPROM", SaveType::Eeprom),
+  ("SRAM", SaveType::Sram),
+  ("FLASH_", SaveType::Flash64k),
+  ("FLASH512_", SaveType::Flash64k),
+  ("FLASH1M_", SaveType::Flash128k)
+];
+
+// how many accesses pointing to a save type it takes to settle on it. a single access can be
+// misleading, like a stray write from a game that doesn't mean to touch its save
+const CONFIRMING_ACCESSES: u8 = 3;
 
 impl Cartridge {
-  pub fn detect_backup_media(&mut self) {
-    for i in 0..5 {
-      let needle = BACKUP_MEDIA[i].as_bytes();
+  pub fn set_rom(&mut self, rom: Vec<u8>) {
+    self.header = RomHeader::parse(&rom);
+    self.rom = rom;
+  }
+
+  pub fn title(&self) -> &str {
+    self.header.as_ref().map_or("", |header| &header.title)
+  }
+
+  pub fn game_code(&self) -> &str {
+    self.header.as_ref().map_or("", |header| &header.game_code)
+  }
+
+  /// looks the game up in the game database, then sets up its backup media from there or by
+  /// searching the rom when it isn't in it
+  pub fn detect_hardware(&mut self) {
+    self.overrides = self.game_db.get(self.game_code()).cloned().unwrap_or_default();
+
+    let save_type = self.overrides.save_type.or_else(|| {
+      BACKUP_MEDIA
+        .iter()
+        .find(|(needle, _)| self.rom_contains(needle))
+        .map(|(_, save_type)| *save_type)
+    });
+
+    self.backup = match save_type {
+      Some(save_type) => self.create_backup(save_type),
+      None => BackupMedia::Undetected
+    };
+
+    // the rom search can be fooled, so anything not in the game database gets confirmed by
+    // watching what the game does with its save
+    self.save_type_detection = if self.overrides.save_type.is_some() {
+      SaveTypeDetection::Known
+    } else {
+      SaveTypeDetection::Watching
+    };
+
+    self.backup_accesses.clear();
+    self.flash_unlock = 0;
+
+    // whatever the frontend hooked up to the last game's hardware carries over to this one
+    let previous_gpio = std::mem::take(&mut self.gpio);
+    let hardware = self.overrides.hardware;
+
+    if hardware.contains(CartridgeHardware::RTC) {
+      let mut rtc = Rtc::new();
+
+      if let Some(backup_file) = self.backup.backup_file() {
+        rtc.load_trailer(backup_file.trailer());
+      }
+
+      self.gpio.rtc = Some(rtc);
+    }
+
+    if hardware.contains(CartridgeHardware::SOLAR_SENSOR) {
+      self.gpio.solar_sensor = Some(SolarSensor::new());
+    }
+
+    if hardware.contains(CartridgeHardware::GYRO) {
+      self.gpio.gyro_sensor = Some(GyroSensor::new());
+    }
+
+    if hardware.contains(CartridgeHardware::RUMBLE) {
+      self.gpio.rumble = Some(Rumble::new());
+    }
+
+    self.tilt_sensor = hardware.contains(CartridgeHardware::TILT).then(TiltSensor::new);
+
+    self.gpio.carry_over(previous_gpio);
+  }
+
+  pub fn read_tilt<T: Number>(&self, address: u32) -> T {
+    let value = self.tilt_sensor.as_ref().map_or(0, |tilt_sensor| tilt_sensor.read(address));
+
+    num::cast::<u8, T>(value).unwrap()
+  }
+
+  pub fn write_tilt(&mut self, address: u32, value: u8) {
+    if let Some(tilt_sensor) = &mut self.tilt_sensor {
+      // the tilt sensor shares the frontend's motion input with the gpio sensors
+      tilt_sensor.write(address, value, &*self.gpio.motion_input);
+    }
+  }
+
+  /// motion for the gyro and tilt sensors
+  pub fn set_motion_input(&mut self, motion_input: Box<dyn MotionInput>) {
+    self.gpio.motion_input = motion_input;
+  }
+
+  pub fn set_rumble_output(&mut self, rumble_output: Box<dyn RumbleOutput>) {
+    self.gpio.rumble_output = rumble_output;
+  }
+
+  /// how much light the solar sensor sees, from 0 up to MAX_SOLAR_LEVEL
+  pub fn set_solar_level(&mut self, level: u8) {
+    if let Some(solar_sensor) = &mut self.gpio.solar_sensor {
+      solar_sensor.set_level(level);
+    }
+  }
+
+  pub fn solar_level(&self) -> Option<u8> {
+    self.gpio.solar_sensor.as_ref().map(|solar_sensor| solar_sensor.level)
+  }
+
+  pub fn read_gpio<T: Number>(&self, address: u32) -> T {
+    let offset = address & 0x01ff_fffe;
+
+    let value = match std::mem::size_of::<T>() {
+      4 => self.gpio.read(offset) as u32 | (self.gpio.read(offset + 2) as u32) << 16,
+      2 => self.gpio.read(offset) as u32,
+      _ => (self.gpio.read(offset) >> ((address & 0b1) * 8)) as u32 & 0xff
+    };
+
+    num::cast::<u32, T>(value).unwrap()
+  }
+
+  /// returns true when the cartridge raises an interrupt
+  pub fn write_gpio<T: Number>(&mut self, address: u32, value: T) -> bool {
+    let offset = address & 0x01ff_fffe;
+    let value = num::cast::<T, u32>(value).unwrap();
+
+    let mut irq = self.gpio.write(offset, value as u16);
+
+    if std::mem::size_of::<T>() == 4 {
+      irq |= self.gpio.write(offset + 2, (value >> 16) as u16);
+    }
+
+    self.save_rtc();
+
+    irq
+  }
+
+  /// checks the rtc for interrupts, returns true if it raised one
+  pub fn tick_rtc(&mut self) -> bool {
+    self.gpio.rtc.as_mut().is_some_and(|rtc| rtc.tick())
+  }
+
+  /// converts a save from another emulator or backup device and replaces the current one with it,
+  /// guessing the format when it isn't given. the imported save is written back right away.
+  /// returns the format it was read as
+  pub fn import_save(&mut self, contents: &[u8], format: Option<SaveFormat>) -> Result<SaveFormat, String> {
+    let format = format.unwrap_or_else(|| SaveFormat::detect(contents));
+
+    let (mut save, mut trailer) = self.decode_save(contents, format)?;
+
+    // while the save type is still a guess, the imported save's size makes a better one. it stays
+    // a guess until the game's own accesses confirm it
+    let size_differs = self.backup.backup_file().is_none_or(|backup_file| backup_file.size != save.len());
+    let mut replaced = false;
+
+    if self.save_type_detection == SaveTypeDetection::Watching && size_differs {
+      let save_type = match save.len() {
+        512 => Some(SaveType::Eeprom512),
+        0x2000 => Some(SaveType::Eeprom8k),
+        0x8000 => Some(SaveType::Sram),
+        0x10000 => Some(SaveType::Flash64k),
+        0x20000 => Some(SaveType::Flash128k),
+        _ => None
+      };
+
+      match save_type {
+        Some(save_type) => {
+          self.release_save_storage();
+          self.backup = self.create_backup(save_type);
+
+          replaced = true;
+
+          // eeprom words are stored in a different order by some formats
+          (save, trailer) = self.decode_save(contents, format)?;
+        }
+        None if matches!(self.backup, BackupMedia::Undetected) => {
+          return Err(format!("a {} byte save doesn't fit any backup media", save.len()));
+        }
+        None => ()
+      }
+    }
+
+    if let BackupMedia::Eeprom(eeprom_controller) = &mut self.backup {
+      eeprom_controller.detect_from_save(save.len());
+    }
+
+    let backup_file = self.backup.backup_file_mut().ok_or("the game has no backup media to import a save to")?;
+
+    backup_file.import(&save, trailer.as_deref());
+    backup_file.flush().map_err(|error| format!("couldn't store the imported save: {error}"))?;
+
+    // new backup media brings along the rtc block stored with the old save, unless one was imported
+    if let Some(rtc) = &mut self.gpio.rtc {
+      if trailer.is_some() || replaced {
+        rtc.load_trailer(backup_file.trailer());
+      }
+    }
+
+    Ok(format)
+  }
+
+  // the save and the rtc block after it, if there is one
+  fn decode_save(&self, contents: &[u8], format: SaveFormat) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+    let eeprom = matches!(self.backup, BackupMedia::Eeprom(_));
+
+    let mut save = save_format::decode(contents, format, &self.rom, eeprom)?;
+
+    // saves are a multiple of 512 bytes, so anything left over is an rtc block
+    let trailer = (save.len() % 512 == TRAILER_SIZE).then(|| save.split_off(save.len() - TRAILER_SIZE));
+
+    Ok((save, trailer))
+  }
+
+  /// the current save converted for another emulator or backup device
+  pub fn export_save(&self, format: SaveFormat) -> Result<Vec<u8>, String> {
+    let eeprom = matches!(self.backup, BackupMedia::Eeprom(_));
+
+    let backup_file = self.backup.backup_file().ok_or("the game has no backup media to export a save from")?;
+
+    save_format::encode(backup_file.data(), backup_file.trailer(), format, &self.rom, eeprom)
+  }
+
+  /// where the next game's save goes instead of a file next to the rom, set before loading it
+  pub fn set_save_storage(&mut self, storage: Box<dyn SaveStorage>) {
+    self.save_storage = Some(storage);
+  }
+
+  /// writes the save out right away instead of waiting for the game to stop writing to it, like
+  /// before exiting
+  pub fn flush_backup(&mut self) -> io::Result<()> {
+    // a save type that hasn't settled yet is still the best guess there is, and anything the game
+    // saved would be lost otherwise
+    self.backup.backup_file_mut().map_or(Ok(()), |backup_file| backup_file.flush())
+  }
+
+  pub fn flush_backup_if_idle(&mut self) {
+    // a wrong guess at the save type would write back a save of the wrong size
+    if self.save_type_detection == SaveTypeDetection::Watching {
+      return;
+    }
+
+    if let Some(backup_file) = self.backup.backup_file_mut() {
+      backup_file.flush_if_idle();
+    }
+  }
+
+  /// the last error loading or storing the save, if there's been one since the last call
+  pub fn take_save_error(&mut self) -> Option<io::Error> {
+    self.backup.backup_file_mut().and_then(|backup_file| backup_file.error.take())
+  }
+
+  /// settles the save type from how the game accesses its save. an access that doesn't fit the
+  /// guess switches the backup media over right away, so the access lands where it should
+  pub fn watch_backup_access(&mut self, access: BackupAccess) {
+    if self.save_type_detection != SaveTypeDetection::Watching {
+      return;
+    }
+
+    let save_type = match access {
+      // games read their flash, but an eeprom game never reads here
+      BackupAccess::Read if matches!(self.backup, BackupMedia::Undetected | BackupMedia::Eeprom(_)) => SaveType::Sram,
+      BackupAccess::Read => return,
+      BackupAccess::Write(address, val) => {
+        let offset = address & 0xffff;
+        let unlocked = self.flash_unlock == 2;
+
+        self.flash_unlock = match (self.flash_unlock, offset, val) {
+          (_, 0x5555, 0xaa) => 1,
+          (1, 0x2aaa, 0x55) => 2,
+          _ => 0
+        };
 
-      if let Some(_) = self.rom.windows(needle.len()).position(|window| window == needle) {
-        self.backup = self.create_backup(i);
-        break;
+        // only a flash game sends a whole command sequence, and it expects the chip to answer
+        // straight away, like with its id
+        if unlocked && offset == 0x5555 {
+          self.settle_on_flash();
+
+          return;
+        }
+
+        // the start of a command sequence could just as well be sram
+        if matches!(offset, 0x5555 | 0x2aaa) {
+          return;
+        }
+
+        // flash games program their data with plain writes too
+        if let BackupMedia::Flash(flash) = &self.backup {
+          if flash.expects_write(address, val) {
+            return;
+          }
+        }
+
+        SaveType::Sram
       }
+      BackupAccess::EepromDma => SaveType::Eeprom
+    };
+
+    let guessed_right = matches!(
+      (&self.backup, save_type),
+      (BackupMedia::Sram(_), SaveType::Sram) | (BackupMedia::Eeprom(_), SaveType::Eeprom)
+    );
+
+    if !guessed_right {
+      self.switch_backup(save_type);
+    }
+
+    let accesses = self.backup_accesses.entry(save_type).or_default();
+
+    *accesses += 1;
+
+    if *accesses >= CONFIRMING_ACCESSES {
+      self.save_type_detection = SaveTypeDetection::Detected;
+    }
+  }
+
+  // called with the command of a flash command sequence about to be written. a new flash is sent
+  // the unlock sequence the old media took, so the command reaches it like it would the real chip
+  fn settle_on_flash(&mut self) {
+    self.save_type_detection = SaveTypeDetection::Detected;
+
+    if matches!(self.backup, BackupMedia::Flash(_)) {
+      return;
+    }
+
+    self.switch_backup(SaveType::Flash64k);
+
+    if let BackupMedia::Flash(flash) = &mut self.backup {
+      flash.write(0x5555, 0xaa, 0);
+      flash.write(0x2aaa, 0x55, 0);
+    }
+  }
+
+  // replaces the backup media with another kind, loading the save again for it. nothing's been
+  // written back while the save type was being watched, so it's still as it was
+  fn switch_backup(&mut self, save_type: SaveType) {
+    self.release_save_storage();
+
+    self.backup = match (save_type, self.overrides.flash_chip) {
+      (SaveType::Flash64k, None) => BackupMedia::Flash(Flash::detect(self.take_save_storage(), self.flash_size_from_rom())),
+      _ => self.create_backup(save_type)
+    };
+
+    if let (Some(rtc), Some(backup_file)) = (&mut self.gpio.rtc, self.backup.backup_file()) {
+      rtc.load_trailer(backup_file.trailer());
     }
   }
 
-  fn create_backup(&self, index: usize) -> BackupMedia {
-    let backup_path = if let Some(file_path) = &self.file_path {
-      Some(Path::new(file_path).with_extension("sav"))
+  // the new media saves to wherever the old one did
+  fn release_save_storage(&mut self) {
+    if let Some(storage) = self.backup.backup_file_mut().and_then(|backup_file| backup_file.storage.take()) {
+      self.save_storage = Some(storage);
+    }
+  }
+
+  // the flash size from the name of nintendo's flash library in the rom, which is what the game
+  // expects the chip id to match
+  fn flash_size_from_rom(&self) -> Option<FlashSize> {
+    if self.rom_contains("FLASH1M_") {
+      Some(FlashSize::Flash128k)
+    } else if self.rom_contains("FLASH512_") || self.rom_contains("FLASH_") {
+      Some(FlashSize::Flash64k)
     } else {
       None
+    }
+  }
+
+  fn rom_contains(&self, needle: &str) -> bool {
+    self.rom.windows(needle.len()).any(|window| window == needle.as_bytes())
+  }
+
+  /// the save type found by watching the game, for pinning in the game database so it doesn't
+  /// have to be detected again
+  pub fn detected_save_type(&self) -> Option<SaveType> {
+    if self.save_type_detection != SaveTypeDetection::Detected {
+      return None;
+    }
+
+    match &self.backup {
+      BackupMedia::Eeprom(eeprom_controller) => Some(match eeprom_controller.detected_type() {
+        Some(EepromType::Eeprom512) => SaveType::Eeprom512,
+        Some(EepromType::Eeprom8k) => SaveType::Eeprom8k,
+        None => SaveType::Eeprom
+      }),
+      // a flash is only reported once its size is known too
+      BackupMedia::Flash(flash) if !flash.size_known() => None,
+      BackupMedia::Flash(flash) if flash.chip().size() > FlashSize::Flash64k as usize => Some(SaveType::Flash128k),
+      BackupMedia::Flash(_) => Some(SaveType::Flash64k),
+      BackupMedia::Sram(_) => Some(SaveType::Sram),
+      BackupMedia::Undetected => None
+    }
+  }
+
+  fn save_rtc(&mut self) {
+    let Some(rtc) = &mut self.gpio.rtc else {
+      return;
+    };
+
+    if rtc.changed {
+      rtc.changed = false;
+
+      if let Some(backup_file) = self.backup.backup_file_mut() {
+        backup_file.write_trailer(&rtc.to_trailer());
+      }
+    }
+  }
+
+  fn take_save_storage(&mut self) -> Option<Box<dyn SaveStorage>> {
+    // a storage set by the frontend takes the place of the save file next to the rom
+    self.save_storage.take().or_else(|| {
+      self.file_path.as_ref().map(|file_path| {
+        Box::new(FileStorage::new(Path::new(file_path).with_extension("sav"))) as Box<dyn SaveStorage>
+      })
+    })
+  }
+
+  fn create_backup(&mut self, save_type: SaveType) -> BackupMedia {
+    let storage = self.take_save_storage();
+
+    // the game database can pick the chip for games that only work with some of them
+    let flash = |flash_size: FlashSize, storage| {
+      BackupMedia::Flash(Flash::new(storage, self.overrides.flash_chip.unwrap_or(flash_size.default_chip())))
     };
 
-    match index {
-      0 => BackupMedia::Eeprom(EepromController::new(backup_path)),
-      1 => BackupMedia::Sram(BackupFile::new(32 * 1024, backup_path)),
-      2 => BackupMedia::Flash(Flash::new(backup_path, FlashSize::Flash64k)), // regular flash
-      3 => BackupMedia::Flash(Flash::new(backup_path, FlashSize::Flash64k)), // flash 512
-      4 => BackupMedia::Flash(Flash::new(backup_path, FlashSize::Flash128k)), // flash 1024
-      _ => BackupMedia::Undetected
+    match save_type {
+      SaveType::Eeprom => BackupMedia::Eeprom(EepromController::new(storage, None)),
+      SaveType::Eeprom512 => BackupMedia::Eeprom(EepromController::new(storage, Some(EepromType::Eeprom512))),
+      SaveType::Eeprom8k => BackupMedia::Eeprom(EepromController::new(storage, Some(EepromType::Eeprom8k))),
+      SaveType::Sram => BackupMedia::Sram(BackupFile::new(32 * 1024, storage)),
+      SaveType::Flash64k => flash(FlashSize::Flash64k, storage),
+      SaveType::Flash128k => flash(FlashSize::Flash128k, storage),
+      SaveType::None => BackupMedia::Undetected
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use crate::cpu::CPU;
+
+  use super::{save_storage::MemoryStorage, *};
+
+  // a game with the name of a save library in it, and its save kept in memory
+  fn load_game(library: &str) -> (CPU, MemoryStorage) {
+    let mut cpu = CPU::new(HeapRb::<f32>::new(1024).split().0);
+    let storage = MemoryStorage::new(None);
+
+    let mut rom = vec![0; 0x1000];
+
+    rom[0x800..0x800 + library.len()].copy_from_slice(library.as_bytes());
+
+    cpu.cartridge.set_save_storage(Box::new(storage.clone()));
+    cpu.load_game(rom, None);
+
+    (cpu, storage)
+  }
+
+  fn send_flash_command(cpu: &mut CPU, command: u8) {
+    cpu.mem_write_8(0xe00_5555, 0xaa);
+    cpu.mem_write_8(0xe00_2aaa, 0x55);
+    cpu.mem_write_8(0xe00_5555, command);
+  }
+
+  #[test]
+  fn sram_game_guessed_flash_settles_on_sram() {
+    let (mut cpu, storage) = load_game("FLASH_V126");
+
+    assert!(matches!(cpu.cartridge.backup, BackupMedia::Flash(_)));
+
+    for (i, value) in [0x12, 0x34, 0x56].into_iter().enumerate() {
+      cpu.mem_write_8(0xe00_0100 + i as u32, value);
+    }
+
+    assert_eq!(cpu.cartridge.detected_save_type(), Some(SaveType::Sram));
+
+    // none of the writes went missing on the way
+    assert_eq!([0, 1, 2].map(|i| cpu.mem_read_8(0xe00_0100 + i)), [0x12, 0x34, 0x56]);
+
+    cpu.cartridge.flush_backup().unwrap();
+
+    let saved = storage.data.borrow().clone().unwrap();
+
+    assert_eq!(saved.len(), 0x8000);
+    assert_eq!(saved[0x100..0x103], [0x12, 0x34, 0x56]);
+  }
+
+  #[test]
+  fn flash_game_guessed_sram_reads_its_chip_id_right_away() {
+    let (mut cpu, _) = load_game("SRAM_V113");
+
+    assert!(matches!(cpu.cartridge.backup, BackupMedia::Sram(_)));
+
+    send_flash_command(&mut cpu, 0x90);
+
+    assert!(cpu.cartridge.save_type_detection == SaveTypeDetection::Detected);
+
+    let id = FlashChip::Macronix64k.id();
+
+    assert_eq!(cpu.mem_read_8(0xe00_0000), id as u8);
+    assert_eq!(cpu.mem_read_8(0xe00_0001), (id >> 8) as u8);
+  }
+
+  #[test]
+  fn flash_game_guessed_flash_settles_on_its_first_command() {
+    let (mut cpu, _) = load_game("FLASH512_V131");
+
+    send_flash_command(&mut cpu, 0xa0);
+    cpu.mem_write_8(0xe00_0010, 0x42);
+
+    assert_eq!(cpu.cartridge.detected_save_type(), Some(SaveType::Flash64k));
+
+    // the flash is busy programming for a while
+    cpu.cycles += 1000;
+
+    assert_eq!(cpu.mem_read_8(0xe00_0010), 0x42);
+  }
+
+  #[test]
+  fn eeprom_guess_switches_to_sram_on_the_first_access() {
+    let (mut cpu, _) = load_game("EEPROM_V124");
+
+    assert!(matches!(cpu.cartridge.backup, BackupMedia::Eeprom(_)));
+
+    cpu.mem_read_8(0xe00_0000);
+
+    assert!(matches!(cpu.cartridge.backup, BackupMedia::Sram(_)));
+
+    cpu.mem_write_8(0xe00_0000, 0x99);
+    cpu.mem_write_8(0xe00_0001, 0x98);
+
+    assert_eq!(cpu.mem_read_8(0xe00_0000), 0x99);
+    assert_eq!(cpu.cartridge.detected_save_type(), Some(SaveType::Sram));
+  }
+
+  #[test]
+  fn a_stray_unlock_in_an_sram_game_still_settles_on_sram() {
+    let (mut cpu, _) = load_game("SRAM_V113");
+
+    cpu.mem_write_8(0xe00_5555, 0xaa);
+
+    for i in 0..3 {
+      cpu.mem_write_8(0xe00_0000 + i, 1);
+    }
+
+    assert_eq!(cpu.cartridge.detected_save_type(), Some(SaveType::Sram));
+  }
+
+  #[test]
+  fn unsettled_saves_are_still_stored_on_exit() {
+    let (mut cpu, storage) = load_game("SRAM_V113");
+
+    cpu.mem_write_8(0xe00_0020, 0x77);
+
+    assert!(cpu.cartridge.save_type_detection == SaveTypeDetection::Watching);
+
+    // but not while playing, in case the guess is wrong
+    cpu.cartridge.flush_backup_if_idle();
+    cpu.cartridge.flush_backup_if_idle();
+
+    assert!(storage.data.borrow().is_none());
+
+    cpu.cartridge.flush_backup().unwrap();
+
+    assert_eq!(storage.data.borrow().as_ref().unwrap()[0x20], 0x77);
+  }
+}
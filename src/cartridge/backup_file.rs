@@ -1,75 +1,297 @@
-use std::{fs::{File, self}, io::{Read, SeekFrom, Seek, Write}, path::PathBuf};
+use std::{io, ops::Range};
 
 use serde::{Deserialize, Serialize};
 
 use crate::number::Number;
 
+use super::{rtc::TRAILER_SIZE, save_storage::SaveStorage};
+
 #[derive(Serialize, Deserialize)]
 pub struct BackupFile {
   pub size: usize,
   #[serde(skip_deserializing)]
   #[serde(skip_serializing)]
-  file: Option<File>,
-  pub buffer: Vec<u8>,
-  pub has_saved: bool
+  pub storage: Option<Box<dyn SaveStorage>>,
+  // the save followed by any extra state kept after it, like the rtc's
+  data: Vec<u8>,
+  // how much was in storage when it was loaded, if anything
+  #[serde(skip_deserializing)]
+  #[serde(skip_serializing)]
+  loaded_len: Option<usize>,
+  dirty: Option<Range<usize>>,
+  // whether anything was written since the last time the storage was checked
+  written: bool,
+  // the last error from storage, kept for the frontend to pick up
+  #[serde(skip_deserializing)]
+  #[serde(skip_serializing)]
+  pub error: Option<io::Error>
 }
 
 impl BackupFile {
-  pub fn new(size: usize, file_path: Option<PathBuf>) -> Self {
-    let mut buffer = Vec::new();
-    let file = if let Some(file_path) = file_path {
-      if !file_path.is_file() {
-        let mut file = File::create(&file_path).unwrap();
-        file.write_all(&vec![0xff; size]).unwrap();
-      }
+  pub fn new(size: usize, storage: Option<Box<dyn SaveStorage>>) -> Self {
+    Self::sized_from_save(storage, |_| size)
+  }
 
-      let mut file = fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(file_path)
-        .unwrap();
+  /// like new, with the size picked from the length of what's in storage, if anything
+  pub fn sized_from_save(mut storage: Option<Box<dyn SaveStorage>>, size: impl FnOnce(Option<usize>) -> usize) -> Self {
+    let mut error = None;
 
-      file.read_to_end(&mut buffer).unwrap();
+    let saved = match storage.as_mut().map(|storage| storage.load()) {
+      Some(Ok(saved)) => saved,
+      Some(Err(load_error)) => {
+        error = Some(load_error);
 
-      Some(file)
-    } else {
-      None
+        None
+      }
+      None => None
     };
 
-    buffer.resize(size, 0xff);
+    let loaded_len = saved.as_ref().map(|saved| saved.len());
+    let size = size(loaded_len);
+    let mut data = saved.unwrap_or_default();
+
+    // the only thing that can follow the save is the rtc's block, anything else is dropped
+    if data.len() > size && data.len() - size != TRAILER_SIZE {
+      error = Some(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("the save is {} bytes, more than the {size} the game uses, so the rest was dropped", data.len())
+      ));
+
+      data.truncate(size);
+    }
+
+    if data.len() < size {
+      data.resize(size, 0xff);
+    }
 
     Self {
-      file,
-      buffer,
       size,
-      has_saved: false
+      storage,
+      data,
+      loaded_len,
+      dirty: None,
+      written: false,
+      error
     }
   }
 
   pub fn read<T: Number>(&self, offset: usize) -> T {
-    unsafe { *(&self.buffer[offset] as *const u8 as *const T) }
+    unsafe { *(&self.data[offset] as *const u8 as *const T) }
   }
 
   pub fn write(&mut self, offset: usize, value: u8) {
-    self.has_saved = true;
-    self.buffer[offset] = value;
+    self.data[offset] = value;
 
-    if let Some(file) = &mut self.file {
-      file.seek(SeekFrom::Start(offset as u64)).unwrap();
-      file.write_all(&[value]).unwrap();
-    }
+    self.mark_dirty(offset..offset + 1);
+  }
+
+  /// the save itself, without the trailer
+  pub fn data(&self) -> &[u8] {
+    &self.data[..self.size]
+  }
+
+  pub fn trailer(&self) -> &[u8] {
+    &self.data[self.size..]
+  }
+
+  /// everything that goes to storage, the save followed by the trailer
+  pub fn contents(&self) -> &[u8] {
+    &self.data
+  }
+
+  /// replaces the save with an imported one, padded or cut to size, and writes it back. the
+  /// current trailer is kept unless the import came with one
+  pub fn import(&mut self, save: &[u8], trailer: Option<&[u8]>) {
+    let trailer = trailer.unwrap_or(self.trailer()).to_vec();
+
+    self.data = save[..save.len().min(self.size)].to_vec();
+    self.data.resize(self.size, 0xff);
+    self.data.extend(trailer);
+
+    self.mark_dirty(0..self.data.len());
+  }
+
+  pub fn loaded_len(&self) -> Option<usize> {
+    self.loaded_len
   }
 
   pub fn resize(&mut self, new_size: usize) {
+    let trailer = self.data.split_off(self.size);
+
+    self.data.resize(new_size, 0xff);
+    self.data.extend(trailer);
+
     self.size = new_size;
-    self.buffer.resize(new_size, 0xff);
-    self.flush();
+
+    self.mark_dirty(0..self.data.len());
+  }
+
+  pub fn write_trailer(&mut self, trailer: &[u8]) {
+    self.data.truncate(self.size);
+    self.data.extend_from_slice(trailer);
+
+    self.mark_dirty(self.size..self.data.len());
+  }
+
+  fn mark_dirty(&mut self, range: Range<usize>) {
+    self.written = true;
+
+    self.dirty = Some(match self.dirty.take() {
+      Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+      None => range
+    });
   }
 
-  pub fn flush(&mut self) {
-    if let Some(file) = &mut self.file {
-      file.seek(SeekFrom::Start(0)).unwrap();
-      file.write_all(&self.buffer).unwrap();
+  /// writes whatever changed to storage
+  pub fn flush(&mut self) -> io::Result<()> {
+    let Some(dirty) = self.dirty.take() else {
+      return Ok(());
+    };
+
+    // the trailer may have shrunk since
+    let dirty = dirty.start.min(self.data.len())..dirty.end.min(self.data.len());
+
+    if let Some(storage) = &mut self.storage {
+      if let Err(error) = storage.store(&self.data, dirty.clone()) {
+        // try again next time
+        self.dirty = Some(dirty);
+
+        return Err(error);
+      }
     }
+
+    Ok(())
   }
-}
\ No newline at end of file
+
+  /// flushes once the game has gone a whole check without writing, so a save made up of many
+  /// writes is stored in one go. errors are kept for the frontend
+  pub fn flush_if_idle(&mut self) {
+    if self.written {
+      self.written = false;
+    } else if let Err(error) = self.flush() {
+      self.error = Some(error);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{cell::RefCell, rc::Rc};
+
+  use super::*;
+
+  // a storage that records what it's asked to store, and can be made to fail
+  #[derive(Clone, Default)]
+  struct RecordingStorage {
+    saved: Option<Vec<u8>>,
+    stored: Rc<RefCell<Vec<Range<usize>>>>,
+    failing: Rc<RefCell<bool>>
+  }
+
+  impl SaveStorage for RecordingStorage {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+      Ok(self.saved.clone())
+    }
+
+    fn store(&mut self, _data: &[u8], dirty: Range<usize>) -> io::Result<()> {
+      if *self.failing.borrow() {
+        return Err(io::Error::other("disk full"));
+      }
+
+      self.stored.borrow_mut().push(dirty);
+
+      Ok(())
+    }
+  }
+
+  fn with_storage(saved: Option<Vec<u8>>) -> (BackupFile, RecordingStorage) {
+    let storage = RecordingStorage { saved, ..Default::default() };
+
+    (BackupFile::new(0x100, Some(Box::new(storage.clone()))), storage)
+  }
+
+  #[test]
+  fn stores_one_range_covering_every_write() {
+    let (mut backup_file, storage) = with_storage(None);
+
+    backup_file.write(10, 1);
+    backup_file.write(3, 2);
+    backup_file.flush().unwrap();
+
+    assert_eq!(*storage.stored.borrow(), [3..11]);
+
+    // and nothing once it's all stored
+    backup_file.flush().unwrap();
+
+    assert_eq!(storage.stored.borrow().len(), 1);
+  }
+
+  #[test]
+  fn waits_for_the_game_to_stop_writing() {
+    let (mut backup_file, storage) = with_storage(None);
+
+    backup_file.write(0, 1);
+    backup_file.flush_if_idle();
+    backup_file.write(1, 1);
+    backup_file.flush_if_idle();
+
+    assert!(storage.stored.borrow().is_empty());
+
+    backup_file.flush_if_idle();
+
+    assert_eq!(*storage.stored.borrow(), [0..2]);
+  }
+
+  #[test]
+  fn keeps_what_failed_to_store_for_next_time() {
+    let (mut backup_file, storage) = with_storage(None);
+
+    *storage.failing.borrow_mut() = true;
+
+    backup_file.write(5, 1);
+    backup_file.flush_if_idle();
+    backup_file.flush_if_idle();
+
+    assert!(backup_file.error.take().is_some());
+
+    *storage.failing.borrow_mut() = false;
+
+    backup_file.write(7, 1);
+    backup_file.flush().unwrap();
+
+    assert_eq!(*storage.stored.borrow(), [5..8]);
+  }
+
+  #[test]
+  fn trailer_writes_only_dirty_the_trailer() {
+    let (mut backup_file, storage) = with_storage(None);
+
+    backup_file.write_trailer(&[0; TRAILER_SIZE]);
+    backup_file.flush().unwrap();
+
+    assert_eq!(*storage.stored.borrow(), [0x100..0x100 + TRAILER_SIZE]);
+    assert_eq!(backup_file.trailer().len(), TRAILER_SIZE);
+  }
+
+  #[test]
+  fn pads_short_saves_and_keeps_the_trailer_of_long_ones() {
+    let (backup_file, _) = with_storage(Some(vec![0; 0x80]));
+
+    assert_eq!(backup_file.data().len(), 0x100);
+    assert_eq!(backup_file.data()[0x80], 0xff);
+    assert_eq!(backup_file.loaded_len(), Some(0x80));
+
+    let (backup_file, _) = with_storage(Some(vec![0; 0x100 + TRAILER_SIZE]));
+
+    assert_eq!(backup_file.trailer().len(), TRAILER_SIZE);
+    assert!(backup_file.error.is_none());
+  }
+
+  #[test]
+  fn drops_anything_else_after_the_save() {
+    let (backup_file, _) = with_storage(Some(vec![0; 0x180]));
+
+    assert_eq!(backup_file.contents().len(), 0x100);
+    assert_eq!(backup_file.error.unwrap().kind(), io::ErrorKind::InvalidData);
+  }
+}
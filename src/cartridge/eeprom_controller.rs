@@ -1,8 +1,7 @@
-use std::{fs, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use super::backup_file::BackupFile;
+use super::{backup_file::BackupFile, save_storage::SaveStorage};
 
 #[derive(Serialize, Deserialize)]
 pub struct EepromController {
@@ -11,30 +10,47 @@ pub struct EepromController {
 }
 
 impl EepromController {
-  pub fn new(file_path: Option<PathBuf>) -> Self {
-    let mut eeprom_type = EepromType::Eeprom512;
-
-    let mut detected = false;
-
-    if let Some(file_path) = &file_path {
-      if let Ok(metadata) = fs::metadata(&file_path) {
-        eeprom_type = match metadata.len() {
-          512 => EepromType::Eeprom512,
-          8192 => EepromType::Eeprom8k,
-          _ => panic!("invalid eeprom type detected")
-        };
-
-        detected = true;
-      }
-    }
+  /// the size is detected from the save or the first dma to the eeprom when not known
+  pub fn new(storage: Option<Box<dyn SaveStorage>>, known_type: Option<EepromType>) -> Self {
+    let memory = BackupFile::sized_from_save(storage, |loaded_len| match (&known_type, loaded_len) {
+      (Some(known_type), _) => known_type.size(),
+      // either size may be followed by the rtc trailer
+      (None, Some(len)) if len >= EepromType::Eeprom8k.size() => EepromType::Eeprom8k.size(),
+      _ => EepromType::Eeprom512.size()
+    });
+
+    let detected = known_type.is_some() || memory.loaded_len().is_some();
+
+    let eeprom_type = if memory.size > EepromType::Eeprom512.size() {
+      EepromType::Eeprom8k
+    } else {
+      EepromType::Eeprom512
+    };
 
-    let size = eeprom_type.size();
     Self {
-      chip: EepromChip::new(eeprom_type, BackupFile::new(size, file_path)),
+      chip: EepromChip::new(eeprom_type, memory),
       detected
     }
   }
 
+  /// sizes the eeprom to fit a save of the given length, if the game hasn't already given it away
+  pub fn detect_from_save(&mut self, len: usize) {
+    if !self.detected {
+      self.detected = true;
+      self.chip.set_type(if len > EepromType::Eeprom512.size() { EepromType::Eeprom8k } else { EepromType::Eeprom512 });
+    }
+  }
+
+  pub fn detected_type(&self) -> Option<EepromType> {
+    let eeprom_type = if self.chip.memory.size > EepromType::Eeprom512.size() {
+      EepromType::Eeprom8k
+    } else {
+      EepromType::Eeprom512
+    };
+
+    self.detected.then_some(eeprom_type)
+  }
+
   pub fn read(&mut self, address: u32) -> u16 {
     if self.detected {
       self.chip.clock_data_out(address) as u16
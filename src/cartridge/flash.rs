@@ -1,22 +1,46 @@
-use std::path::PathBuf;
+// flash saves come on a handful of different chips. they all take commands written to 0x5555 after
+// an unlock sequence, but differ in their ids, which commands they have and how they're programmed.
+// programming and erasing take a while, during which reads return status bits games poll instead
+// of the data.
 
 use serde::{Deserialize, Serialize};
 
 use crate::number::Number;
 
-use super::backup_file::BackupFile;
+use super::{backup_file::BackupFile, save_storage::SaveStorage};
 
 const BANK_SIZE: usize = 0x10000;
 const SECTOR_SIZE: usize = 4 * 1024;
+// the atmel chip is programmed a page at a time instead of a byte at a time
+const PAGE_SIZE: usize = 128;
+
+const COMMAND_ADDRESS: u32 = 0x5555;
+const UNLOCK_ADDRESS: u32 = 0x2aaa;
+
+// roughly how long the chips are busy for, in cpu cycles
+const BYTE_PROGRAM_CYCLES: usize = 650;
+const PAGE_PROGRAM_CYCLES: usize = 84_000;
+const SECTOR_ERASE_CYCLES: usize = 30_000;
+const CHIP_ERASE_CYCLES: usize = 300_000;
+
+// while busy, bit 7 reads as the opposite of what's being written and bit 6 flips on every read
+const DATA_POLLING_BIT: u8 = 0b1 << 7;
+const TOGGLE_BIT: u8 = 0b1 << 6;
 
 #[derive(Serialize, Deserialize)]
 pub struct Flash {
   pub memory: BackupFile,
-  size: usize,
+  chip: FlashChip,
   bank: usize,
   mode: FlashMode,
-  chip_id: u16,
-  state: FlashState
+  state: FlashState,
+  // the cycle the current program or erase finishes at, and the value it leaves behind
+  busy_until: usize,
+  busy_value: u8,
+  toggle: u8,
+  // a flash found by watching the game with nothing to size it from starts out small. it grows if
+  // the game switches banks before it has read the chip's id
+  growable: bool
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,6 +49,8 @@ pub enum FlashMode {
   ChipId,
   Erase,
   WriteByte,
+  // how many bytes of the page have been written so far
+  WritePage(usize),
   BankSwitch
 }
 
@@ -37,31 +63,80 @@ pub enum FlashState {
 }
 
 impl Flash {
-  pub fn new(file_path: Option<PathBuf>, flash_size: FlashSize) -> Self {
-    let size = flash_size as usize;
-    let chip_id = flash_size.chip_id();
+  pub fn new(storage: Option<Box<dyn SaveStorage>>, chip: FlashChip) -> Self {
+    Self::with_memory(BackupFile::new(chip.size(), storage), chip)
+  }
+
+  fn with_memory(memory: BackupFile, chip: FlashChip) -> Self {
     Self {
-      memory: BackupFile::new(size, file_path),
-      size,
+      memory,
+      chip,
       bank: 0,
       mode: FlashMode::Initial,
-      chip_id,
-      state: FlashState::Initial
+      state: FlashState::Initial,
+      busy_until: 0,
+      busy_value: 0xff,
+      toggle: 0,
+      growable: false
     }
   }
 
-  pub fn read<T: Number>(&self, address: u32) -> T {
+  /// a flash of unknown size, sized from the existing save if there is one, or else the size the
+  /// game was found to expect
+  pub fn detect(storage: Option<Box<dyn SaveStorage>>, expected_size: Option<FlashSize>) -> Self {
+    let memory = BackupFile::sized_from_save(storage, |loaded_len| match (loaded_len, expected_size) {
+      (Some(len), _) if len > FlashSize::Flash64k as usize => FlashSize::Flash128k as usize,
+      (Some(_), _) => FlashSize::Flash64k as usize,
+      (None, Some(size)) => size as usize,
+      (None, None) => FlashSize::Flash64k as usize
+    });
+
+    let size = if memory.size > FlashSize::Flash64k as usize { FlashSize::Flash128k } else { FlashSize::Flash64k };
+    let growable = memory.loaded_len().is_none() && expected_size.is_none();
+
+    let mut flash = Self::with_memory(memory, size.default_chip());
+
+    flash.growable = growable;
+
+    flash
+  }
+
+  fn set_chip(&mut self, chip: FlashChip) {
+    self.chip = chip;
+    self.growable = false;
+
+    self.memory.resize(chip.size());
+  }
+
+  pub fn chip(&self) -> FlashChip {
+    self.chip
+  }
+
+  pub fn size_known(&self) -> bool {
+    !self.growable
+  }
+
+  pub fn read<T: Number>(&mut self, address: u32, cycles: usize) -> T {
     let offset = address & 0xffff;
-    if matches!(self.mode, FlashMode::ChipId) {
-      match offset {
-        0 => num::cast::<u16, T>(self.chip_id & 0xff).unwrap(),
-        1 => num::cast::<u16, T>((self.chip_id >> 8)).unwrap(),
-        _ => panic!("invalid offset specified for chip id mode")
-      }
-    } else {
-      let mem_address = self.flash_offset(offset as usize);
 
-      self.memory.read(mem_address)
+    if cycles < self.busy_until {
+      self.toggle ^= TOGGLE_BIT;
+
+      let status = (!self.busy_value & DATA_POLLING_BIT) | self.toggle;
+
+      return num::cast::<u8, T>(status).unwrap();
+    }
+
+    if matches!(self.mode, FlashMode::ChipId) && offset < 2 {
+      // the game checks the id against the chips it supports, so once it's seen one it's settled
+      // on that chip's size
+      self.growable = false;
+    }
+
+    match (&self.mode, offset) {
+      (FlashMode::ChipId, 0) => num::cast::<u16, T>(self.chip.id() & 0xff).unwrap(),
+      (FlashMode::ChipId, 1) => num::cast::<u16, T>(self.chip.id() >> 8).unwrap(),
+      _ => self.memory.read(self.flash_offset(offset as usize))
     }
   }
 
@@ -69,55 +144,66 @@ impl Flash {
     offset + self.bank * BANK_SIZE
   }
 
-  pub fn command(&mut self, address: u32, val: u8) {
-    const COMMAND_ADDRESS: u32 = 0xe00_5555;
+  /// moves the end of the current program or erase along with the cpu's cycles
+  pub fn rebase_cycles(&mut self, to_subtract: usize) {
+    self.busy_until = self.busy_until.saturating_sub(to_subtract);
+  }
+
+  fn set_busy(&mut self, cycles: usize, busy_cycles: usize, value: u8) {
+    self.busy_until = cycles + busy_cycles;
+    self.busy_value = value;
+  }
+
+  fn erase(&mut self, offset: usize, len: usize) {
+    for i in offset..offset + len {
+      self.memory.write(i, 0xff);
+    }
+  }
+
+  pub fn command(&mut self, address: u32, val: u8, cycles: usize) {
     match (address, val) {
-      (COMMAND_ADDRESS, 0x90) => {
-        self.mode = FlashMode::ChipId;
-        self.reset_state();
-      }
-      (COMMAND_ADDRESS, 0x80) => {
-        self.mode = FlashMode::Erase;
-        self.reset_state();
-      }
+      (COMMAND_ADDRESS, 0x90) => self.mode = FlashMode::ChipId,
+      (COMMAND_ADDRESS, 0x80) => self.mode = FlashMode::Erase,
       (COMMAND_ADDRESS, 0xa0) => {
-        self.mode = FlashMode::WriteByte;
+        self.mode = if self.chip.has_page_writes() {
+          FlashMode::WritePage(0)
+        } else {
+          FlashMode::WriteByte
+        };
         self.state = FlashState::Argument;
-      },
-      (COMMAND_ADDRESS, 0xb0) => {
+      }
+      (COMMAND_ADDRESS, 0xb0) if self.chip.size() > BANK_SIZE || self.growable => {
+        if self.growable {
+          self.set_chip(FlashSize::Flash128k.default_chip());
+        }
+
         self.mode = FlashMode::BankSwitch;
         self.state = FlashState::Argument;
-      },
-      (COMMAND_ADDRESS, 0xf0) => {
-        if matches!(self.mode, FlashMode::ChipId) {
-          self.mode = FlashMode::Initial;
-        }
-        self.reset_state();
       }
-      (COMMAND_ADDRESS, 0x10) => {
-        if matches!(self.mode, FlashMode::Erase) {
-          // erase the entire chip
-          for i in 0..self.size {
-            self.memory.write(i, 0xff);
-          }
-        }
-        self.reset_state();
+      (COMMAND_ADDRESS, 0x10) if matches!(self.mode, FlashMode::Erase) => {
+        self.erase(0, self.chip.size());
+        self.set_busy(cycles, CHIP_ERASE_CYCLES, 0xff);
+
         self.mode = FlashMode::Initial;
       }
-      (sector_address, 0x30) => {
-        if matches!(self.mode, FlashMode::Erase) {
-          let sector = sector_address & 0xf000;
+      (sector_address, 0x30) if matches!(self.mode, FlashMode::Erase) && !self.chip.has_page_writes() => {
+        let offset = self.flash_offset((sector_address & 0xf000) as usize);
 
-          let offset = self.flash_offset(sector as usize);
+        self.erase(offset, SECTOR_SIZE);
+        self.set_busy(cycles, SECTOR_ERASE_CYCLES, 0xff);
 
-          for i in 0..SECTOR_SIZE {
-            self.memory.write(i + offset, 0xff);
-          }
-        }
-        self.reset_state();
         self.mode = FlashMode::Initial;
       }
-      _ => panic!("invalid command or address specified to flash chip")
+      // 0xf0 leaves chip id mode, and anything the chip doesn't know gets ignored the same way
+      _ => self.mode = FlashMode::Initial
+    }
+  }
+
+  /// whether the chip would take a write, as opposed to one that only makes sense for sram
+  pub fn expects_write(&self, address: u32, val: u8) -> bool {
+    match self.state {
+      FlashState::Initial => (address & 0xffff == COMMAND_ADDRESS && val == 0xaa) || val == 0xf0,
+      _ => true
     }
   }
 
@@ -125,42 +211,68 @@ impl Flash {
     self.state = FlashState::Initial;
   }
 
-  pub fn write(&mut self, address: u32, val: u8) {
+  pub fn write(&mut self, address: u32, val: u8, cycles: usize) {
+    // the chip ignores everything while it's busy
+    if cycles < self.busy_until {
+      return;
+    }
+
+    let offset = address & 0xffff;
+
     match self.state {
       FlashState::Initial => {
-        if address == 0xe00_5555 && val == 0xaa {
-          self.state = FlashState::Initial2
+        if offset == COMMAND_ADDRESS && val == 0xaa {
+          self.state = FlashState::Initial2;
+        } else if val == 0xf0 {
+          // most chips also take a reset without the unlock sequence
+          self.mode = FlashMode::Initial;
         }
       }
       FlashState::Initial2 => {
-        if address == 0xe00_2aaa && val == 0x55 {
-          self.state = FlashState::Command
-        }
+        self.state = if offset == UNLOCK_ADDRESS && val == 0x55 {
+          FlashState::Command
+        } else {
+          FlashState::Initial
+        };
       }
       FlashState::Command => {
-        self.command(address, val);
+        self.reset_state();
+        self.command(offset, val, cycles);
       }
-      FlashState::Argument => {
-        match self.mode {
-          FlashMode::BankSwitch => {
-            if address == 0xe00_0000 {
-              self.bank = val as usize;
-            }
-          }
-          FlashMode::WriteByte => {
-            let offset = address & 0xffff;
-            let mem_offset = self.flash_offset(offset as usize);
-
-            self.memory.write(mem_offset, val);
-          }
-          _ => panic!("invalid mode specified for argument state")
+      FlashState::Argument => self.write_argument(offset as usize, val, cycles)
+    }
+  }
+
+  fn write_argument(&mut self, offset: usize, val: u8, cycles: usize) {
+    match self.mode {
+      FlashMode::BankSwitch if offset == 0 => self.bank = val as usize & 0b1,
+      FlashMode::WriteByte => {
+        self.memory.write(self.flash_offset(offset), val);
+        self.set_busy(cycles, BYTE_PROGRAM_CYCLES, val);
+      }
+      FlashMode::WritePage(written) => {
+        let page = offset & !(PAGE_SIZE - 1);
+
+        // any bytes of the page the game doesn't write end up erased
+        if written == 0 {
+          self.erase(page, PAGE_SIZE);
         }
 
-        self.reset_state();
-        self.mode = FlashMode::Initial;
+        self.memory.write(offset, val);
+
+        if written + 1 < PAGE_SIZE {
+          self.mode = FlashMode::WritePage(written + 1);
 
+          return;
+        }
+
+        self.set_busy(cycles, PAGE_PROGRAM_CYCLES, val);
       }
+      _ => ()
     }
+
+    self.reset_state();
+    self.mode = FlashMode::Initial;
   }
 }
 
@@ -170,15 +282,240 @@ pub enum FlashSize {
   Flash64k = 64 * 1024
 }
 
-const MACRONIX_64K_CHIP_ID: u16 = 0x1CC2;
-const MACRONIX_128K_CHIP_ID: u16 = 0x09c2;
-
 impl FlashSize {
-  // macronix chips should suffice for now
-  pub fn chip_id(&self) -> u16 {
+  // macronix chips work with most games
+  pub fn default_chip(&self) -> FlashChip {
+    match self {
+      Self::Flash128k => FlashChip::Macronix128k,
+      Self::Flash64k => FlashChip::Macronix64k
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub enum FlashChip {
+  /// atmel at29lv512
+  Atmel,
+  /// sst 39vf512
+  Sst,
+  /// macronix mx29l512
+  Macronix64k,
+  /// panasonic mn63f805mnp
+  Panasonic,
+  /// macronix mx29l010
+  Macronix128k,
+  /// sanyo le26fv10n1ts
+  Sanyo
+}
+
+impl FlashChip {
+  pub const ALL: [FlashChip; 6] = [
+    FlashChip::Atmel,
+    FlashChip::Sst,
+    FlashChip::Macronix64k,
+    FlashChip::Panasonic,
+    FlashChip::Macronix128k,
+    FlashChip::Sanyo
+  ];
+
+  /// the device id in the high byte and the manufacturer in the low byte, as the game reads them
+  pub fn id(&self) -> u16 {
     match self {
-      Self::Flash128k => MACRONIX_128K_CHIP_ID,
-      Self::Flash64k => MACRONIX_64K_CHIP_ID
+      FlashChip::Atmel => 0x3d1f,
+      FlashChip::Sst => 0xd4bf,
+      FlashChip::Macronix64k => 0x1cc2,
+      FlashChip::Panasonic => 0x1b32,
+      FlashChip::Macronix128k => 0x09c2,
+      FlashChip::Sanyo => 0x1362
     }
   }
-}
\ No newline at end of file
+
+  pub fn from_id(id: u16) -> Option<Self> {
+    Self::ALL.into_iter().find(|chip| chip.id() == id)
+  }
+
+  pub fn size(&self) -> usize {
+    match self {
+      FlashChip::Macronix128k | FlashChip::Sanyo => FlashSize::Flash128k as usize,
+      _ => FlashSize::Flash64k as usize
+    }
+  }
+
+  fn has_page_writes(&self) -> bool {
+    matches!(self, FlashChip::Atmel)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn send_command(flash: &mut Flash, address: u32, command: u8, cycles: usize) {
+    flash.write(COMMAND_ADDRESS, 0xaa, cycles);
+    flash.write(UNLOCK_ADDRESS, 0x55, cycles);
+    flash.write(address, command, cycles);
+  }
+
+  fn program_byte(flash: &mut Flash, offset: u32, value: u8, cycles: usize) {
+    send_command(flash, COMMAND_ADDRESS, 0xa0, cycles);
+    flash.write(offset, value, cycles);
+  }
+
+  fn read(flash: &mut Flash, offset: u32, cycles: usize) -> u8 {
+    flash.read::<u8>(offset, cycles)
+  }
+
+  #[test]
+  fn reads_the_chip_id_until_reset() {
+    let mut flash = Flash::new(None, FlashChip::Sanyo);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0x90, 0);
+
+    assert_eq!([read(&mut flash, 0, 0), read(&mut flash, 1, 0)], [0x62, 0x13]);
+
+    flash.write(COMMAND_ADDRESS, 0xf0, 0);
+
+    assert_eq!(read(&mut flash, 0, 0), 0xff);
+  }
+
+  #[test]
+  fn programming_a_byte_is_busy_for_a_while() {
+    let mut flash = Flash::new(None, FlashChip::Macronix64k);
+
+    program_byte(&mut flash, 0x10, 0x42, 100);
+
+    // bit 7 reads inverted and bit 6 toggles until it's done
+    let status = [read(&mut flash, 0x10, 101), read(&mut flash, 0x10, 102)];
+
+    assert_eq!(status[0] & DATA_POLLING_BIT, !0x42 & DATA_POLLING_BIT);
+    assert_ne!(status[0] & TOGGLE_BIT, status[1] & TOGGLE_BIT);
+
+    assert_eq!(read(&mut flash, 0x10, 100 + BYTE_PROGRAM_CYCLES), 0x42);
+  }
+
+  #[test]
+  fn writes_are_ignored_while_busy() {
+    let mut flash = Flash::new(None, FlashChip::Macronix64k);
+
+    program_byte(&mut flash, 0x10, 0x42, 0);
+    program_byte(&mut flash, 0x11, 0x43, 1);
+
+    assert_eq!(read(&mut flash, 0x11, BYTE_PROGRAM_CYCLES), 0xff);
+  }
+
+  #[test]
+  fn atmel_programs_a_page_at_a_time() {
+    let mut flash = Flash::new(None, FlashChip::Atmel);
+
+    flash.memory.write(0x100, 0x11);
+    flash.memory.write(0x17f, 0x22);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0xa0, 0);
+
+    for i in 0..PAGE_SIZE as u32 - 1 {
+      flash.write(0x100 + i, i as u8, 0);
+    }
+
+    // not busy until the whole page has been written
+    assert_eq!(flash.busy_until, 0);
+
+    flash.write(0x17f, 0x7f, 0);
+
+    assert_eq!(flash.busy_until, PAGE_PROGRAM_CYCLES);
+    assert_eq!(read(&mut flash, 0x100, PAGE_PROGRAM_CYCLES), 0);
+    assert_eq!(read(&mut flash, 0x17f, PAGE_PROGRAM_CYCLES), 0x7f);
+  }
+
+  #[test]
+  fn sector_erase_clears_4k_and_toggles_while_busy() {
+    let mut flash = Flash::new(None, FlashChip::Macronix64k);
+
+    for offset in [0xfff, 0x1000, 0x1fff, 0x2000] {
+      flash.memory.write(offset, 0);
+    }
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0x80, 0);
+    send_command(&mut flash, 0x1000, 0x30, 0);
+
+    assert_ne!(read(&mut flash, 0x1000, 1) & TOGGLE_BIT, read(&mut flash, 0x1000, 2) & TOGGLE_BIT);
+
+    let cycles = SECTOR_ERASE_CYCLES;
+
+    assert_eq!([0xfff, 0x1000, 0x1fff, 0x2000].map(|offset| read(&mut flash, offset, cycles)), [0, 0xff, 0xff, 0]);
+  }
+
+  #[test]
+  fn atmel_has_no_sector_erase() {
+    let mut flash = Flash::new(None, FlashChip::Atmel);
+
+    flash.memory.write(0x1000, 0);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0x80, 0);
+    send_command(&mut flash, 0x1000, 0x30, 0);
+
+    assert_eq!(read(&mut flash, 0x1000, 0), 0);
+  }
+
+  #[test]
+  fn chip_erase_clears_everything() {
+    let mut flash = Flash::new(None, FlashChip::Macronix128k);
+
+    flash.memory.write(0, 0);
+    flash.memory.write(0x1_ffff, 0);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0x80, 0);
+    send_command(&mut flash, COMMAND_ADDRESS, 0x10, 0);
+
+    assert_eq!(read(&mut flash, 0, CHIP_ERASE_CYCLES - 1) & DATA_POLLING_BIT, 0);
+    assert_eq!(read(&mut flash, 0, CHIP_ERASE_CYCLES), 0xff);
+    assert_eq!(flash.memory.read::<u8>(0x1_ffff), 0xff);
+  }
+
+  #[test]
+  fn unknown_commands_are_ignored() {
+    let mut flash = Flash::new(None, FlashChip::Macronix64k);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0x42, 0);
+
+    // the next write isn't taken as an argument to anything
+    flash.write(0x10, 0x00, 0);
+
+    assert_eq!(read(&mut flash, 0x10, 0), 0xff);
+    assert_eq!(flash.busy_until, 0);
+  }
+
+  #[test]
+  fn switches_banks_on_128k_chips() {
+    let mut flash = Flash::new(None, FlashChip::Macronix128k);
+
+    flash.memory.write(0x1_0010, 0x42);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0xb0, 0);
+    flash.write(0, 1, 0);
+
+    assert_eq!(read(&mut flash, 0x10, 0), 0x42);
+  }
+
+  #[test]
+  fn ignores_bank_switches_on_64k_chips() {
+    let mut flash = Flash::new(None, FlashChip::Sst);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0xb0, 0);
+    flash.write(0, 1, 0);
+
+    assert_eq!(flash.bank, 0);
+    assert_eq!(flash.memory.size, FlashSize::Flash64k as usize);
+  }
+
+  #[test]
+  fn grows_to_128k_when_switching_banks_before_reading_the_id() {
+    let mut flash = Flash::detect(None, None);
+
+    send_command(&mut flash, COMMAND_ADDRESS, 0xb0, 0);
+    flash.write(0, 1, 0);
+
+    assert_eq!(flash.bank, 1);
+    assert_eq!(flash.chip(), FlashChip::Macronix128k);
+    assert!(flash.size_known());
+  }
+}
@@ -0,0 +1,351 @@
+// per game hardware that can't be reliably detected from the rom itself. every line is a game
+// code followed by options separated by spaces:
+//
+//   none | eeprom | eeprom512 | eeprom8k | sram | flash64k | flash128k   save type
+//   flash_id=0x1362                                                        flash chip id
+//   rtc | solar | gyro | tilt | rumble                                     extra hardware
+//   idle_loop=off                                                          no idle loop skipping
+//   ignore_idle_loop=0x8001234                                             never skip this loop
+//
+// a plain eeprom still has its size detected from the first dma to it. the flash chip id is one of
+// atmel 0x3d1f, sst 0xd4bf, macronix 0x1cc2 or 0x09c2, panasonic 0x1b32 or sanyo 0x1362, and the
+// chip decides the flash size, so it has to agree with a flash save type given alongside it. the
+// idle loop options are for games the idle loop detector still gets wrong, none of which are known
+// yet, so no built in entry uses them. users can supply a file in the same format, where each
+// entry replaces the built in one for that game.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::idle_loop::IdleLoopOverride;
+
+use super::flash::{FlashChip, FlashSize};
+
+const BUILT_IN_GAMES: &str = "
+# pokemon ruby and sapphire
+AXVE flash128k rtc
+AXVJ flash128k rtc
+AXVP flash128k rtc
+AXVD flash128k rtc
+AXVF flash128k rtc
+AXVI flash128k rtc
+AXVS flash128k rtc
+AXPE flash128k rtc
+AXPJ flash128k rtc
+AXPP flash128k rtc
+AXPD flash128k rtc
+AXPF flash128k rtc
+AXPI flash128k rtc
+AXPS flash128k rtc
+
+# pokemon emerald
+BPEE flash128k rtc
+BPEJ flash128k rtc
+BPEP flash128k rtc
+BPED flash128k rtc
+BPEF flash128k rtc
+BPEI flash128k rtc
+BPES flash128k rtc
+
+# pokemon firered and leafgreen
+BPRE flash128k
+BPRJ flash128k
+BPRP flash128k
+BPRD flash128k
+BPRF flash128k
+BPRI flash128k
+BPRS flash128k
+BPGE flash128k
+BPGJ flash128k
+BPGP flash128k
+BPGD flash128k
+BPGF flash128k
+BPGI flash128k
+BPGS flash128k
+
+# super mario advance 4
+AX4E flash128k
+AX4J flash128k
+AX4P flash128k
+
+# sennen kazoku
+BKAJ flash128k rtc
+
+# boktai 1, 2 and 3
+U3IE eeprom rtc solar
+U3IJ eeprom rtc solar
+U3IP eeprom rtc solar
+U32E eeprom rtc solar
+U32J eeprom rtc solar
+U32P eeprom rtc solar
+U33J eeprom rtc solar
+
+# warioware: twisted!
+RZWE sram gyro rumble
+RZWJ sram gyro rumble
+RZWP sram gyro rumble
+
+# drill dozer
+V49E sram rumble
+V49J sram rumble
+V49P sram rumble
+
+# yoshi topsy-turvy
+KYGE eeprom tilt
+KYGJ eeprom tilt
+KYGP eeprom tilt
+
+# koro koro puzzle happy panechu!
+KHPJ eeprom tilt
+";
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+pub enum SaveType {
+  None,
+  Eeprom,
+  Eeprom512,
+  Eeprom8k,
+  Sram,
+  Flash64k,
+  Flash128k
+}
+
+impl SaveType {
+  /// the name used for the save type in game database files
+  pub fn name(&self) -> &'static str {
+    match self {
+      SaveType::None => "none",
+      SaveType::Eeprom => "eeprom",
+      SaveType::Eeprom512 => "eeprom512",
+      SaveType::Eeprom8k => "eeprom8k",
+      SaveType::Sram => "sram",
+      SaveType::Flash64k => "flash64k",
+      SaveType::Flash128k => "flash128k"
+    }
+  }
+}
+
+bitflags! {
+  #[derive(Copy, Clone, Default, Serialize, Deserialize)]
+  #[serde(transparent)]
+  pub struct CartridgeHardware: u8 {
+    const RTC = 0b1;
+    const SOLAR_SENSOR = 0b1 << 1;
+    const GYRO = 0b1 << 2;
+    const TILT = 0b1 << 3;
+    const RUMBLE = 0b1 << 4;
+  }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GameOverrides {
+  pub save_type: Option<SaveType>,
+  pub flash_chip: Option<FlashChip>,
+  pub hardware: CartridgeHardware,
+  pub idle_loop: Option<IdleLoopOverride>
+}
+
+pub struct GameDb {
+  games: HashMap<String, GameOverrides>
+}
+
+impl Default for GameDb {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl GameDb {
+  pub fn new() -> Self {
+    let mut game_db = Self {
+      games: HashMap::new()
+    };
+
+    game_db.load(BUILT_IN_GAMES).unwrap();
+
+    game_db
+  }
+
+  /// adds the entries in text, replacing any existing entries for the same games. nothing is
+  /// added if any line is invalid
+  pub fn load(&mut self, text: &str) -> Result<(), String> {
+    let mut games = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap_or("").trim();
+
+      let mut fields = line.split_whitespace();
+
+      if let Some(game_code) = fields.next() {
+        let overrides = Self::parse_options(fields).map_err(|error| format!("line {}: {error}", i + 1))?;
+
+        games.push((game_code.to_string(), overrides));
+      }
+    }
+
+    self.games.extend(games);
+
+    Ok(())
+  }
+
+  pub fn get(&self, game_code: &str) -> Option<&GameOverrides> {
+    self.games.get(game_code)
+  }
+
+  fn parse_options<'a>(options: impl Iterator<Item = &'a str>) -> Result<GameOverrides, String> {
+    let mut overrides = GameOverrides::default();
+
+    for option in options {
+      let (name, value) = match option.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (option, None)
+      };
+
+      match (name, value) {
+        ("none", None) => overrides.save_type = Some(SaveType::None),
+        ("eeprom", None) => overrides.save_type = Some(SaveType::Eeprom),
+        ("eeprom512", None) => overrides.save_type = Some(SaveType::Eeprom512),
+        ("eeprom8k", None) => overrides.save_type = Some(SaveType::Eeprom8k),
+        ("sram", None) => overrides.save_type = Some(SaveType::Sram),
+        ("flash64k", None) => overrides.save_type = Some(SaveType::Flash64k),
+        ("flash128k", None) => overrides.save_type = Some(SaveType::Flash128k),
+        ("flash_id", Some(value)) => {
+          let chip = FlashChip::from_id(Self::parse_number(value)? as u16).ok_or(format!("unknown flash chip id {value}"))?;
+
+          overrides.flash_chip = Some(chip);
+        }
+        ("rtc", None) => overrides.hardware.insert(CartridgeHardware::RTC),
+        ("solar", None) => overrides.hardware.insert(CartridgeHardware::SOLAR_SENSOR),
+        ("gyro", None) => overrides.hardware.insert(CartridgeHardware::GYRO),
+        ("tilt", None) => overrides.hardware.insert(CartridgeHardware::TILT),
+        ("rumble", None) => overrides.hardware.insert(CartridgeHardware::RUMBLE),
+        ("idle_loop", Some("off")) => overrides.idle_loop = Some(IdleLoopOverride::Disabled),
+        ("ignore_idle_loop", Some(value)) => {
+          let address = Self::parse_number(value)?;
+
+          match &mut overrides.idle_loop {
+            Some(IdleLoopOverride::Ignore(addresses)) => addresses.push(address),
+            Some(IdleLoopOverride::Disabled) => (),
+            None => overrides.idle_loop = Some(IdleLoopOverride::Ignore(vec![address]))
+          }
+        }
+        _ => return Err(format!("unknown option {option}"))
+      }
+    }
+
+    if let Some(chip) = overrides.flash_chip {
+      let flash_size = match overrides.save_type {
+        Some(SaveType::Flash64k) => Some(FlashSize::Flash64k),
+        Some(SaveType::Flash128k) => Some(FlashSize::Flash128k),
+        Some(save_type) => return Err(format!("a flash chip id doesn't go with {}", save_type.name())),
+        None => None
+      };
+
+      if flash_size.is_some_and(|flash_size| flash_size as usize != chip.size()) {
+        return Err(format!("flash chip id {:#06x} is for a different flash size", chip.id()));
+      }
+    }
+
+    Ok(overrides)
+  }
+
+  fn parse_number(value: &str) -> Result<u32, String> {
+    let result = match value.strip_prefix("0x") {
+      Some(hex) => u32::from_str_radix(hex, 16),
+      None => value.parse()
+    };
+
+    result.map_err(|_| format!("invalid number {value}"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reads_options_and_skips_comments() {
+    let mut game_db = GameDb::new();
+
+    game_db.load("# a comment\n\nABCE sram rtc tilt # trailing comment\nABCJ idle_loop=off").unwrap();
+
+    let overrides = game_db.get("ABCE").unwrap();
+
+    assert_eq!(overrides.save_type, Some(SaveType::Sram));
+    assert_eq!(overrides.hardware.bits(), (CartridgeHardware::RTC | CartridgeHardware::TILT).bits());
+    assert!(matches!(game_db.get("ABCJ").unwrap().idle_loop, Some(IdleLoopOverride::Disabled)));
+  }
+
+  #[test]
+  fn entries_replace_the_built_in_ones() {
+    let mut game_db = GameDb::new();
+
+    assert_eq!(game_db.get("AXVE").unwrap().save_type, Some(SaveType::Flash128k));
+
+    game_db.load("AXVE sram").unwrap();
+
+    assert_eq!(game_db.get("AXVE").unwrap().save_type, Some(SaveType::Sram));
+  }
+
+  #[test]
+  fn errors_name_the_line() {
+    let mut game_db = GameDb::new();
+
+    assert_eq!(game_db.load("ABCE sram\nABCJ sram bogus"), Err("line 2: unknown option bogus".to_string()));
+    assert_eq!(game_db.load("ABCE ignore_idle_loop=0xzz"), Err("line 1: invalid number 0xzz".to_string()));
+  }
+
+  #[test]
+  fn nothing_is_added_from_an_invalid_file() {
+    let mut game_db = GameDb::new();
+
+    assert!(game_db.load("ABCE sram\nABCJ bogus").is_err());
+    assert!(game_db.get("ABCE").is_none());
+  }
+
+  #[test]
+  fn ignored_idle_loops_add_up() {
+    let mut game_db = GameDb::new();
+
+    game_db.load("ABCE ignore_idle_loop=0x8000100 ignore_idle_loop=134218240").unwrap();
+
+    let Some(IdleLoopOverride::Ignore(addresses)) = &game_db.get("ABCE").unwrap().idle_loop else {
+      panic!("expected ignored idle loops");
+    };
+
+    assert_eq!(addresses, &[0x800_0100, 0x800_0200]);
+  }
+
+  #[test]
+  fn flash_ids_pick_the_chip() {
+    let mut game_db = GameDb::new();
+
+    game_db.load("ABCE flash128k flash_id=0x1362\nABCJ flash_id=0x3d1f").unwrap();
+
+    assert_eq!(game_db.get("ABCE").unwrap().flash_chip, Some(FlashChip::Sanyo));
+    assert_eq!(game_db.get("ABCJ").unwrap().flash_chip, Some(FlashChip::Atmel));
+  }
+
+  #[test]
+  fn rejects_unknown_flash_ids() {
+    assert_eq!(GameDb::new().load("ABCE flash_id=0x1234"), Err("line 1: unknown flash chip id 0x1234".to_string()));
+  }
+
+  #[test]
+  fn rejects_flash_ids_for_a_different_size() {
+    assert_eq!(
+      GameDb::new().load("ABCE flash64k flash_id=0x09c2"),
+      Err("line 1: flash chip id 0x09c2 is for a different flash size".to_string())
+    );
+    assert_eq!(
+      GameDb::new().load("ABCE flash128k flash_id=0xd4bf"),
+      Err("line 1: flash chip id 0xd4bf is for a different flash size".to_string())
+    );
+  }
+
+  #[test]
+  fn rejects_flash_ids_for_other_save_types() {
+    assert_eq!(GameDb::new().load("ABCE sram flash_id=0x1cc2"), Err("line 1: a flash chip id doesn't go with sram".to_string()));
+  }
+}
@@ -0,0 +1,113 @@
+// the four pin gpio port some cartridges have at 0x80000c4 for extra hardware like the rtc. the
+// registers are only visible once the game makes them readable, before that the addresses read
+// as rom like everywhere else. a cartridge can have several devices on the same pins, like the
+// rtc and solar sensor on boktai, each only driving its own output pins.
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+  gyro_sensor::GyroSensor,
+  motion_input::MotionInput,
+  rtc::Rtc,
+  rumble::{Rumble, RumbleOutput},
+  solar_sensor::SolarSensor
+};
+
+pub const GPIO_DATA: u32 = 0xc4;
+pub const GPIO_DIRECTION: u32 = 0xc6;
+pub const GPIO_CONTROL: u32 = 0xc8;
+
+const PIN_MASK: u8 = 0xf;
+
+const RTC_OUTPUT_PINS: u8 = 0b10;
+const SOLAR_SENSOR_OUTPUT_PINS: u8 = 0b1000;
+const GYRO_SENSOR_OUTPUT_PINS: u8 = 0b100;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Gpio {
+  pins: u8,
+  // set bits are outputs driven by the game, clear bits are inputs driven by the cartridge
+  direction: u8,
+  pub readable: bool,
+  pub rtc: Option<Rtc>,
+  pub solar_sensor: Option<SolarSensor>,
+  pub gyro_sensor: Option<GyroSensor>,
+  pub rumble: Option<Rumble>,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  pub motion_input: Box<dyn MotionInput>,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  pub rumble_output: Box<dyn RumbleOutput>
+}
+
+impl Gpio {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// moves over everything the frontend set up on the gpio being replaced, like when loading a
+  /// save state or another game
+  pub fn carry_over(&mut self, previous: Gpio) {
+    if let (Some(rtc), Some(previous_rtc)) = (&mut self.rtc, previous.rtc) {
+      rtc.time_source = previous_rtc.time_source;
+    }
+
+    if let (Some(solar_sensor), Some(previous_solar_sensor)) = (&mut self.solar_sensor, previous.solar_sensor) {
+      solar_sensor.set_level(previous_solar_sensor.level);
+    }
+
+    self.motion_input = previous.motion_input;
+    self.rumble_output = previous.rumble_output;
+  }
+
+  pub fn read(&self, offset: u32) -> u16 {
+    match offset {
+      GPIO_DATA => self.pins as u16,
+      GPIO_DIRECTION => self.direction as u16,
+      GPIO_CONTROL => self.readable as u16,
+      _ => 0
+    }
+  }
+
+  /// returns true when the cartridge raises an interrupt
+  pub fn write(&mut self, offset: u32, value: u16) -> bool {
+    let mut irq = false;
+
+    match offset {
+      GPIO_DATA => {
+        self.pins = (self.pins & !self.direction) | (value as u8 & self.direction & PIN_MASK);
+
+        let pins = self.pins;
+
+        if let Some(output) = self.rtc.as_mut().and_then(|rtc| rtc.write_pins(pins, &mut irq)) {
+          self.drive(output, RTC_OUTPUT_PINS);
+        }
+
+        if let Some(output) = self.solar_sensor.as_mut().and_then(|solar_sensor| solar_sensor.write_pins(pins)) {
+          self.drive(output, SOLAR_SENSOR_OUTPUT_PINS);
+        }
+
+        if let Some(output) = self.gyro_sensor.as_mut().and_then(|gyro_sensor| gyro_sensor.write_pins(pins, &*self.motion_input)) {
+          self.drive(output, GYRO_SENSOR_OUTPUT_PINS);
+        }
+
+        if let Some(rumble) = &mut self.rumble {
+          rumble.write_pins(pins, &mut *self.rumble_output);
+        }
+      }
+      GPIO_DIRECTION => self.direction = value as u8 & PIN_MASK,
+      GPIO_CONTROL => self.readable = value & 0b1 != 0,
+      _ => ()
+    }
+
+    irq
+  }
+
+  /// sets the device's output pins that the game has left as inputs
+  fn drive(&mut self, output: u8, output_pins: u8) {
+    let mask = !self.direction & output_pins;
+
+    self.pins = (self.pins & !mask) | (output & mask);
+  }
+}
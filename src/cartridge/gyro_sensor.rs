@@ -0,0 +1,51 @@
+// the gyroscope on warioware: twisted!. raising the start pin samples the rotation rate, which is
+// then clocked out most significant bit first on each falling edge of the clock pin.
+
+use serde::{Deserialize, Serialize};
+
+use super::motion_input::MotionInput;
+
+const START: u8 = 0b1;
+const CLOCK: u8 = 0b1 << 1;
+const OUTPUT_SHIFT: u8 = 2;
+
+// what the sensor reads when the console is still, and how far it goes either way
+const CENTER: f32 = 0x6c0 as f32;
+const RANGE: f32 = 0x400 as f32;
+
+// the fastest rotation the sensor can measure, 300 degrees per second
+pub const MAX_ROTATION_RATE: f32 = 300.0 * std::f32::consts::PI / 180.0;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GyroSensor {
+  sample: u16,
+  last_clock: bool
+}
+
+impl GyroSensor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// called whenever the gpio pins change, returns the sensor's output pin
+  pub fn write_pins(&mut self, pins: u8, motion_input: &dyn MotionInput) -> Option<u8> {
+    if pins & START != 0 {
+      let rotation = (motion_input.motion().rotation_z / MAX_ROTATION_RATE).clamp(-1.0, 1.0);
+
+      self.sample = (CENTER + rotation * RANGE) as u16;
+    }
+
+    let clock = pins & CLOCK != 0;
+    let mut output = None;
+
+    if self.last_clock && !clock {
+      output = Some(((self.sample >> 15) as u8) << OUTPUT_SHIFT);
+
+      self.sample <<= 1;
+    }
+
+    self.last_clock = clock;
+
+    output
+  }
+}
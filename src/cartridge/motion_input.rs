@@ -0,0 +1,38 @@
+// how the frontend tells motion sensing cartridges how the console is being moved, whether that's
+// from a phone's own sensors, an analog stick or keys
+
+use std::{cell::Cell, rc::Rc};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Motion {
+  // rotation around the axis going through the screen in radians per second, clockwise is positive
+  pub rotation_z: f32,
+  // how far the console is tilted to the right and towards the player, from -1.0 to 1.0 where 1.0
+  // is standing on its side
+  pub tilt_x: f32,
+  pub tilt_y: f32
+}
+
+pub trait MotionInput {
+  fn motion(&self) -> Motion;
+}
+
+impl Default for Box<dyn MotionInput> {
+  fn default() -> Self {
+    Box::new(Motion::default())
+  }
+}
+
+/// a console that's always moving the same way
+impl MotionInput for Motion {
+  fn motion(&self) -> Motion {
+    *self
+  }
+}
+
+/// motion the frontend keeps updating through its own clone of the cell
+impl MotionInput for Rc<Cell<Motion>> {
+  fn motion(&self) -> Motion {
+    self.get()
+  }
+}
@@ -0,0 +1,77 @@
+// the header at the start of every rom. the bios refuses to boot a cartridge whose logo or
+// complement check doesn't match, but games loaded straight into an emulator can have either
+// one wrong, so they're only reported here rather than enforced.
+
+const HEADER_SIZE: usize = 0xc0;
+
+// the compressed nintendo logo every licensed cartridge carries at 0x04
+const NINTENDO_LOGO: [u8; 156] = [
+  0x24, 0xff, 0xae, 0x51, 0x69, 0x9a, 0xa2, 0x21, 0x3d, 0x84, 0x82, 0x0a, 0x84, 0xe4, 0x09, 0xad,
+  0x11, 0x24, 0x8b, 0x98, 0xc0, 0x81, 0x7f, 0x21, 0xa3, 0x52, 0xbe, 0x19, 0x93, 0x09, 0xce, 0x20,
+  0x10, 0x46, 0x4a, 0x4a, 0xf8, 0x27, 0x31, 0xec, 0x58, 0xc7, 0xe8, 0x33, 0x82, 0xe3, 0xce, 0xbf,
+  0x85, 0xf4, 0xdf, 0x94, 0xce, 0x4b, 0x09, 0xc1, 0x94, 0x56, 0x8a, 0xc0, 0x13, 0x72, 0xa7, 0xfc,
+  0x9f, 0x84, 0x4d, 0x73, 0xa3, 0xca, 0x9a, 0x61, 0x58, 0x97, 0xa3, 0x27, 0xfc, 0x03, 0x98, 0x76,
+  0x23, 0x1d, 0xc7, 0x61, 0x03, 0x04, 0xae, 0x56, 0xbf, 0x38, 0x84, 0x00, 0x40, 0xa7, 0x0e, 0xfd,
+  0xff, 0x52, 0xfe, 0x03, 0x6f, 0x95, 0x30, 0xf1, 0x97, 0xfb, 0xc0, 0x85, 0x60, 0xd6, 0x80, 0x25,
+  0xa9, 0x63, 0xbe, 0x03, 0x01, 0x4e, 0x38, 0xe2, 0xf9, 0xa2, 0x34, 0xff, 0xbb, 0x3e, 0x03, 0x44,
+  0x78, 0x00, 0x90, 0xcb, 0x88, 0x11, 0x3a, 0x94, 0x65, 0xc0, 0x7c, 0x63, 0x87, 0xf0, 0x3c, 0xaf,
+  0xd6, 0x25, 0xe4, 0x8b, 0x38, 0x0a, 0xac, 0x72, 0x21, 0xd4, 0xf8, 0x07
+];
+
+#[derive(Clone)]
+pub struct RomHeader {
+  /// where the branch at the start of the rom jumps to, if it is one
+  pub entry_point: Option<u32>,
+  pub logo_valid: bool,
+  pub title: String,
+  pub game_code: String,
+  pub maker_code: String,
+  pub unit_code: u8,
+  pub version: u8,
+  pub complement_check: u8,
+  pub complement_check_valid: bool
+}
+
+impl RomHeader {
+  /// returns None for roms too small to have a header, like an empty slot
+  pub fn parse(rom: &[u8]) -> Option<Self> {
+    let header = rom.get(..HEADER_SIZE)?;
+
+    let entry_branch = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+    // an unconditional arm branch, with the offset relative to the pipelined pc
+    let entry_point = (entry_branch & 0xff00_0000 == 0xea00_0000)
+      .then(|| 0x800_0008u32.wrapping_add((((entry_branch << 8) as i32) >> 6) as u32));
+
+    let complement_check = header[0xbd];
+
+    Some(Self {
+      entry_point,
+      logo_valid: header[0x04..0xa0] == NINTENDO_LOGO,
+      title: Self::read_string(&header[0xa0..0xac]),
+      game_code: Self::read_string(&header[0xac..0xb0]),
+      maker_code: Self::read_string(&header[0xb0..0xb2]),
+      unit_code: header[0xb3],
+      version: header[0xbc],
+      complement_check,
+      complement_check_valid: complement_check == Self::calculate_complement_check(header)
+    })
+  }
+
+  fn calculate_complement_check(header: &[u8]) -> u8 {
+    let sum = header[0xa0..0xbd].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    0u8.wrapping_sub(sum).wrapping_sub(0x19)
+  }
+
+  // strings are padded with zeroes, and homebrew sometimes fills them with garbage
+  fn read_string(bytes: &[u8]) -> String {
+    bytes
+      .iter()
+      .take_while(|byte| **byte != 0)
+      .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '?' })
+      .collect::<String>()
+      .trim_end()
+      .to_string()
+  }
+}
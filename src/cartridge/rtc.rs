@@ -0,0 +1,566 @@
+// seiko S-3511 real time clock, talked to serially over three of the gpio pins. a transfer starts
+// by raising CS while SCK is high, after which a command byte is clocked in most significant bit
+// first, followed by any parameter bytes clocked in or out least significant bit first. every bit
+// is latched on the rising edge of SCK. the clock itself is kept as an offset from the time
+// source, which changes whenever the game sets the time.
+
+use serde::{Deserialize, Serialize};
+
+use super::time_source::{DateTime, TimeSource};
+
+const SCK: u8 = 0b1;
+const SIO: u8 = 0b1 << 1;
+const CS: u8 = 0b1 << 2;
+
+// every command byte starts with this in the upper nibble
+const COMMAND_CODE: u8 = 0b0110;
+
+const COMMAND_RESET: u8 = 0;
+const COMMAND_STATUS: u8 = 1;
+const COMMAND_DATE_TIME: u8 = 2;
+const COMMAND_TIME: u8 = 3;
+const COMMAND_ALARM: u8 = 4;
+const COMMAND_IRQ: u8 = 6;
+
+// parameter bytes for each command
+const COMMAND_BYTES: [usize; 8] = [0, 1, 7, 3, 2, 0, 0, 0];
+
+const STATUS_FREQUENCY_IRQ: u8 = 0b1 << 1;
+const STATUS_MINUTE_IRQ: u8 = 0b1 << 3;
+const STATUS_ALARM_IRQ: u8 = 0b1 << 5;
+const STATUS_24_HOUR: u8 = 0b1 << 6;
+const STATUS_POWER_FAILURE: u8 = 0b1 << 7;
+
+const STATUS_WRITABLE: u8 = STATUS_FREQUENCY_IRQ | STATUS_MINUTE_IRQ | STATUS_ALARM_IRQ | STATUS_24_HOUR;
+
+pub const TRAILER_SIZE: usize = 16;
+
+// 2000-01-01 00:00:00, what a reset sets the clock to
+const RESET_TIMESTAMP: i64 = 946684800;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum TransferState {
+  Idle,
+  Selecting,
+  Transferring
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Rtc {
+  state: TransferState,
+  last_pins: u8,
+  shift: u8,
+  bits_shifted: usize,
+  command: Option<u8>,
+  // the date and time being read or written, in the order the chip sends them
+  buffer: [u8; 7],
+  buffer_index: usize,
+  bytes_left: usize,
+  pub status: u8,
+  alarm: [u8; 2],
+  // seconds between the time source and the time the game has set
+  pub offset: i64,
+  last_minute: i64,
+  // set whenever the time or status changes, so the cartridge knows to persist them
+  pub changed: bool,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  pub time_source: Box<dyn TimeSource>
+}
+
+impl Default for Rtc {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Rtc {
+  pub fn new() -> Self {
+    let mut rtc = Self {
+      state: TransferState::Idle,
+      last_pins: 0,
+      shift: 0,
+      bits_shifted: 0,
+      command: None,
+      buffer: [0; 7],
+      buffer_index: 0,
+      bytes_left: 0,
+      status: STATUS_24_HOUR,
+      alarm: [0; 2],
+      offset: 0,
+      last_minute: 0,
+      changed: false,
+      time_source: Box::default()
+    };
+
+    rtc.last_minute = rtc.minute();
+
+    rtc
+  }
+
+  pub fn now(&self) -> i64 {
+    self.time_source.now() + self.offset
+  }
+
+  fn minute(&self) -> i64 {
+    self.now().div_euclid(60)
+  }
+
+  pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+    self.time_source = time_source;
+
+    // a different clock isn't a minute passing
+    self.last_minute = self.minute();
+  }
+
+  /// called whenever the gpio pins change. returns what the rtc drives onto the pins, if anything.
+  /// the irq flag is set when the game forces an interrupt
+  pub fn write_pins(&mut self, pins: u8, irq: &mut bool) -> Option<u8> {
+    let rising_edge = self.last_pins & SCK == 0 && pins & SCK != 0;
+
+    self.last_pins = pins;
+
+    match self.state {
+      TransferState::Idle => {
+        if pins & (SCK | CS) == SCK {
+          self.state = TransferState::Selecting;
+        }
+
+        None
+      }
+      TransferState::Selecting => {
+        if pins & (SCK | CS) == SCK | CS {
+          self.state = TransferState::Transferring;
+          self.command = None;
+          self.shift = 0;
+          self.bits_shifted = 0;
+        } else if pins & (SCK | CS) != SCK {
+          self.state = TransferState::Idle;
+        }
+
+        None
+      }
+      TransferState::Transferring if pins & CS == 0 => {
+        self.state = if pins & SCK != 0 { TransferState::Selecting } else { TransferState::Idle };
+        self.command = None;
+
+        Some(SCK)
+      }
+      TransferState::Transferring => {
+        if pins & SCK == 0 {
+          // the game sets up the next bit on SIO while SCK is low, then raises it
+          let bit = (pins & SIO) >> 1;
+
+          self.shift = (self.shift & !(1 << self.bits_shifted)) | bit << self.bits_shifted;
+
+          return None;
+        }
+
+        if !rising_edge {
+          return None;
+        }
+
+        match self.command {
+          Some(command) if command & 0b1 == 1 => {
+            let bit = self.output_bit();
+
+            self.bits_shifted += 1;
+
+            if self.bits_shifted == 8 {
+              self.finish_byte();
+            }
+
+            Some(SCK | CS | bit << 1)
+          }
+          _ => {
+            self.bits_shifted += 1;
+
+            if self.bits_shifted == 8 {
+              self.process_byte(irq);
+            }
+
+            None
+          }
+        }
+      }
+    }
+  }
+
+  fn output_bit(&self) -> u8 {
+    let byte = match self.command.map(|command| command >> 1 & 0b111) {
+      Some(COMMAND_STATUS) => self.status,
+      Some(COMMAND_DATE_TIME) | Some(COMMAND_TIME) => self.buffer[self.buffer_index],
+      Some(COMMAND_ALARM) => self.alarm[self.buffer_index],
+      _ => 0
+    };
+
+    (byte >> self.bits_shifted) & 0b1
+  }
+
+  fn process_byte(&mut self, irq: &mut bool) {
+    let byte = self.shift;
+
+    self.shift = 0;
+    self.bits_shifted = 0;
+
+    let Some(command) = self.command else {
+      // the command is sent most significant bit first, unlike everything else
+      let command = byte.reverse_bits();
+
+      if command >> 4 == COMMAND_CODE {
+        self.start_command(command, irq);
+      }
+
+      return;
+    };
+
+    match command >> 1 & 0b111 {
+      COMMAND_STATUS => {
+        self.status = (self.status & !STATUS_WRITABLE) | (byte & STATUS_WRITABLE);
+        self.changed = true;
+      }
+      COMMAND_DATE_TIME | COMMAND_TIME => self.buffer[self.buffer_index] = byte,
+      COMMAND_ALARM => self.alarm[self.buffer_index] = byte,
+      _ => ()
+    }
+
+    self.finish_byte();
+  }
+
+  fn start_command(&mut self, command: u8, irq: &mut bool) {
+    let command_type = command >> 1 & 0b111;
+
+    self.bytes_left = COMMAND_BYTES[command_type as usize];
+    self.buffer_index = 0;
+
+    match command_type {
+      COMMAND_RESET => {
+        self.status = 0;
+        self.alarm = [0; 2];
+        self.offset = RESET_TIMESTAMP - self.time_source.now();
+        self.changed = true;
+      }
+      COMMAND_DATE_TIME => self.buffer = self.date_time_bytes(self.now()),
+      COMMAND_TIME => {
+        // only the time part is sent
+        self.buffer = self.date_time_bytes(self.now());
+        self.buffer_index = 4;
+      }
+      COMMAND_IRQ => *irq = true,
+      _ => ()
+    }
+
+    if self.bytes_left > 0 {
+      self.command = Some(command);
+    }
+  }
+
+  fn finish_byte(&mut self) {
+    let Some(command) = self.command else {
+      return;
+    };
+
+    self.bits_shifted = 0;
+    self.buffer_index += 1;
+    self.bytes_left -= 1;
+
+    if command & 0b1 == 1 && command >> 1 & 0b111 == COMMAND_STATUS {
+      // the power failure flag clears once it's been read
+      self.status &= !STATUS_POWER_FAILURE;
+    }
+
+    if self.bytes_left == 0 {
+      let command_type = command >> 1 & 0b111;
+
+      if command & 0b1 == 0 && (command_type == COMMAND_DATE_TIME || command_type == COMMAND_TIME) {
+        self.set_time(command_type == COMMAND_TIME);
+      }
+
+      self.command = None;
+    }
+  }
+
+  /// the date and time as the chip sends it: year, month, day, day of week, hour, minute, second
+  pub fn date_time_bytes(&self, timestamp: i64) -> [u8; 7] {
+    let date_time = DateTime::from_timestamp(timestamp);
+
+    let hour = if self.status & STATUS_24_HOUR != 0 {
+      bcd(date_time.hour)
+    } else {
+      bcd(date_time.hour % 12) | if date_time.hour >= 12 { 0x80 } else { 0 }
+    };
+
+    [
+      bcd(date_time.year.rem_euclid(100) as u32),
+      bcd(date_time.month),
+      bcd(date_time.day),
+      date_time.day_of_week as u8,
+      hour,
+      bcd(date_time.minute),
+      bcd(date_time.second)
+    ]
+  }
+
+  /// the timestamp for a date and time in the format the chip sends it
+  pub fn parse_date_time(&self, bytes: &[u8; 7]) -> i64 {
+    let hour = if self.status & STATUS_24_HOUR != 0 {
+      from_bcd(bytes[4] & 0x3f)
+    } else {
+      from_bcd(bytes[4] & 0x1f) % 12 + if bytes[4] & 0x80 != 0 { 12 } else { 0 }
+    };
+
+    DateTime {
+      year: 2000 + from_bcd(bytes[0]) as i64,
+      month: from_bcd(bytes[1] & 0x1f).clamp(1, 12),
+      day: from_bcd(bytes[2] & 0x3f).clamp(1, 31),
+      day_of_week: bytes[3] as u32 & 0b111,
+      hour: hour.min(23),
+      minute: from_bcd(bytes[5] & 0x7f).min(59),
+      second: from_bcd(bytes[6] & 0x7f).min(59)
+    }.timestamp()
+  }
+
+  fn set_time(&mut self, time_only: bool) {
+    if time_only {
+      // keep the current date
+      let date = self.date_time_bytes(self.now());
+
+      self.buffer[..4].copy_from_slice(&date[..4]);
+    }
+
+    self.offset = self.parse_date_time(&self.buffer) - self.time_source.now();
+    self.changed = true;
+  }
+
+  /// checks for the per minute and alarm interrupts, called about once a second
+  pub fn tick(&mut self) -> bool {
+    let now = self.now();
+    let minute = now.div_euclid(60);
+
+    if minute == self.last_minute {
+      return false;
+    }
+
+    self.last_minute = minute;
+
+    let date_time = self.date_time_bytes(now);
+
+    let alarm = self.status & STATUS_ALARM_IRQ != 0
+      && date_time[4] & 0x3f == self.alarm[0] & 0x3f
+      && date_time[5] == self.alarm[1];
+
+    self.status & STATUS_MINUTE_IRQ != 0 || alarm
+  }
+
+  /// the rtc state as stored after the save data: the current date and time, the status register,
+  /// then the time source's time when it was saved as a little endian u64
+  pub fn to_trailer(&self) -> [u8; TRAILER_SIZE] {
+    let mut trailer = [0; TRAILER_SIZE];
+    let source_time = self.time_source.now();
+
+    trailer[..7].copy_from_slice(&self.date_time_bytes(source_time + self.offset));
+    trailer[7] = self.status;
+    trailer[8..].copy_from_slice(&(source_time as u64).to_le_bytes());
+
+    trailer
+  }
+
+  pub fn load_trailer(&mut self, trailer: &[u8]) {
+    if trailer.len() < TRAILER_SIZE {
+      return;
+    }
+
+    self.status = trailer[7];
+
+    let time: [u8; 7] = trailer[..7].try_into().unwrap();
+    let source_time = u64::from_le_bytes(trailer[8..TRAILER_SIZE].try_into().unwrap()) as i64;
+
+    // the clock kept running while the emulator was closed
+    self.offset = self.parse_date_time(&time) - source_time;
+  }
+}
+
+fn bcd(value: u32) -> u8 {
+  (((value / 10) << 4) | (value % 10)) as u8
+}
+
+fn from_bcd(value: u8) -> u32 {
+  (value >> 4) as u32 * 10 + (value & 0xf) as u32
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::cartridge::time_source::FixedTime;
+
+  use super::*;
+
+  // 2023-11-14 22:13:20, a tuesday
+  const NOW: i64 = 1700000000;
+
+  fn rtc() -> Rtc {
+    let mut rtc = Rtc::new();
+
+    rtc.set_time_source(Box::new(FixedTime(NOW)));
+
+    rtc
+  }
+
+  fn pins(rtc: &mut Rtc, pins: u8) -> Option<u8> {
+    let mut irq = false;
+
+    rtc.write_pins(pins, &mut irq)
+  }
+
+  /// selects the chip and sends a command, most significant bit first
+  fn start(rtc: &mut Rtc, command: u8) {
+    pins(rtc, SCK);
+    pins(rtc, SCK | CS);
+
+    for i in (0..8).rev() {
+      write_bit(rtc, command >> i & 0b1);
+    }
+  }
+
+  fn stop(rtc: &mut Rtc) {
+    pins(rtc, SCK);
+  }
+
+  fn write_bit(rtc: &mut Rtc, bit: u8) {
+    pins(rtc, CS | bit << 1);
+    pins(rtc, SCK | CS | bit << 1);
+  }
+
+  fn write_byte(rtc: &mut Rtc, byte: u8) {
+    for i in 0..8 {
+      write_bit(rtc, byte >> i & 0b1);
+    }
+  }
+
+  fn read_byte(rtc: &mut Rtc) -> u8 {
+    (0..8).fold(0, |byte, i| {
+      pins(rtc, CS);
+
+      let bit = pins(rtc, SCK | CS).unwrap() >> 1 & 0b1;
+
+      byte | bit << i
+    })
+  }
+
+  fn read_date_time(rtc: &mut Rtc) -> Vec<u8> {
+    start(rtc, 0x65);
+
+    let bytes = (0..7).map(|_| read_byte(rtc)).collect();
+
+    stop(rtc);
+
+    bytes
+  }
+
+  #[test]
+  fn reads_the_date_and_time() {
+    let mut rtc = rtc();
+
+    assert_eq!(read_date_time(&mut rtc), [0x23, 0x11, 0x14, 2, 0x22, 0x13, 0x20]);
+  }
+
+  #[test]
+  fn reads_the_hour_in_12_hour_mode() {
+    let mut rtc = rtc();
+
+    start(&mut rtc, 0x62);
+    write_byte(&mut rtc, 0);
+    stop(&mut rtc);
+
+    // pm is the top bit of the hour
+    assert_eq!(read_date_time(&mut rtc)[4], 0x80 | 0x10);
+  }
+
+  #[test]
+  fn sets_the_date_and_time() {
+    let mut rtc = rtc();
+
+    start(&mut rtc, 0x64);
+
+    for byte in [0x05, 0x06, 0x07, 3, 0x08, 0x09, 0x10] {
+      write_byte(&mut rtc, byte);
+    }
+
+    stop(&mut rtc);
+
+    assert!(rtc.changed);
+    // 2005-06-07 was a tuesday, the day of week always follows from the date
+    assert_eq!(read_date_time(&mut rtc), [0x05, 0x06, 0x07, 2, 0x08, 0x09, 0x10]);
+  }
+
+  #[test]
+  fn sets_the_time_keeping_the_date() {
+    let mut rtc = rtc();
+
+    start(&mut rtc, 0x66);
+
+    for byte in [0x01, 0x02, 0x03] {
+      write_byte(&mut rtc, byte);
+    }
+
+    stop(&mut rtc);
+
+    assert_eq!(read_date_time(&mut rtc), [0x23, 0x11, 0x14, 2, 0x01, 0x02, 0x03]);
+  }
+
+  #[test]
+  fn resets_to_2000() {
+    let mut rtc = rtc();
+
+    start(&mut rtc, 0x60);
+    stop(&mut rtc);
+
+    assert_eq!(read_date_time(&mut rtc), [0x00, 0x01, 0x01, 6, 0x00, 0x00, 0x00]);
+    assert_eq!(rtc.status, 0);
+  }
+
+  #[test]
+  fn ignores_bytes_without_the_command_code() {
+    let mut rtc = rtc();
+
+    start(&mut rtc, 0x15);
+
+    assert_eq!(rtc.command, None);
+  }
+
+  #[test]
+  fn only_raises_the_minute_irq_when_the_minute_changes() {
+    let mut rtc = rtc();
+
+    rtc.status |= STATUS_MINUTE_IRQ;
+
+    assert!(!rtc.tick());
+
+    rtc.offset += 30;
+
+    assert!(!rtc.tick());
+
+    rtc.offset += 30;
+
+    assert!(rtc.tick());
+    assert!(!rtc.tick());
+  }
+
+  #[test]
+  fn round_trips_the_trailer() {
+    let mut rtc = rtc();
+
+    rtc.offset = -1234567;
+    rtc.status = STATUS_MINUTE_IRQ;
+
+    let trailer = rtc.to_trailer();
+
+    let mut loaded = Rtc::new();
+
+    loaded.set_time_source(Box::new(FixedTime(NOW + 60)));
+    loaded.load_trailer(&trailer);
+
+    // the clock kept running for the minute in between
+    assert_eq!(loaded.now(), rtc.now() + 60);
+    assert_eq!(loaded.status, STATUS_MINUTE_IRQ);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,53 @@
+// the rumble motor on warioware: twisted! and drill dozer, switched on and off by a gpio pin.
+
+use std::{cell::Cell, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+
+const MOTOR: u8 = 0b1 << 3;
+
+pub trait RumbleOutput {
+  fn set_rumble(&mut self, active: bool);
+}
+
+impl Default for Box<dyn RumbleOutput> {
+  fn default() -> Self {
+    Box::new(NoRumble)
+  }
+}
+
+/// for frontends without any way to rumble
+pub struct NoRumble;
+
+impl RumbleOutput for NoRumble {
+  fn set_rumble(&mut self, _active: bool) {}
+}
+
+/// lets the frontend poll the motor through its own clone of the cell
+impl RumbleOutput for Rc<Cell<bool>> {
+  fn set_rumble(&mut self, active: bool) {
+    self.set(active);
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct Rumble {
+  pub active: bool
+}
+
+impl Rumble {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// called whenever the gpio pins change
+  pub fn write_pins(&mut self, pins: u8, rumble_output: &mut dyn RumbleOutput) {
+    let active = pins & MOTOR != 0;
+
+    if active != self.active {
+      self.active = active;
+
+      rumble_output.set_rumble(active);
+    }
+  }
+}
@@ -0,0 +1,289 @@
+// converts saves to and from the formats other emulators and backup devices use. raw saves are
+// what this emulator, mgba, vba-m and flash carts write: the backup memory as is, possibly followed
+// by the 16 byte rtc block. gameshark/action replay and xploder backups wrap the save with a header
+// identifying the game and a checksum, and store each 64 bit eeprom word little endian instead of
+// big endian.
+
+use super::time_source::{DateTime, HostClock, TimeSource};
+
+const SHARKPORT_MAGIC: &[u8] = b"SharkPortSave";
+const SHARKPORT_VERSION: u32 = 0xf0000;
+// the game identifier at the start of a sharkport payload
+const SHARKPORT_GAME_ID_SIZE: usize = 0x1c;
+
+const GSV_MAGIC: &[u8] = b"ADVSAVEG";
+const GSV_MAGIC_OFFSET: usize = 0xc;
+const GSV_PAYLOAD_OFFSET: usize = 0x430;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SaveFormat {
+  Raw,
+  /// a raw save with every 64 bit eeprom word stored little endian
+  RawSwappedEeprom,
+  /// gameshark, action replay (.sps) and xploder (.xpo) backups
+  SharkPort,
+  /// gameshark sp backups (.gsv)
+  GameSharkSp
+}
+
+impl SaveFormat {
+  /// guesses the format from a file's magic bytes
+  pub fn detect(contents: &[u8]) -> Self {
+    if contents.get(4..4 + SHARKPORT_MAGIC.len()) == Some(SHARKPORT_MAGIC) {
+      SaveFormat::SharkPort
+    } else if contents.get(GSV_MAGIC_OFFSET..GSV_MAGIC_OFFSET + GSV_MAGIC.len()) == Some(GSV_MAGIC) {
+      SaveFormat::GameSharkSp
+    } else {
+      SaveFormat::Raw
+    }
+  }
+
+  pub fn from_extension(extension: &str) -> Option<Self> {
+    match extension.to_ascii_lowercase().as_str() {
+      "sav" | "srm" => Some(SaveFormat::Raw),
+      "sps" | "xpo" => Some(SaveFormat::SharkPort),
+      "gsv" => Some(SaveFormat::GameSharkSp),
+      _ => None
+    }
+  }
+
+  /// the format with the given name, or the one used by files with that extension. swapped eeprom
+  /// saves use the same extension as raw ones, so they can only be picked by name
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "raw" => Some(SaveFormat::Raw),
+      "swapped-eeprom" => Some(SaveFormat::RawSwappedEeprom),
+      "sharkport" => Some(SaveFormat::SharkPort),
+      "gameshark-sp" => Some(SaveFormat::GameSharkSp),
+      name => Self::from_extension(name)
+    }
+  }
+}
+
+/// the save data in this emulator's layout, along with anything stored after it
+pub fn decode(contents: &[u8], format: SaveFormat, rom: &[u8], eeprom: bool) -> Result<Vec<u8>, String> {
+  match format {
+    SaveFormat::Raw => Ok(contents.to_vec()),
+    SaveFormat::RawSwappedEeprom => {
+      let mut data = contents.to_vec();
+
+      if eeprom {
+        reverse_words(&mut data);
+      }
+
+      Ok(data)
+    }
+    SaveFormat::SharkPort => {
+      let mut data = sharkport_payload(contents, rom)?;
+
+      if eeprom {
+        reverse_words(&mut data);
+      }
+
+      Ok(data)
+    }
+    SaveFormat::GameSharkSp => {
+      let mut data = contents.get(GSV_PAYLOAD_OFFSET..).ok_or("gsv save is too short")?.to_vec();
+
+      if eeprom {
+        reverse_words(&mut data);
+      }
+
+      Ok(data)
+    }
+  }
+}
+
+/// the save data and its trailer in the given format
+pub fn encode(data: &[u8], trailer: &[u8], format: SaveFormat, rom: &[u8], eeprom: bool) -> Result<Vec<u8>, String> {
+  match format {
+    SaveFormat::Raw => Ok([data, trailer].concat()),
+    SaveFormat::RawSwappedEeprom => {
+      let mut data = data.to_vec();
+
+      if eeprom {
+        reverse_words(&mut data);
+      }
+
+      data.extend_from_slice(trailer);
+
+      Ok(data)
+    }
+    SaveFormat::SharkPort => {
+      let mut data = data.to_vec();
+
+      if eeprom {
+        reverse_words(&mut data);
+      }
+
+      sharkport_file(&data, rom)
+    }
+    SaveFormat::GameSharkSp => Err("saving to gameshark sp backups isn't supported".to_string())
+  }
+}
+
+fn reverse_words(data: &mut [u8]) {
+  for word in data.chunks_exact_mut(8) {
+    word.reverse();
+  }
+}
+
+/// identifies the game a sharkport save belongs to, from its rom header
+fn sharkport_game_id(rom: &[u8]) -> Result<[u8; SHARKPORT_GAME_ID_SIZE], String> {
+  let header = rom.get(0xa0..0xc0).ok_or("the rom is too short to have a header")?;
+
+  let mut game_id = [0; SHARKPORT_GAME_ID_SIZE];
+
+  // title and game code
+  game_id[..0x10].copy_from_slice(&header[..0x10]);
+  // complement check and the first byte of the maker code
+  game_id[0x12] = header[0x1d];
+  game_id[0x13] = header[0x10];
+  game_id[0x14] = 1;
+
+  Ok(game_id)
+}
+
+fn sharkport_checksum(bytes: &[u8]) -> u32 {
+  // the bytes are sign extended, since the original tools used signed chars
+  bytes.iter().fold(0u32, |checksum, byte| checksum.wrapping_add(((*byte as i8) as u32) << (checksum % 24)))
+}
+
+fn sharkport_payload(contents: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+  let mut reader = Reader { contents, position: 0 };
+
+  if reader.read_block()? != SHARKPORT_MAGIC {
+    return Err("not a sharkport save".to_string());
+  }
+
+  if reader.read_u32()? != SHARKPORT_VERSION {
+    return Err("unsupported sharkport save version".to_string());
+  }
+
+  // title, date and notes
+  for _ in 0..3 {
+    reader.read_block()?;
+  }
+
+  let payload = reader.read_block()?;
+  let checksum = reader.read_u32()?;
+
+  if payload.len() < SHARKPORT_GAME_ID_SIZE {
+    return Err("sharkport save is too short".to_string());
+  }
+
+  if sharkport_checksum(payload) != checksum {
+    return Err("sharkport save checksum doesn't match".to_string());
+  }
+
+  // only the title and game code are checked, since the rest varies between devices
+  if payload[..0x10] != sharkport_game_id(rom)?[..0x10] {
+    return Err("sharkport save is for a different game".to_string());
+  }
+
+  Ok(payload[SHARKPORT_GAME_ID_SIZE..].to_vec())
+}
+
+fn sharkport_file(data: &[u8], rom: &[u8]) -> Result<Vec<u8>, String> {
+  let mut payload = sharkport_game_id(rom)?.to_vec();
+
+  payload.extend_from_slice(data);
+
+  let date_time = DateTime::from_timestamp(HostClock.now());
+
+  let date = format!(
+    "{:02}/{:02}/{} {:02}:{:02}:{:02} {}",
+    date_time.month,
+    date_time.day,
+    date_time.year,
+    (date_time.hour + 11) % 12 + 1,
+    date_time.minute,
+    date_time.second,
+    if date_time.hour < 12 { "AM" } else { "PM" }
+  );
+
+  let mut file = Vec::new();
+
+  write_block(&mut file, SHARKPORT_MAGIC);
+  file.extend_from_slice(&SHARKPORT_VERSION.to_le_bytes());
+  // the title only has room for the first 12 bytes of the header
+  write_block(&mut file, &rom[0xa0..0xac]);
+  write_block(&mut file, date.as_bytes());
+  write_block(&mut file, &[]);
+  write_block(&mut file, &payload);
+  file.extend_from_slice(&sharkport_checksum(&payload).to_le_bytes());
+
+  Ok(file)
+}
+
+fn write_block(file: &mut Vec<u8>, block: &[u8]) {
+  file.extend_from_slice(&(block.len() as u32).to_le_bytes());
+  file.extend_from_slice(block);
+}
+
+// sharkport saves are a sequence of length prefixed blocks
+struct Reader<'a> {
+  contents: &'a [u8],
+  position: usize
+}
+
+impl<'a> Reader<'a> {
+  fn read(&mut self, len: usize) -> Result<&'a [u8], String> {
+    let bytes = self.contents
+      .get(self.position..self.position.saturating_add(len))
+      .ok_or("sharkport save is cut off")?;
+
+    self.position += len;
+
+    Ok(bytes)
+  }
+
+  fn read_u32(&mut self) -> Result<u32, String> {
+    Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+  }
+
+  fn read_block(&mut self) -> Result<&'a [u8], String> {
+    let len = self.read_u32()? as usize;
+
+    self.read(len)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0xc0];
+
+    rom[0xa0..0xb0].copy_from_slice(b"TESTGAME    ATST");
+
+    rom
+  }
+
+  fn eeprom() -> Vec<u8> {
+    (0..512).map(|i| i as u8).collect()
+  }
+
+  #[test]
+  fn sharkport_eeprom_words_are_little_endian() {
+    let data = eeprom();
+
+    let file = encode(&data, &[], SaveFormat::SharkPort, &rom(), true).unwrap();
+    let payload = sharkport_payload(&file, &rom()).unwrap();
+
+    assert_eq!(payload[..8], [7, 6, 5, 4, 3, 2, 1, 0]);
+    assert_eq!(payload[8..16], [15, 14, 13, 12, 11, 10, 9, 8]);
+  }
+
+  #[test]
+  fn eeprom_round_trips() {
+    let data = eeprom();
+
+    for format in [SaveFormat::Raw, SaveFormat::RawSwappedEeprom, SaveFormat::SharkPort] {
+      let file = encode(&data, &[], format, &rom(), true).unwrap();
+
+      assert_eq!(decode(&file, format, &rom(), true).unwrap(), data, "{format:?}");
+    }
+  }
+}
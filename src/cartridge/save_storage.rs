@@ -0,0 +1,190 @@
+// where backup memory gets saved to. the emulator keeps the whole save in memory and hands it to
+// the storage every so often along with the range of bytes that changed, so storages that can
+// write partially don't have to rewrite everything.
+
+use std::{
+  cell::RefCell,
+  ffi::OsString,
+  fs,
+  io::{self, Write},
+  ops::Range,
+  path::PathBuf,
+  rc::Rc
+};
+
+pub trait SaveStorage {
+  /// the saved data, or None if nothing has been saved yet
+  fn load(&mut self) -> io::Result<Option<Vec<u8>>>;
+  fn store(&mut self, data: &[u8], dirty: Range<usize>) -> io::Result<()>;
+}
+
+/// a save file on disk. the whole file is written to a temporary file next to it which then
+/// replaces it, so a crash halfway through a write never leaves a corrupted save behind
+pub struct FileStorage {
+  path: PathBuf
+}
+
+impl FileStorage {
+  pub fn new(path: PathBuf) -> Self {
+    Self { path }
+  }
+
+  fn temp_path(&self) -> PathBuf {
+    let mut file_name = self.path.file_name().map(OsString::from).unwrap_or_default();
+
+    file_name.push(".tmp");
+
+    self.path.with_file_name(file_name)
+  }
+}
+
+impl SaveStorage for FileStorage {
+  fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(&self.path) {
+      Ok(data) => Ok(Some(data)),
+      Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(error) => Err(error)
+    }
+  }
+
+  fn store(&mut self, data: &[u8], _dirty: Range<usize>) -> io::Result<()> {
+    let temp_path = self.temp_path();
+
+    let mut file = fs::File::create(&temp_path)?;
+
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(temp_path, &self.path)
+  }
+}
+
+/// keeps the save in memory, for frontends that persist it themselves. clones share the same data
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+  pub data: Rc<RefCell<Option<Vec<u8>>>>
+}
+
+impl MemoryStorage {
+  pub fn new(data: Option<Vec<u8>>) -> Self {
+    Self {
+      data: Rc::new(RefCell::new(data))
+    }
+  }
+}
+
+impl SaveStorage for MemoryStorage {
+  fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+    Ok(self.data.borrow().clone())
+  }
+
+  fn store(&mut self, data: &[u8], dirty: Range<usize>) -> io::Result<()> {
+    let mut saved = self.data.borrow_mut();
+
+    match saved.as_mut() {
+      Some(saved) if saved.len() == data.len() => saved[dirty.clone()].copy_from_slice(&data[dirty]),
+      _ => *saved = Some(data.to_vec())
+    }
+
+    Ok(())
+  }
+}
+
+pub type SaveCallback = Box<dyn FnMut(&[u8], Range<usize>) -> io::Result<()>>;
+
+/// hands every write to a function, for frontends that save somewhere of their own
+pub struct CallbackStorage {
+  saved: Option<Vec<u8>>,
+  callback: SaveCallback
+}
+
+impl CallbackStorage {
+  pub fn new(saved: Option<Vec<u8>>, callback: SaveCallback) -> Self {
+    Self { saved, callback }
+  }
+}
+
+impl SaveStorage for CallbackStorage {
+  // kept around, since the save is loaded again if the game turns out to use a different save type
+  fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+    Ok(self.saved.clone())
+  }
+
+  fn store(&mut self, data: &[u8], dirty: Range<usize>) -> io::Result<()> {
+    (self.callback)(data, dirty)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a save file in a directory of its own, removed again afterwards
+  struct TempSave {
+    dir: PathBuf
+  }
+
+  impl TempSave {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!("gba-save-storage-{name}-{}", std::process::id()));
+
+      fs::create_dir_all(&dir).unwrap();
+
+      Self { dir }
+    }
+
+    fn storage(&self) -> FileStorage {
+      FileStorage::new(self.dir.join("game.sav"))
+    }
+  }
+
+  impl Drop for TempSave {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.dir);
+    }
+  }
+
+  #[test]
+  fn file_storage_replaces_the_whole_file() {
+    let temp_save = TempSave::new("replace");
+    let mut storage = temp_save.storage();
+
+    assert!(storage.load().unwrap().is_none());
+
+    storage.store(&[1; 8], 0..8).unwrap();
+    storage.store(&[2; 4], 1..2).unwrap();
+
+    assert_eq!(storage.load().unwrap(), Some(vec![2; 4]));
+
+    // the temporary file was renamed over the save
+    assert!(!storage.temp_path().exists());
+  }
+
+  #[test]
+  fn file_storage_leaves_the_save_alone_when_the_write_fails() {
+    let temp_save = TempSave::new("failed");
+    let mut storage = temp_save.storage();
+
+    storage.store(&[1; 8], 0..8).unwrap();
+
+    // a directory in the way of the temporary file stops the write before the save is touched
+    fs::create_dir(storage.temp_path()).unwrap();
+
+    assert!(storage.store(&[2; 8], 0..8).is_err());
+    assert_eq!(storage.load().unwrap(), Some(vec![1; 8]));
+  }
+
+  #[test]
+  fn memory_storage_only_copies_the_dirty_range() {
+    let mut storage = MemoryStorage::new(Some(vec![0; 4]));
+
+    storage.store(&[1, 2, 3, 4], 1..3).unwrap();
+
+    assert_eq!(storage.load().unwrap(), Some(vec![0, 2, 3, 0]));
+
+    // unless the size changed
+    storage.store(&[5; 2], 0..1).unwrap();
+
+    assert_eq!(storage.load().unwrap(), Some(vec![5; 2]));
+  }
+}
@@ -0,0 +1,66 @@
+// the light sensor on the boktai cartridges. the game resets a counter, then clocks it up until the
+// sensor's output pin goes high, at which point the counter has passed a value that gets lower the
+// brighter the light is.
+
+use serde::{Deserialize, Serialize};
+
+const CLOCK: u8 = 0b1;
+const RESET: u8 = 0b1 << 1;
+// active low
+const CHIP_SELECT: u8 = 0b1 << 2;
+const OUTPUT: u8 = 0b1 << 3;
+
+// how far the counter has to get for each light level, from darkest to brightest
+const LEVEL_THRESHOLDS: [u8; 11] = [0xff, 0xf4, 0xee, 0xe7, 0xdd, 0xd0, 0xc0, 0xac, 0x96, 0x7c, 0x5e];
+
+pub const MAX_SOLAR_LEVEL: u8 = LEVEL_THRESHOLDS.len() as u8 - 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct SolarSensor {
+  counter: u8,
+  last_clock: bool,
+  // the threshold latched on the last reset
+  threshold: u8,
+  pub level: u8
+}
+
+impl Default for SolarSensor {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl SolarSensor {
+  pub fn new() -> Self {
+    Self {
+      counter: 0,
+      last_clock: false,
+      threshold: LEVEL_THRESHOLDS[0],
+      level: 0
+    }
+  }
+
+  pub fn set_level(&mut self, level: u8) {
+    self.level = level.min(MAX_SOLAR_LEVEL);
+  }
+
+  /// called whenever the gpio pins change, returns the sensor's output pin
+  pub fn write_pins(&mut self, pins: u8) -> Option<u8> {
+    if pins & CHIP_SELECT != 0 {
+      return None;
+    }
+
+    let clock = pins & CLOCK != 0;
+
+    if pins & RESET != 0 {
+      self.counter = 0;
+      self.threshold = LEVEL_THRESHOLDS[self.level as usize];
+    } else if clock && !self.last_clock {
+      self.counter = self.counter.saturating_add(1);
+    }
+
+    self.last_clock = clock;
+
+    Some(if self.counter >= self.threshold { OUTPUT } else { 0 })
+  }
+}
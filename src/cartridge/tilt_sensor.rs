@@ -0,0 +1,52 @@
+// the accelerometer on yoshi topsy-turvy and koro koro puzzle. unlike the other sensors it isn't on
+// the gpio port but mapped into the sram region. writing 0x55 then 0xaa latches the current tilt,
+// which is then read back as two 12 bit values.
+
+use serde::{Deserialize, Serialize};
+
+use super::motion_input::MotionInput;
+
+pub const TILT_START: u32 = 0xe00_8000;
+pub const TILT_END: u32 = 0xe00_85ff;
+
+// what the sensor reads when the console is level, and how far it goes either way
+const CENTER: f32 = 0x3a0 as f32;
+const RANGE: f32 = 0x200 as f32;
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct TiltSensor {
+  sampling: bool,
+  x: u16,
+  y: u16
+}
+
+impl TiltSensor {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn write(&mut self, address: u32, value: u8, motion_input: &dyn MotionInput) {
+    match address & 0xff00 {
+      0x8000 => self.sampling = value == 0x55,
+      0x8100 if value == 0xaa && self.sampling => {
+        let motion = motion_input.motion();
+
+        self.sampling = false;
+        self.x = (CENTER + motion.tilt_x.clamp(-1.0, 1.0) * RANGE) as u16;
+        self.y = (CENTER + motion.tilt_y.clamp(-1.0, 1.0) * RANGE) as u16;
+      }
+      _ => ()
+    }
+  }
+
+  pub fn read(&self, address: u32) -> u8 {
+    match address & 0xff00 {
+      0x8200 => self.x as u8,
+      // bit 7 is set once a sample is ready
+      0x8300 => ((self.x >> 8) as u8 & 0xf) | 0x80,
+      0x8400 => self.y as u8,
+      0x8500 => (self.y >> 8) as u8 & 0xf,
+      _ => 0
+    }
+  }
+}
@@ -0,0 +1,135 @@
+// where the cartridge rtc gets the current time from. times are seconds since 1970-01-01 in
+// whatever timezone the console should be in, since the rtc itself has no notion of timezones.
+
+/// the current date and time, split into its calendar fields
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DateTime {
+  pub year: i64,
+  pub month: u32,
+  pub day: u32,
+  // 0 is sunday
+  pub day_of_week: u32,
+  pub hour: u32,
+  pub minute: u32,
+  pub second: u32
+}
+
+impl DateTime {
+  pub fn from_timestamp(timestamp: i64) -> Self {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400) as u32;
+
+    // converts days since 1970 to a date in the proleptic gregorian calendar, years starting in march
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    Self {
+      year,
+      month,
+      day,
+      // 1970-01-01 was a thursday
+      day_of_week: (days + 4).rem_euclid(7) as u32,
+      hour: seconds / 3600,
+      minute: seconds / 60 % 60,
+      second: seconds % 60
+    }
+  }
+
+  /// the day of week isn't used, since it always follows from the date
+  pub fn timestamp(&self) -> i64 {
+    let year = if self.month <= 2 { self.year - 1 } else { self.year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if self.month > 2 { self.month - 3 } else { self.month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + self.day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    let days = era * 146097 + day_of_era - 719468;
+
+    days * 86400 + (self.hour * 3600 + self.minute * 60 + self.second) as i64
+  }
+}
+
+pub trait TimeSource {
+  fn now(&self) -> i64;
+}
+
+impl Default for Box<dyn TimeSource> {
+  fn default() -> Self {
+    Box::new(HostClock)
+  }
+}
+
+/// the host's clock in its local timezone, the way a console set up by its owner would read.
+/// targets without a system clock, like the web, read as 1970 and should supply their own time
+/// source
+pub struct HostClock;
+
+impl TimeSource for HostClock {
+  #[cfg(not(target_arch = "wasm32"))]
+  fn now(&self) -> i64 {
+    let now = chrono::Local::now();
+
+    now.timestamp() + now.offset().local_minus_utc() as i64
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  fn now(&self) -> i64 {
+    0
+  }
+}
+
+/// always the same time, for reproducible runs
+pub struct FixedTime(pub i64);
+
+impl TimeSource for FixedTime {
+  fn now(&self) -> i64 {
+    self.0
+  }
+}
+
+/// the host's clock shifted by a number of seconds, like for a timezone
+pub struct OffsetClock(pub i64);
+
+impl TimeSource for OffsetClock {
+  fn now(&self) -> i64 {
+    HostClock.now() + self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::DateTime;
+
+  fn date_time(year: i64, month: u32, day: u32, day_of_week: u32, hour: u32, minute: u32, second: u32) -> DateTime {
+    DateTime { year, month, day, day_of_week, hour, minute, second }
+  }
+
+  #[test]
+  fn converts_timestamps_to_dates() {
+    assert_eq!(DateTime::from_timestamp(0), date_time(1970, 1, 1, 4, 0, 0, 0));
+    assert_eq!(DateTime::from_timestamp(-1), date_time(1969, 12, 31, 3, 23, 59, 59));
+    assert_eq!(DateTime::from_timestamp(951782400), date_time(2000, 2, 29, 2, 0, 0, 0));
+    assert_eq!(DateTime::from_timestamp(1700000000), date_time(2023, 11, 14, 2, 22, 13, 20));
+    assert_eq!(DateTime::from_timestamp(4102444800), date_time(2100, 1, 1, 5, 0, 0, 0));
+    assert_eq!(DateTime::from_timestamp(-2208988800), date_time(1900, 1, 1, 1, 0, 0, 0));
+  }
+
+  #[test]
+  fn round_trips_timestamps() {
+    // every day from 1900 to 2100, at an odd time of day
+    for day in -25567..47482 {
+      let timestamp = day * 86400 + 45296;
+
+      assert_eq!(DateTime::from_timestamp(timestamp).timestamp(), timestamp);
+    }
+  }
+}
\ No newline at end of file
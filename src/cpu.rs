@@ -1,29 +1,35 @@
 // general comments
 
-use std::sync::Arc;
+use std::{collections::HashMap, io, sync::Arc};
 
 // per the ARM7tdmi manual,
 // in ARM state, bits [1:0] of
 // R15 are zero and bits [31:2] contain the PC. In THUMB state,
 // bit [0] is zero and bits [31:1] contain the PC.
-use dma::dma_channel::{registers::dma_control_register::DmaControlRegister, DmaParams};
 use ringbuf::{storage::Heap, wrap::caching::Caching, SharedRb};
 use serde::{Deserialize, Serialize};
 
 use crate::{
   apu::APU,
   cartridge::{
+    game_db::{GameDb, GameOverrides},
+    gpio::Gpio,
+    time_source::TimeSource,
+    BackupAccess,
     BackupMedia,
-    Cartridge
+    Cartridge,
+    SaveTypeDetection
   },
   gpu::{
+    CYCLES_PER_FRAME,
     GPU,
     HDRAW_CYCLES
   },
   scheduler::{
     EventType,
     Scheduler
-  }
+  },
+  sio::{transport::LinkTransport, SerialIO}
 };
 
 use self::{
@@ -32,9 +38,14 @@ use self::{
     interrupt_enable_register::InterruptEnableRegister,
     interrupt_request_register::InterruptRequestRegister,
     key_input_register::KeyInputRegister,
+    key_interrupt_control_register::KeyInterruptControlRegister,
     waitstate_control_register::WaitstateControlRegister
   },
+  block_cache::{BlockCache, DecodedInstruction},
   dma::dma_channels::DmaChannels,
+  idle_loop::IdleLoopDetector,
+  prefetch_buffer::PrefetchBuffer,
+  hle_bios::{generate_hle_bios, BIOS_SIZE},
   timers::Timers
 };
 
@@ -46,6 +57,10 @@ pub mod rotations_shifts;
 pub mod registers;
 pub mod dma;
 pub mod timers;
+pub mod hle_bios;
+pub mod prefetch_buffer;
+pub mod block_cache;
+pub mod idle_loop;
 
 pub const PC_REGISTER: usize = 15;
 pub const LR_REGISTER: usize = 14;
@@ -56,6 +71,20 @@ pub const IRQ_VECTOR: u32 = 0x18;
 
 pub const CPU_CLOCK_SPEED: u32 = 2u32.pow(24);
 
+// how often the save is checked for writes to flush, a quarter of a second
+const SAVE_FLUSH_CYCLES: usize = CPU_CLOCK_SPEED as usize / 4;
+
+// the events driven by the system clock, which stop along with it in stop mode
+const STOPPED_EVENTS: [EventType; 7] = [
+  EventType::Hblank,
+  EventType::Hdraw,
+  EventType::Timer(0),
+  EventType::Timer(1),
+  EventType::Timer(2),
+  EventType::Timer(3),
+  EventType::SampleAudio
+];
+
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum MemoryAccess {
   Sequential,
@@ -94,20 +123,37 @@ pub struct CPU {
   #[serde(skip_serializing)]
   #[serde(skip_deserializing)]
   bios: Vec<u8>,
+  hle_bios: bool,
+  hle_intr_wait: bool,
+  last_bios_opcode: u32,
   board_wram: Box<[u8]>,
   chip_wram: Box<[u8]>,
   pub cartridge: Cartridge,
   next_fetch: MemoryAccess,
   cycle_luts: CycleLookupTables,
+  prefetch: PrefetchBuffer,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  block_cache: BlockCache,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  idle_loop: IdleLoopDetector,
   pub gpu: GPU,
   interrupt_enable: InterruptEnableRegister,
   pub interrupt_request: InterruptRequestRegister,
   waitcnt: WaitstateControlRegister,
   is_halted: bool,
+  is_stopped: bool,
+  // the cycle stop mode was entered at
+  stopped_at: usize,
   pub dma: DmaChannels,
   pub key_input: KeyInputRegister,
+  key_interrupt_control: KeyInterruptControlRegister,
+  // whether the keys matched KEYCNT's condition last step, since the irq fires when they start to
+  key_irq_condition: bool,
   pub timers: Timers,
   pub apu: APU,
+  pub sio: SerialIO,
   pub scheduler: Scheduler,
   pub cycles: usize,
   pub paused: bool
@@ -191,10 +237,22 @@ impl CPU {
       pipeline: [0; 2],
       cartridge: Cartridge {
         rom: Vec::new(),
+        header: None,
+        game_db: GameDb::new(),
+        overrides: GameOverrides::default(),
         file_path: None,
-        backup: BackupMedia::Undetected
+        backup: BackupMedia::Undetected,
+        gpio: Gpio::new(),
+        tilt_sensor: None,
+        save_storage: None,
+        save_type_detection: SaveTypeDetection::Known,
+        backup_accesses: HashMap::new(),
+        flash_unlock: 0
       },
-      bios: Vec::new(),
+      bios: generate_hle_bios(),
+      hle_bios: true,
+      hle_intr_wait: false,
+      last_bios_opcode: 0,
       next_fetch: MemoryAccess::NonSequential,
       board_wram: vec![0; 256 * 1024].into_boxed_slice(),
       chip_wram: vec![0; 32 * 1024].into_boxed_slice(),
@@ -202,14 +260,22 @@ impl CPU {
       gpu: GPU::new(),
       interrupt_request: InterruptRequestRegister::from_bits_retain(0),
       cycle_luts: CycleLookupTables::new(),
+      prefetch: PrefetchBuffer::new(),
+      block_cache: BlockCache::new(),
+      idle_loop: IdleLoopDetector::new(),
       interrupt_master_enable: false,
       interrupt_enable: InterruptEnableRegister::from_bits_retain(0),
       dma: DmaChannels::new(),
       is_halted: false,
+      is_stopped: false,
+      stopped_at: 0,
       key_input: KeyInputRegister::from_bits_retain(0x3ff),
+      key_interrupt_control: KeyInterruptControlRegister::from_bits_retain(0),
+      key_irq_condition: false,
       timers: Timers::new(),
       waitcnt: WaitstateControlRegister::new(),
       apu: APU::new(producer),
+      sio: SerialIO::new(),
       scheduler: Scheduler::new(),
       cycles: 0,
       paused: false
@@ -218,6 +284,9 @@ impl CPU {
     cpu.populate_thumb_lut();
     cpu.populate_arm_lut();
 
+    cpu.cycle_luts.init();
+    cpu.cycle_luts.update_tables(&cpu.waitcnt);
+
     cpu.apu.schedule_samples(&mut cpu.scheduler);
     cpu.scheduler.schedule(EventType::Hdraw, HDRAW_CYCLES as usize);
 
@@ -289,6 +358,9 @@ impl CPU {
     self.pc = 0x0800_0000;
     self.cpsr = PSRRegister::from_bits_retain(0x5f);
 
+    // value left in the bios latch by the real bios right before jumping to the cartridge
+    self.last_bios_opcode = 0xe129_f000;
+
     for bg_prop in &mut self.gpu.bg_props {
       bg_prop.dx = 0x100;
       bg_prop.dmx = 0;
@@ -298,13 +370,24 @@ impl CPU {
   }
 
   pub fn load_game(&mut self, rom: Vec<u8>, file_path: Option<String>) {
-    self.cartridge.rom = rom;
+    self.cartridge.set_rom(rom);
     self.cartridge.file_path = file_path;
-    self.cartridge.detect_backup_media();
+    self.cartridge.detect_hardware();
+    self.block_cache.clear();
+    self.idle_loop.apply_overrides(self.cartridge.overrides.idle_loop.as_ref());
+
+    if self.cartridge.gpio.rtc.is_some() {
+      self.scheduler.schedule(EventType::RtcTick, CPU_CLOCK_SPEED as usize);
+    } else {
+      self.scheduler.remove(EventType::RtcTick);
+    }
+
+    self.scheduler.schedule(EventType::SaveFlush, SAVE_FLUSH_CYCLES);
   }
 
   pub fn reload_game(&mut self, rom: Vec<u8>) {
-    self.cartridge.rom = rom;
+    self.cartridge.set_rom(rom);
+    self.block_cache.clear();
   }
 
   pub fn execute_thumb(&mut self, instr: u16) -> Option<MemoryAccess> {
@@ -322,23 +405,35 @@ impl CPU {
   fn step_arm(&mut self) {
     let pc = self.pc & !(0b11);
 
-    let next_instruction = self.load_32(pc, self.next_fetch);
+    let next_instruction = self.fetch_32(pc, self.next_fetch);
+
+    self.advance_arm_pipeline(pc, next_instruction, None);
+  }
 
+  fn advance_arm_pipeline(&mut self, pc: u32, next_instruction: u32, decoded: Option<&DecodedInstruction>) {
     let instruction = self.pipeline[0];
     self.pipeline[0] = self.pipeline[1];
     self.pipeline[1] = next_instruction;
 
+    self.latch_bios_opcode(pc);
+
     let condition = (instruction >> 28) as u8;
 
     // println!("attempting to execute instruction {:032b} at address {:X}", instruction, pc.wrapping_sub(8));
 
     if self.arm_condition_met(condition) {
-      if let Some(access) = self.execute_arm(instruction) {
+      let access = match decoded {
+        Some(decoded) => decoded.execute(self),
+        None => self.execute_arm(instruction)
+      };
+
+      if let Some(access) = access {
         self.next_fetch = access;
       }
     } else {
+      // a skipped instruction only takes the 1S cycle of its fetch
       self.pc = self.pc.wrapping_add(4);
-      self.next_fetch = MemoryAccess::NonSequential;
+      self.next_fetch = MemoryAccess::Sequential;
     }
   }
 
@@ -372,142 +467,171 @@ impl CPU {
     }
   }
 
-  fn handle_dma(&mut self) -> Vec<bool> {
-    let mut trigger_irqs = Vec::new();
-    for i in 0..self.dma.channels.len() {
-      if self.dma.channels[i].pending {
-        let mut dma_params = self.get_params(i);
-        self.do_transfer(&mut dma_params, i);
-        trigger_irqs.push(dma_params.should_trigger_irq);
-        self.dma.channels[i].pending = false;
-      } else {
-        trigger_irqs.push(false);
-      }
+  /// transfers a single unit for the highest priority pending channel, so a channel triggered
+  /// in the middle of another transfer can take over the bus
+  fn handle_dma(&mut self) {
+    let Some(id) = self.dma.next_channel() else {
+      return;
+    };
+
+    if !self.dma.channels[id].active {
+      self.start_dma(id);
     }
 
-    trigger_irqs
-  }
+    let channel = &self.dma.channels[id];
 
-  fn do_transfer(&mut self, params: &mut DmaParams, channel_id: usize) {
-    let mut access = MemoryAccess::NonSequential;
+    let source = channel.internal_source_address;
+    let destination = channel.internal_destination_address;
+    let source_adjust = channel.source_adjust();
+    let destination_adjust = channel.destination_adjust();
+    let word_size = channel.word_size();
 
-    if params.fifo_mode {
-      for _ in 0..4 {
-        let value = self.load_32(params.internal_source_address & !(0b11), access);
-        self.store_32(params.internal_destination_address & !(0b11), value, access);
-        access = MemoryAccess::Sequential;
-        params.internal_source_address += 4;
-      }
-    } else if params.word_size == 4 {
-      for _ in 0..params.count {
-        let word = self.load_32(params.internal_source_address & !(0b11), access);
-        self.store_32(params.internal_destination_address & !(0b11), word, access);
-        access = MemoryAccess::Sequential;
-        params.internal_source_address = (params.internal_source_address as i32).wrapping_add(params.source_adjust) as u32;
-        params.internal_destination_address = (params.internal_destination_address as i32).wrapping_add(params.destination_adjust) as u32;
-      }
+    let access = if self.dma.last_channel == Some(id) {
+      MemoryAccess::Sequential
     } else {
-      for _ in 0..params.count {
-        let half_word = self.load_16(params.internal_source_address & !(0b1), access);
-        self.store_16(params.internal_destination_address & !(0b1), half_word, access);
-        access = MemoryAccess::Sequential;
-        params.internal_source_address = (params.internal_source_address as i32).wrapping_add(params.source_adjust) as u32;
-        params.internal_destination_address = (params.internal_destination_address as i32).wrapping_add(params.destination_adjust) as u32;
-      }
-    }
+      MemoryAccess::NonSequential
+    };
 
-    self.dma.channels[channel_id].internal_source_address = params.internal_source_address;
-    self.dma.channels[channel_id].internal_destination_address = params.internal_destination_address;
-  }
+    self.dma.last_channel = Some(id);
 
-  pub fn get_params(&mut self, channel_id: usize) -> DmaParams {
-    let channel = &mut self.dma.channels[channel_id];
-    let mut should_trigger_irq = false;
+    if word_size == 4 {
+      let value = self.dma_read(id, source & !(0b11), access, word_size);
 
-    let word_size: u32 = if channel.dma_control.contains(DmaControlRegister::DMA_TRANSFER_TYPE) {
-      4 // 32 bit
+      self.store_32(destination & !(0b11), value, access);
     } else {
-      2 // 16 bit
-    };
+      let value = self.dma_read(id, source & !(0b1), access, word_size);
 
-    let count = match channel.internal_count {
-      0 => if channel.id == 3 { 0x1_0000 } else { 0x4000 },
-      _ => channel.internal_count as u32
-    };
+      self.store_16(destination & !(0b1), value as u16, access);
+    }
 
-    let destination_adjust = match channel.dma_control.dest_addr_control() {
-      0 | 3 => word_size as i32,
-      1 => -(word_size as i32),
-      2 => 0,
-      _ => unreachable!("can't be")
-    };
+    let channel = &mut self.dma.channels[id];
 
-    let source_adjust = match channel.dma_control.source_addr_control() {
-      0 => word_size as i32,
-      1 => -(word_size as i32),
-      2 => 0,
-      _ => panic!("illegal value specified for source address control")
-    };
+    channel.internal_source_address = (source as i32).wrapping_add(source_adjust) as u32;
+    channel.internal_destination_address = (destination as i32).wrapping_add(destination_adjust) as u32;
+    channel.units_left -= 1;
+
+    if channel.units_left == 0 {
+      if channel.finish_transfer() {
+        self.interrupt_request.request_dma(id);
+      }
+
+      self.dma.last_channel = None;
+
+      // the cpu gave up the bus, so its next fetch can't be sequential
+      self.next_fetch = MemoryAccess::NonSequential;
+    }
+  }
+
+  fn start_dma(&mut self, id: usize) {
+    let channel = &mut self.dma.channels[id];
+
+    channel.active = true;
+    channel.units_left = channel.unit_count();
+
+    if id == 3 && channel.word_size() == 2 {
+      if (0xd00_0000..=0xdff_ffff).contains(&channel.internal_destination_address) {
+        self.cartridge.watch_backup_access(BackupAccess::EepromDma);
+      }
 
-    if channel.id == 3 && word_size == 2 {
       if let BackupMedia::Eeprom(eeprom_controller) = &mut self.cartridge.backup {
         eeprom_controller.handle_dma(channel.internal_destination_address, channel.internal_source_address, channel.internal_count.into());
       }
     }
 
-    if channel.dma_control.contains(DmaControlRegister::IRQ_ENABLE) {
-      should_trigger_irq = true;
-    }
+    // the dma takes 2 internal cycles to start up
+    self.add_cycles(2);
+  }
+
+  fn dma_read(&mut self, id: usize, address: u32, access: MemoryAccess, word_size: u32) -> u32 {
+    // the bios and unmapped memory can't be read by dma, and dma 0 can't read the gamepak either.
+    // those reads return the last value any channel read instead
+    let invalid_source = !(0x200_0000..0x1000_0000).contains(&address) || (id == 0 && address >= 0x800_0000);
 
-    if channel.dma_control.contains(DmaControlRegister::DMA_REPEAT) {
-      if channel.dma_control.dest_addr_control() == 3 {
-        channel.internal_destination_address = channel.destination_address;
+    if word_size == 4 {
+      if invalid_source {
+        self.update_cycles(address, access, MemoryWidth::Width32);
+      } else {
+        self.dma.open_bus = self.load_32(address, access);
       }
+
+      self.dma.open_bus
     } else {
-      channel.running = false;
-      channel.dma_control.remove(DmaControlRegister::DMA_ENABLE);
-    }
+      if invalid_source {
+        self.update_cycles(address, access, MemoryWidth::Width16);
+      } else {
+        let value = self.load_16(address, access) as u32;
+
+        self.dma.open_bus = value | value << 16;
+      }
 
-    DmaParams {
-      source_adjust,
-      destination_adjust,
-      count,
-      internal_source_address: channel.internal_source_address,
-      internal_destination_address: channel.internal_destination_address,
-      word_size,
-      fifo_mode: channel.fifo_mode,
-      should_trigger_irq
+      (self.dma.open_bus >> ((address & 0b10) * 8)) & 0xffff
     }
   }
 
   pub fn step(&mut self) {
-    let cycles = self.scheduler.get_cycles_to_next_event();
+    // key_input is updated directly by the frontends, so check for the keypad irq here
+    let key_irq_condition = self.key_interrupt_control.irq_triggered(self.key_input);
+
+    if key_irq_condition && !self.key_irq_condition {
+      self.interrupt_request.insert(InterruptRequestRegister::KEYPAD);
+    }
+
+    self.key_irq_condition = key_irq_condition;
+
+    if self.is_stopped {
+      self.step_stopped();
+
+      return;
+    }
+
+    let mut cycles = self.scheduler.get_cycles_to_next_event();
 
     while self.cycles < cycles {
        // first check interrupts
       self.check_interrupts();
       if self.dma.has_pending_transfers() {
-        let should_trigger_irqs = self.handle_dma();
-        for i in 0..4 {
-          if should_trigger_irqs[i] {
-            self.interrupt_request.request_dma(i);
+        self.handle_dma();
+      } else if !self.is_halted {
+        let ran_block = self.block_cache.enabled && self.step_block();
+
+        if !ran_block {
+          if self.cpsr.contains(PSRRegister::STATE_BIT) {
+            self.step_thumb();
+          } else {
+            self.step_arm();
           }
         }
-      } else if !self.is_halted {
-        if self.cpsr.contains(PSRRegister::STATE_BIT) {
-          self.step_thumb();
-        } else {
-          self.step_arm();
+
+        if self.is_stopped {
+          self.scheduler.update_cycles(self.cycles);
+          self.stop_clock();
+
+          return;
+        }
+
+        if self.idle_loop.detected {
+          // nothing changes until the next event, so skip ahead the same way halt does
+          self.idle_loop.detected = false;
+          self.cycles = cycles;
+
+          break;
         }
       } else {
         self.cycles = cycles;
 
         break;
       }
+
+      // a register write may have scheduled an event earlier than the one being waited on
+      cycles = self.scheduler.get_cycles_to_next_event();
     }
 
     self.scheduler.update_cycles(cycles);
 
+    self.handle_events();
+  }
+
+  fn handle_events(&mut self) {
     while let Some((event_type, cycles_left)) = self.scheduler.get_next_event() {
       match event_type {
         EventType::Hdraw => self.gpu.handle_hdraw(&mut self.scheduler, &mut self.interrupt_request, &mut self.dma),
@@ -518,29 +642,124 @@ impl CPU {
           self.timers.t[timer_id].handle_overflow(&mut self.scheduler, &mut self.interrupt_request, cycles_left);
           self.timers.handle_overflow(timer_id, dma, &mut self.scheduler, &mut self.apu, &mut self.interrupt_request, cycles_left);
         }
-        EventType::SampleAudio => self.apu.sample_audio(&mut self.scheduler)
+        EventType::SampleAudio => self.apu.sample_audio(&mut self.scheduler),
+        EventType::Serial => self.sio.handle_event(&mut self.scheduler, &mut self.interrupt_request),
+        EventType::LinkSync => self.sio.handle_link_sync(&mut self.scheduler, &mut self.interrupt_request, cycles_left),
+        EventType::RtcTick => {
+          if self.cartridge.tick_rtc() {
+            self.interrupt_request.insert(InterruptRequestRegister::GAMEPACK);
+          }
+
+          self.scheduler.schedule(EventType::RtcTick, CPU_CLOCK_SPEED as usize - cycles_left);
+        }
+        EventType::SaveFlush => {
+          self.cartridge.flush_backup_if_idle();
+
+          self.scheduler.schedule(EventType::SaveFlush, SAVE_FLUSH_CYCLES - cycles_left);
+        }
       }
     }
+  }
+
+  /// in stop mode the video, sound and timers freeze, but the serial port, the cartridge and the
+  /// keypad keep going and can wake the cpu back up. each step advances to the next of their
+  /// events like it does when running, with a frame still ending at the usual rate, so the rtc
+  /// and any link keep pace with the host
+  fn step_stopped(&mut self) {
+    let wakeup_irqs = InterruptRequestRegister::KEYPAD | InterruptRequestRegister::SERIAL_COMM | InterruptRequestRegister::GAMEPACK;
+
+    if self.interrupt_enable.bits() & self.interrupt_request.bits() & wakeup_irqs.bits() != 0 {
+      self.start_clock();
+
+      return;
+    }
+
+    // frames keep ending at the usual rate, counted from when the clock stopped
+    let frame_cycles = CYCLES_PER_FRAME as usize;
+    let frame_end = self.stopped_at + ((self.cycles - self.stopped_at) / frame_cycles + 1) * frame_cycles;
+
+    let next_event = match self.scheduler.get_cycles_to_next_event() {
+      0 => frame_end,
+      cycles => cycles.min(frame_end)
+    };
+
+    self.cycles = self.cycles.max(next_event);
+    self.scheduler.update_cycles(self.cycles);
+
+    self.handle_events();
+
+    if self.cycles >= frame_end {
+      // nothing is drawn while stopped, but the frontends still need to present a frame
+      self.gpu.frame_finished = true;
+    }
+  }
+
+  fn stop_clock(&mut self) {
+    self.stopped_at = self.cycles;
+
+    for event_type in STOPPED_EVENTS {
+      self.scheduler.pause(event_type);
+    }
+  }
+
+  fn start_clock(&mut self) {
+    self.is_stopped = false;
+
+    for event_type in STOPPED_EVENTS {
+      self.scheduler.resume(event_type);
+    }
+
+    self.timers.delay(self.cycles - self.stopped_at);
+  }
+
+  /// moves every cycle count back to near zero so they don't overflow on 32 bit targets
+  pub fn rebase_cycles(&mut self) {
+    let to_subtract = self.scheduler.rebase_cycles();
 
+    self.cycles -= to_subtract;
+    self.stopped_at = self.stopped_at.saturating_sub(to_subtract);
+    self.timers.rebase_cycles(to_subtract);
 
+    if let BackupMedia::Flash(flash) = &mut self.cartridge.backup {
+      flash.rebase_cycles(to_subtract);
+    }
   }
 
   fn step_thumb(&mut self) {
     let pc = self.pc & !(0b1);
 
-    let next_instruction = self.load_16(pc, self.next_fetch) as u32;
+    let next_instruction = self.fetch_16(pc, self.next_fetch) as u32;
+
+    self.advance_thumb_pipeline(pc, next_instruction, None);
+  }
 
+  fn advance_thumb_pipeline(&mut self, pc: u32, next_instruction: u32, decoded: Option<&DecodedInstruction>) {
     let instruction = self.pipeline[0];
     self.pipeline[0] = self.pipeline[1];
     self.pipeline[1] = next_instruction;
 
+    self.latch_bios_opcode(pc);
+
     // println!("executing instruction {:016b} at address {:X}", instruction, pc.wrapping_sub(4));
 
-    if let Some(fetch) = self.execute_thumb(instruction as u16) {
+    let fetch = match decoded {
+      Some(decoded) => decoded.execute(self),
+      None => self.execute_thumb(instruction as u16)
+    };
+
+    if let Some(fetch) = fetch {
       self.next_fetch = fetch;
     }
   }
 
+  fn latch_bios_opcode(&mut self, pc: u32) {
+    if pc < BIOS_SIZE as u32 {
+      let address = (pc & !(0b11)) as usize;
+
+      self.last_bios_opcode = u32::from_le_bytes(self.bios[address..address + 4].try_into().unwrap());
+    }
+  }
+
   fn get_register(&self, r: usize) -> u32 {
     if r == PC_REGISTER {
       self.pc
@@ -549,39 +768,100 @@ impl CPU {
     }
   }
 
+  fn fetch_32(&mut self, address: u32, access: MemoryAccess) -> u32 {
+    self.update_fetch_cycles(address, access, MemoryWidth::Width32);
+    self.mem_read_32(address)
+  }
+
+  fn fetch_16(&mut self, address: u32, access: MemoryAccess) -> u16 {
+    self.update_fetch_cycles(address, access, MemoryWidth::Width16);
+    self.mem_read_16(address)
+  }
+
   pub fn load_32(&mut self, address: u32, access: MemoryAccess) -> u32 {
     self.update_cycles(address, access, MemoryWidth::Width32);
+    self.check_idle_loop_read(address);
     self.mem_read_32(address)
   }
 
   pub fn load_16(&mut self, address: u32, access: MemoryAccess) -> u16 {
     self.update_cycles(address, access, MemoryWidth::Width16);
+    self.check_idle_loop_read(address);
     self.mem_read_16(address)
   }
 
   pub fn load_8(&mut self, address: u32, access: MemoryAccess) -> u8 {
     self.update_cycles(address, access, MemoryWidth::Width8);
+    self.check_idle_loop_read(address);
     self.mem_read_8(address)
   }
 
   pub fn store_8(&mut self, address: u32, value: u8, access: MemoryAccess) {
     self.update_cycles(address, access, MemoryWidth::Width8);
     self.mem_write_8(address, value);
+
+    self.idle_loop.memory_written = true;
   }
 
   pub fn store_16(&mut self, address: u32, value: u16, access: MemoryAccess) {
-    self.update_cycles(address, access, MemoryWidth::Width8);
+    self.update_cycles(address, access, MemoryWidth::Width16);
     self.mem_write_16(address, value);
+
+    self.idle_loop.memory_written = true;
   }
 
   pub fn store_32(&mut self, address: u32, value: u32, access: MemoryAccess) {
-    self.update_cycles(address, access, MemoryWidth::Width8);
+    self.update_cycles(address, access, MemoryWidth::Width32);
     self.mem_write_32(address, value);
+
+    self.idle_loop.memory_written = true;
+  }
+
+  fn update_fetch_cycles(&mut self, address: u32, access: MemoryAccess, width: MemoryWidth) {
+    if !self.prefetch.enabled || !PrefetchBuffer::is_gamepak_rom(address) {
+      self.update_cycles(address, access, width);
+      return;
+    }
+
+    if let Some(cycles) = self.prefetch.read(address, &self.cycle_luts) {
+      // an opcode already in the buffer only takes a single cycle
+      if cycles == 0 {
+        self.add_cycles(1);
+      } else {
+        self.cycles += cycles as usize;
+      }
+
+      if matches!(width, MemoryWidth::Width32) {
+        let cycles = self.prefetch.read(address + 2, &self.cycle_luts).unwrap_or(0);
+
+        self.cycles += cycles as usize;
+      }
+
+      return;
+    }
+
+    let size = if matches!(width, MemoryWidth::Width32) { 4 } else { 2 };
+
+    self.cycles += self.access_cycles(address, access, width) as usize;
+    self.prefetch.restart(address + size, &self.cycle_luts);
   }
 
   fn update_cycles(&mut self, address: u32,  access: MemoryAccess, width: MemoryWidth) {
+    let cycles = self.access_cycles(address, access, width);
+
+    if (0x800_0000..=0xfff_ffff).contains(&address) {
+      // the gamepak bus is busy with this access, so the prefetch unit can't run
+      self.prefetch.stop();
+      self.cycles += cycles as usize;
+    } else {
+      self.add_cycles(cycles);
+    }
+  }
+
+  fn access_cycles(&self, address: u32, access: MemoryAccess, width: MemoryWidth) -> u32 {
     let page = ((address >> 24) & 0xf) as usize;
-    let cycles = match width {
+
+    match width {
       MemoryWidth::Width8 | MemoryWidth::Width16 => match access {
         MemoryAccess::NonSequential => self.cycle_luts.n_cycles_16[page],
         MemoryAccess::Sequential => self.cycle_luts.s_cycles_16[page]
@@ -590,33 +870,32 @@ impl CPU {
         MemoryAccess::NonSequential => self.cycle_luts.n_cycles_32[page],
         MemoryAccess::Sequential => self.cycle_luts.s_cycles_32[page],
       }
-    };
-
-    self.add_cycles(cycles);
+    }
   }
 
   fn add_cycles(&mut self, cycles: u32) {
     self.cycles += cycles as usize;
+    self.prefetch.step(cycles, &self.cycle_luts);
   }
 
   pub fn reload_pipeline16(&mut self) {
     self.pc = self.pc & !(0b1);
-    self.pipeline[0] = self.load_16(self.pc, MemoryAccess::NonSequential) as u32;
+    self.pipeline[0] = self.fetch_16(self.pc, MemoryAccess::NonSequential) as u32;
 
     self.pc = self.pc.wrapping_add(2);
 
-    self.pipeline[1] = self.load_16(self.pc, MemoryAccess::Sequential) as u32;
+    self.pipeline[1] = self.fetch_16(self.pc, MemoryAccess::Sequential) as u32;
 
     self.pc = self.pc.wrapping_add(2);
   }
 
   pub fn reload_pipeline32(&mut self) {
     self.pc = self.pc & !(0b11);
-    self.pipeline[0] = self.load_32(self.pc, MemoryAccess::NonSequential);
+    self.pipeline[0] = self.fetch_32(self.pc, MemoryAccess::NonSequential);
 
     self.pc = self.pc.wrapping_add(4);
 
-    self.pipeline[1] = self.load_32(self.pc, MemoryAccess::Sequential);
+    self.pipeline[1] = self.fetch_32(self.pc, MemoryAccess::Sequential);
 
     self.pc = self.pc.wrapping_add(4);
   }
@@ -641,8 +920,15 @@ impl CPU {
     self.pc + 4 - (2 * word_size)
   }
 
-  pub fn software_interrupt(&mut self) {
+  pub fn software_interrupt(&mut self, comment: u8) {
     let lr = if self.cpsr.contains(PSRRegister::STATE_BIT) { self.pc - 2 } else { self.pc - 4 };
+
+    if self.hle_bios {
+      self.handle_swi(comment, lr);
+
+      return;
+    }
+
     self.interrupt(OperatingMode::Supervisor, SOFTWARE_INTERRUPT_VECTOR, lr);
     self.cpsr.insert(PSRRegister::IRQ_DISABLE);
   }
@@ -724,15 +1010,32 @@ impl CPU {
     }
   }
 
-  pub fn load_bios(&mut self, bytes: Vec<u8>) {
+  /// keeps the current bios if the image isn't a whole 16k bios
+  pub fn load_bios(&mut self, bytes: Vec<u8>) -> Result<(), String> {
+    if bytes.len() != BIOS_SIZE {
+      return Err(format!("a bios is {} bytes, not {}", BIOS_SIZE, bytes.len()));
+    }
+
     self.bios = bytes;
+    self.hle_bios = false;
+
+    Ok(())
+  }
+
+  pub fn use_hle_bios(&mut self) {
+    self.bios = generate_hle_bios();
+    self.hle_bios = true;
   }
 
   fn clear_interrupts(&mut self, value: u16) {
     self.interrupt_request = InterruptRequestRegister::from_bits_retain(self.interrupt_request.bits() & !value);
   }
 
-  pub fn get_multiplier_cycles(&self, operand: u32) -> u32 {
+  pub fn get_multiplier_cycles(&self, operand: u32, signed: bool) -> u32 {
+    // the multiplier terminates early once the remaining bits of the operand are all zeroes,
+    // or all ones for signed multiplies
+    let operand = if signed && (operand as i32) < 0 { !operand } else { operand };
+
     if operand & 0xff == operand {
       1
     } else if operand & 0xffff == operand {
@@ -745,15 +1048,237 @@ impl CPU {
   }
 
   pub fn create_save_state(&mut self) -> Vec<u8> {
-    self.scheduler.create_save_state();
-
     bincode::serialize(self).unwrap()
   }
 
   pub fn load_save_state(&mut self, buf: &[u8]) {
+    // the bios isn't part of the save state, so carry over whichever one is loaded
+    let bios = std::mem::take(&mut self.bios);
+    let hle_bios = self.hle_bios;
+    let block_cache_enabled = self.block_cache.enabled;
+    let idle_loop = std::mem::take(&mut self.idle_loop);
+    let transport = self.sio.take_transport();
+    let game_db = std::mem::take(&mut self.cartridge.game_db);
+    let gpio = std::mem::take(&mut self.cartridge.gpio);
+    let save_storage = self.cartridge.backup.backup_file_mut().and_then(|backup_file| backup_file.storage.take());
+    // kept for a save type that's still being detected
+    let pending_save_storage = self.cartridge.save_storage.take();
+
     *self = bincode::deserialize(&buf).unwrap();
 
-    self.scheduler.load_save_state();
+    self.bios = bios;
+    self.hle_bios = hle_bios;
+    self.block_cache.enabled = block_cache_enabled;
+    self.idle_loop = idle_loop;
+    self.idle_loop.reset();
+    self.sio.set_transport(transport, &mut self.scheduler);
+    self.cartridge.game_db = game_db;
+
+    self.cartridge.gpio.carry_over(gpio);
+
+    if let Some(backup_file) = self.cartridge.backup.backup_file_mut() {
+      backup_file.storage = save_storage;
+    }
+
+    self.cartridge.save_storage = pending_save_storage;
+  }
+
+  /// where the cartridge's rtc gets the time from, the host clock by default
+  pub fn set_rtc_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+    if let Some(rtc) = &mut self.cartridge.gpio.rtc {
+      rtc.set_time_source(time_source);
+    }
+  }
+
+  pub fn set_link_transport(&mut self, transport: Box<dyn LinkTransport>) {
+    self.sio.set_transport(transport, &mut self.scheduler);
+  }
+
+  /// why the link cable was disconnected, if it was since the last call
+  pub fn take_link_error(&mut self) -> Option<io::Error> {
+    self.sio.take_link_error()
+  }
+
+  pub fn is_stopped(&self) -> bool {
+    self.is_stopped
+  }
+
+}
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  // a game that stops the clock right away, with the keypad able to wake it up
+  fn stopped_cpu() -> CPU {
+    let mut cpu = CPU::new(HeapRb::<f32>::new(1024).split().0);
+
+    let code = [
+      0xe3a0_1080, // mov r1, #0x80
+      0xe3a0_2301, // mov r2, #0x4000000
+      0xe282_2c03, // add r2, r2, #0x300
+      0xe5c2_1001, // strb r1, [r2, #1], stop
+      0xeaff_fffe  // b .
+    ];
+
+    let mut rom: Vec<u8> = code.iter().flat_map(|word: &u32| word.to_le_bytes()).collect();
+
+    rom.resize(0x1000, 0);
+
+    cpu.load_game(rom, None);
+    cpu.skip_bios();
+
+    // keypad irq when a is pressed
+    cpu.mem_write_16(0x400_0132, 0x4001);
+    cpu.mem_write_16(0x400_0200, InterruptEnableRegister::KEYPAD.bits());
+
+    // timer 0 counting every cycle
+    cpu.mem_write_16(0x400_0102, 0x80);
+
+    while !cpu.is_stopped() {
+      cpu.step();
+    }
+
+    cpu
+  }
+
+  #[test]
+  fn stop_freezes_the_timers_until_a_key_wakes_it() {
+    let mut cpu = stopped_cpu();
+
+    let count = cpu.timers.t[0].read_value(cpu.cycles);
+    let stopped_at = cpu.cycles;
+
+    for _ in 0..100 {
+      cpu.step();
+    }
+
+    assert!(cpu.is_stopped());
+    assert!(cpu.cycles > stopped_at + CYCLES_PER_FRAME as usize);
+
+    cpu.key_input.remove(KeyInputRegister::ButtonA);
+    cpu.step();
+
+    // the timer picks up where it was stopped
+    assert!(!cpu.is_stopped());
+    assert_eq!(cpu.timers.t[0].read_value(cpu.cycles), count);
+  }
+
+  #[test]
+  fn other_interrupts_dont_wake_it() {
+    let mut cpu = stopped_cpu();
+
+    cpu.mem_write_16(0x400_0200, !InterruptEnableRegister::KEYPAD.bits());
+    cpu.interrupt_request.insert(InterruptRequestRegister::VBLANK);
+
+    cpu.step();
+
+    assert!(cpu.is_stopped());
+  }
+
+  #[test]
+  fn keypad_irq_is_raised_when_the_keys_are_pressed_not_while_held() {
+    let mut cpu = stopped_cpu();
+
+    cpu.key_input.remove(KeyInputRegister::ButtonA);
+    cpu.step();
+
+    assert!(cpu.interrupt_request.contains(InterruptRequestRegister::KEYPAD));
+
+    cpu.interrupt_request.remove(InterruptRequestRegister::KEYPAD);
+    cpu.step();
+
+    assert!(!cpu.interrupt_request.contains(InterruptRequestRegister::KEYPAD));
+
+    // letting go and pressing again raises it again
+    cpu.key_input.insert(KeyInputRegister::ButtonA);
+    cpu.step();
+    cpu.key_input.remove(KeyInputRegister::ButtonA);
+    cpu.step();
+
+    assert!(cpu.interrupt_request.contains(InterruptRequestRegister::KEYPAD));
+  }
+
+  // sets a dma channel's addresses, count and control, which starts it
+  fn start_dma(cpu: &mut CPU, id: u32, source: u32, destination: u32, count: u16, control: u16) {
+    let base = 0x400_00b0 + id * 12;
+
+    cpu.mem_write_32(base, source);
+    cpu.mem_write_32(base + 4, destination);
+    cpu.mem_write_32(base + 8, count as u32 | (control as u32) << 16);
+  }
+
+  fn dma_cpu() -> CPU {
+    let mut cpu = CPU::new(HeapRb::<f32>::new(1024).split().0);
+
+    for i in 0..8 {
+      cpu.mem_write_16(0x200_0000 + i * 2, i as u16 + 1);
+    }
+
+    cpu
   }
 
-}
\ No newline at end of file
+  #[test]
+  fn higher_priority_dma_pauses_a_lower_one() {
+    let mut cpu = dma_cpu();
+
+    start_dma(&mut cpu, 3, 0x200_0000, 0x200_0100, 8, 0x8000);
+
+    for _ in 0..3 {
+      cpu.handle_dma();
+    }
+
+    start_dma(&mut cpu, 0, 0x200_0000, 0x200_0200, 2, 0x8000);
+
+    cpu.handle_dma();
+
+    assert_eq!(cpu.mem_read_16(0x200_0200), 1);
+    assert_eq!(cpu.dma.channels[3].units_left, 5);
+
+    cpu.handle_dma();
+
+    assert!(!cpu.dma.channels[0].running);
+
+    // channel 3 picks up where it left off
+    while cpu.dma.has_pending_transfers() {
+      cpu.handle_dma();
+    }
+
+    assert_eq!((0..8).map(|i| cpu.mem_read_16(0x200_0100 + i * 2)).collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(cpu.mem_read_16(0x200_0202), 2);
+  }
+
+  #[test]
+  fn dma_takes_its_startup_and_the_memory_wait_states() {
+    let mut cpu = dma_cpu();
+
+    start_dma(&mut cpu, 3, 0x200_0000, 0x200_0100, 2, 0x8000);
+
+    let start = cpu.cycles;
+
+    cpu.handle_dma();
+
+    // 2 cycles to start up, then a 3 cycle read and write of ewram
+    assert_eq!(cpu.cycles - start, 8);
+
+    let start = cpu.cycles;
+
+    cpu.handle_dma();
+
+    assert_eq!(cpu.cycles - start, 6);
+  }
+
+  #[test]
+  fn dma_reads_from_the_bios_return_the_last_value_read() {
+    let mut cpu = dma_cpu();
+
+    start_dma(&mut cpu, 3, 0x200_0000, 0x200_0100, 1, 0x8400);
+    cpu.handle_dma();
+
+    start_dma(&mut cpu, 3, 0x000_0000, 0x200_0104, 1, 0x8400);
+    cpu.handle_dma();
+
+    assert_eq!(cpu.mem_read_32(0x200_0104), 0x0002_0001);
+  }
+}
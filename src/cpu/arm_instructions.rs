@@ -1,6 +1,9 @@
 use crate::cpu::{PC_REGISTER, PSRRegister, LR_REGISTER, OperatingMode};
 
-use super::{CPU, MemoryAccess};
+use super::{
+  block_cache::{Handler, OperandHandler, Operands},
+  CPU, MemoryAccess
+};
 
 impl CPU {
   pub fn populate_arm_lut(&mut self) {
@@ -10,6 +13,28 @@ impl CPU {
     }
   }
 
+  /// the handler and operands for the formats the block cache decodes ahead of time
+  pub fn decode_arm_operands(instr: u32) -> Option<Handler> {
+    let upper = (instr >> 20) & 0xff;
+    let lower = (instr >> 4) & 0xf;
+
+    // multiplies, swaps and halfword transfers share the data processing space
+    let is_multiply_or_transfer = upper & 0b11100000 == 0 && lower & 0b1001 == 0b1001;
+    // as do psr transfers and bx, which are the compare ops without s
+    let is_psr_transfer = upper & 0b11011001 == 0b00010000;
+    let is_undefined = upper & 0b11100000 == 0b01100000 && lower & 0b1 == 1;
+
+    let (handler, operands): (OperandHandler, Operands) = if upper & 0b11000000 == 0 && !is_multiply_or_transfer && !is_psr_transfer {
+      (CPU::execute_data_processing, Self::data_processing_operands(instr))
+    } else if upper & 0b11000000 == 0b01000000 && !is_undefined {
+      (CPU::execute_single_data_transfer, Self::single_data_transfer_operands(instr))
+    } else {
+      return None;
+    };
+
+    Some(Handler::Operands(handler, operands))
+  }
+
   fn decode_arm(&mut self, upper: u16, lower: u16) -> fn(&mut CPU, instr: u32) -> Option<MemoryAccess> {
     if upper & 0b11111100 == 0 && lower == 0b1001 {
       CPU::multiply
@@ -61,15 +86,38 @@ impl CPU {
   }
 
   fn data_processing(&mut self, instr: u32) -> Option<MemoryAccess> {
+    self.execute_data_processing(&Self::data_processing_operands(instr))
+  }
+
+  fn data_processing_operands(instr: u32) -> Operands {
+    let i = (instr >> 25) & 0b1;
+    let rotate = (2 * ((instr >> 8) & 0xf)) as u8;
+
+    Operands {
+      op_code: ((instr >> 21) & 0xf) as u8,
+      rn: ((instr >> 16) & 0xf) as u8,
+      rd: ((instr >> 12) & 0xf) as u8,
+      rs: ((instr >> 8) & 0xf) as u8,
+      rm: (instr & 0xf) as u8,
+      shift_type: ((instr >> 5) & 0b11) as u8,
+      // immediates are rotated here, but the carry out still has to be set when the op runs
+      shift: if i == 1 { rotate } else { ((instr >> 7) & 0x1f) as u8 },
+      register_shift: (instr >> 4) & 0b1 == 1,
+      immediate: (instr & 0xff).rotate_right(rotate as u32),
+      flags: ((instr >> 20) & 0x3f) as u8
+    }
+  }
+
+  fn execute_data_processing(&mut self, operands: &Operands) -> Option<MemoryAccess> {
     // println!("inside data processing");
 
     let mut return_val = Some(MemoryAccess::Sequential);
 
-    let i = (instr >> 25) & 0b1;
-    let op_code = (instr >> 21) & 0xf;
-    let mut s = (instr >> 20) & 0b1;
-    let rn = (instr >> 16) & 0xf;
-    let rd = (instr >> 12) & 0xf;
+    let i = operands.flags >> 5;
+    let op_code = operands.op_code as u32;
+    let mut s = operands.flags & 0b1;
+    let rn = operands.rn as u32;
+    let rd = operands.rd as u32;
 
     let mut operand1 = self.get_register(rn as usize);
 
@@ -77,13 +125,14 @@ impl CPU {
     let mut overflow = self.cpsr.contains(PSRRegister::OVERFLOW);
 
     let operand2 = if i == 1 {
-      let immediate = instr & 0xff;
-      let rotate = (2 * ((instr >> 8) & 0xf)) as u8;
+      if operands.shift != 0 {
+        carry = operands.immediate >> 31 == 1;
+      }
 
-      self.ror(immediate, rotate, false, true, &mut carry)
+      operands.immediate
     } else {
       // println!("using register for 2nd operand");
-      self.get_data_processing_register_operand(instr, rn, &mut operand1, &mut carry)
+      self.get_data_processing_register_operand(operands, &mut operand1, &mut carry)
     };
 
     if rd == PC_REGISTER as u32 && s == 1 {
@@ -148,7 +197,7 @@ impl CPU {
       operand1.wrapping_mul(operand2).wrapping_add(operand3)
     };
 
-    let cycles = self.get_multiplier_cycles(operand2);
+    let cycles = self.get_multiplier_cycles(operand2, true);
 
     self.add_cycles(cycles);
 
@@ -199,7 +248,8 @@ impl CPU {
     self.r[rd_low as usize] = (result & 0xffffffff) as i32 as u32;
     self.r[rd_hi as usize] = (result >> 32) as i32 as u32;
 
-    let cycles = self.get_multiplier_cycles(operand2);
+    // long multiplies take one more internal cycle than the regular ones
+    let cycles = self.get_multiplier_cycles(operand2, u == 1) + 1;
 
     self.add_cycles(cycles);
 
@@ -224,16 +274,19 @@ impl CPU {
 
     let base_address = self.get_register(rn as usize);
 
+    // swp takes 1S + 2N + 1I, the S being the fetch
     if b == 1 {
       let temp = self.load_8(base_address, MemoryAccess::NonSequential);
-      self.store_8(base_address, self.get_register(rm as usize) as u8, MemoryAccess::Sequential);
+      self.store_8(base_address, self.get_register(rm as usize) as u8, MemoryAccess::NonSequential);
       self.r[rd as usize] = temp as u32;
     } else {
       let temp = self.ldr_word(base_address);
-      self.store_32(base_address & !(0b11), self.get_register(rm as usize), MemoryAccess::Sequential);
+      self.store_32(base_address & !(0b11), self.get_register(rm as usize), MemoryAccess::NonSequential);
       self.r[rd as usize] = temp;
     }
 
+    self.add_cycles(1);
+
     self.pc = self.pc.wrapping_add(4);
     Some(MemoryAccess::NonSequential)
   }
@@ -346,6 +399,9 @@ impl CPU {
         _ => panic!("shouldn't happen")
       };
 
+      // the internal cycle comes before the pipeline refill
+      self.add_cycles(1);
+
       if rd == PC_REGISTER as u32 {
         self.pc = value & !(0b11);
 
@@ -359,8 +415,6 @@ impl CPU {
       }
 
       // println!("loaded value {value} from address {:X}", address);
-
-      self.add_cycles(1);
     }
 
     if (l == 0 || rd != rn) && (w == 1 || p == 0) {
@@ -375,20 +429,41 @@ impl CPU {
   }
 
   fn single_data_transfer(&mut self, instr: u32) -> Option<MemoryAccess>  {
+    self.execute_single_data_transfer(&Self::single_data_transfer_operands(instr))
+  }
+
+  fn single_data_transfer_operands(instr: u32) -> Operands {
+    Operands {
+      rn: ((instr >> 16) & 0xf) as u8,
+      rd: ((instr >> 12) & 0xf) as u8,
+      rs: ((instr >> 8) & 0xf) as u8,
+      rm: (instr & 0xf) as u8,
+      shift_type: ((instr >> 5) & 0b11) as u8,
+      shift: ((instr >> 7) & 0x1f) as u8,
+      register_shift: (instr >> 4) & 0b1 == 1,
+      immediate: instr & 0xfff,
+      flags: ((instr >> 20) & 0x3f) as u8,
+      ..Default::default()
+    }
+  }
+
+  fn execute_single_data_transfer(&mut self, operands: &Operands) -> Option<MemoryAccess>  {
     // println!("inside single data transfer");
 
     let mut result = Some(MemoryAccess::NonSequential);
 
-    let i = (instr >> 25) & 0b1;
-    let p = (instr >> 24) & 0b1;
-    let u = (instr >> 23) & 0b1;
-    let b = (instr >> 22) & 0b1;
-    let w = (instr >> 21) & 0b1;
-    let l = (instr >> 20) & 0b1;
+    let flags = operands.flags as u32;
 
-    let rn = (instr >> 16) & 0xf;
-    let rd = (instr >> 12) & 0xf;
-    let mut offset: u32 = instr & 0xfff;
+    let i = (flags >> 5) & 0b1;
+    let p = (flags >> 4) & 0b1;
+    let u = (flags >> 3) & 0b1;
+    let b = (flags >> 2) & 0b1;
+    let w = (flags >> 1) & 0b1;
+    let l = flags & 0b1;
+
+    let rn = operands.rn as u32;
+    let rd = operands.rd as u32;
+    let mut offset: u32 = operands.immediate;
 
     let mut should_update_pc = true;
 
@@ -399,7 +474,7 @@ impl CPU {
     if i == 1 {
       // println!("offset is a register shifted in some way");
       // offset is a register shifted in some way
-      self.update_single_data_transfer_offset(instr, &mut offset);
+      offset = self.single_data_transfer_register_offset(operands);
     }
 
     if u == 0 {
@@ -431,6 +506,9 @@ impl CPU {
 
       // println!("setting register {rd} to {data} from address {:X}", address);
 
+      // the internal cycle comes before the pipeline refill
+      self.add_cycles(1);
+
       if rd == PC_REGISTER as u32 {
         self.pc = data & !(0b11);
 
@@ -442,8 +520,6 @@ impl CPU {
       } else {
         self.r[rd as usize] = data;
       }
-
-      self.add_cycles(1);
     } else {
       // store
       let value = if rd == PC_REGISTER as u32 {
@@ -541,7 +617,8 @@ impl CPU {
       }
     }
 
-    let mut access = MemoryAccess::Sequential;
+    // the first transfer is non sequential, the rest are sequential
+    let mut access = MemoryAccess::NonSequential;
 
     if register_list != 0 {
       if l == 0 {
@@ -613,6 +690,8 @@ impl CPU {
               }
 
               should_increment_pc = false;
+
+              self.add_cycles(1);
               self.reload_pipeline32();
 
               result = None;
@@ -627,7 +706,9 @@ impl CPU {
           }
         }
 
-        self.add_cycles(1);
+        if should_increment_pc {
+          self.add_cycles(1);
+        }
       }
     } else {
       // empty rlist edge case
@@ -645,6 +726,7 @@ impl CPU {
       } else {
         let val = self.ldr_word(address);
         self.pc = val & !(0b11);
+        self.add_cycles(1);
         self.reload_pipeline32();
 
         result = None;
@@ -682,17 +764,23 @@ impl CPU {
       self.r[LR_REGISTER] = (self.pc - 4) & !(0b1);
     }
 
+    let branch_address = self.pc.wrapping_sub(8);
+
     self.pc = ((self.pc as i32).wrapping_add(offset) as u32) & !(0b1);
 
+    if l == 0 {
+      self.check_idle_loop(branch_address, self.pc);
+    }
+
     self.reload_pipeline32();
 
     None
   }
 
-  fn arm_software_interrupt(&mut self, _instr: u32) -> Option<MemoryAccess>  {
+  fn arm_software_interrupt(&mut self, instr: u32) -> Option<MemoryAccess>  {
     // println!("inside arm software interrupt");
 
-    self.software_interrupt();
+    self.software_interrupt(((instr >> 16) & 0xff) as u8);
 
     None
   }
@@ -893,8 +981,9 @@ impl CPU {
     }
   }
 
-  fn get_data_processing_register_operand(&mut self, instr: u32, rn: u32, operand1: &mut u32, carry: &mut bool) -> u32 {
-    let shift_by_register = (instr >> 4) & 0b1 == 1;
+  fn get_data_processing_register_operand(&mut self, operands: &Operands, operand1: &mut u32, carry: &mut bool) -> u32 {
+    let shift_by_register = operands.register_shift;
+    let rn = operands.rn as u32;
 
     let mut immediate = true;
 
@@ -906,18 +995,18 @@ impl CPU {
       }
       self.add_cycles(1);
 
-      let rs = (instr >> 8) & 0xf;
+      let rs = operands.rs;
 
       // println!("rs = {rs}");
 
       self.r[rs as usize] & 0xff
     } else {
-      (instr >> 7) & 0x1f
+      operands.shift as u32
     };
 
-    let shift_type = (instr >> 5) & 0b11;
+    let shift_type = operands.shift_type;
 
-    let rm = instr & 0xf;
+    let rm = operands.rm as u32;
 
     // println!("rm = {rm}");
 
@@ -938,11 +1027,11 @@ impl CPU {
     }
   }
 
-  fn update_single_data_transfer_offset(&mut self, instr: u32, offset: &mut u32) {
+  fn single_data_transfer_register_offset(&mut self, operands: &Operands) -> u32 {
     // offset is a register shifted in some way
-    let shift_type = (instr >> 5) & 0b11;
+    let shift_type = operands.shift_type;
 
-    let rm = *offset & 0xf;
+    let rm = operands.rm as u32;
 
     let shifted_operand = if rm == PC_REGISTER as u32 {
       self.pc + 4
@@ -951,13 +1040,13 @@ impl CPU {
       self.r[rm as usize]
     };
 
-    let shift_by_register = (instr >> 4) & 0b1;
+    let shift_by_register = operands.register_shift;
 
     let mut immediate = true;
 
-    let shift = if shift_by_register == 1 {
+    let shift = if shift_by_register {
       immediate = false;
-      let rs = *offset >> 8;
+      let rs = operands.rs as u32;
 
       if rs == PC_REGISTER as u32 {
         self.pc & 0xff
@@ -965,17 +1054,17 @@ impl CPU {
         self.r[rs as usize] & 0xff
       }
     } else {
-      *offset >> 7
+      operands.shift as u32
     };
 
     let mut carry = self.cpsr.contains(PSRRegister::CARRY);
 
-    *offset = match shift_type {
+    match shift_type {
       0 => self.lsl(shifted_operand, shift, &mut carry),
       1 => self.lsr(shifted_operand, shift, immediate, &mut carry),
       2 => self.asr(shifted_operand, shift, immediate, &mut carry),
       3 => self.ror(shifted_operand, shift as u8, immediate, true, &mut carry),
       _ => unreachable!("can't happen")
-    };
+    }
   }
 }
\ No newline at end of file
@@ -0,0 +1,371 @@
+// cached interpreter. straight-line runs of code in rom and iwram are decoded once into blocks
+// that hold the opcodes along with their handlers, so executing them skips the bus reads for
+// opcode fetches and the lookup table decode. the most common formats also have their register
+// indices, immediates and shifts decoded up front. fetch timing is still charged per instruction,
+// so cycle accounting is the same as when the cache is disabled.
+
+use std::{collections::HashMap, rc::Rc};
+
+use super::{MemoryAccess, MemoryWidth, PSRRegister, CPU};
+
+pub type ArmHandler = fn(&mut CPU, instr: u32) -> Option<MemoryAccess>;
+pub type ThumbHandler = fn(&mut CPU, instr: u16) -> Option<MemoryAccess>;
+pub type OperandHandler = fn(&mut CPU, operands: &Operands) -> Option<MemoryAccess>;
+
+const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+
+// blocks never cross a page, so a write only has to invalidate the blocks in its own page
+const PAGE_SHIFT: u32 = 10;
+const IWRAM_PAGES: usize = 0x8000 >> PAGE_SHIFT;
+
+/// the fields of an opcode, pulled out once when its block is decoded. which of them mean
+/// anything depends on the instruction format
+#[derive(Clone, Copy, Default)]
+pub struct Operands {
+  pub op_code: u8,
+  pub rd: u8,
+  pub rn: u8,
+  pub rm: u8,
+  pub rs: u8,
+  pub shift_type: u8,
+  pub shift: u8,
+  pub register_shift: bool,
+  pub immediate: u32,
+  // for arm, bits 20-25 of the opcode, which hold i and s or the single transfer flags
+  pub flags: u8
+}
+
+#[derive(Clone, Copy)]
+pub enum Handler {
+  Arm(ArmHandler),
+  Thumb(ThumbHandler),
+  Operands(OperandHandler, Operands)
+}
+
+#[derive(Clone, Copy)]
+pub struct DecodedInstruction {
+  instr: u32,
+  handler: Handler
+}
+
+impl DecodedInstruction {
+  pub fn execute(&self, cpu: &mut CPU) -> Option<MemoryAccess> {
+    match &self.handler {
+      Handler::Arm(handler) => handler(cpu, self.instr),
+      Handler::Thumb(handler) => handler(cpu, self.instr as u16),
+      Handler::Operands(handler, operands) => handler(cpu, operands)
+    }
+  }
+}
+
+pub struct Block {
+  start: u32,
+  thumb: bool,
+  instructions: Vec<DecodedInstruction>
+}
+
+impl Block {
+  fn index(&self, address: u32) -> usize {
+    let shift = if self.thumb { 1 } else { 2 };
+
+    (address.wrapping_sub(self.start) >> shift) as usize
+  }
+
+  fn contains(&self, address: u32, thumb: bool) -> bool {
+    thumb == self.thumb && address >= self.start && self.index(address) < self.instructions.len()
+  }
+}
+
+#[derive(Default)]
+pub struct BlockCache {
+  pub enabled: bool,
+  blocks: HashMap<u32, Rc<Block>>,
+  iwram_pages: [Vec<u32>; IWRAM_PAGES],
+  current: Option<Rc<Block>>,
+  // bumped whenever blocks are dropped, so a running block knows to stop
+  generation: u32
+}
+
+impl BlockCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn is_cacheable(address: u32) -> bool {
+    // the eeprom lives in the 0xd00_0000 region, so reading ahead there isn't safe
+    (0x300_0000..=0x3ff_ffff).contains(&address) || (0x800_0000..=0xcff_ffff).contains(&address)
+  }
+
+  fn key(address: u32, thumb: bool) -> u32 {
+    address | thumb as u32
+  }
+
+  fn find(&mut self, address: u32, thumb: bool) -> Option<Rc<Block>> {
+    if let Some(block) = &self.current {
+      if block.contains(address, thumb) {
+        return Some(block.clone());
+      }
+    }
+
+    let block = self.blocks.get(&Self::key(address, thumb))?.clone();
+
+    self.current = Some(block.clone());
+
+    Some(block)
+  }
+
+  fn insert(&mut self, block: Block) -> Rc<Block> {
+    let key = Self::key(block.start, block.thumb);
+
+    if (0x300_0000..=0x3ff_ffff).contains(&block.start) {
+      self.iwram_pages[Self::iwram_page(block.start)].push(key);
+    }
+
+    let block = Rc::new(block);
+
+    self.blocks.insert(key, block.clone());
+    self.current = Some(block.clone());
+
+    block
+  }
+
+  /// drops any blocks decoded from the iwram page containing address
+  pub fn invalidate(&mut self, address: u32) {
+    let page = &mut self.iwram_pages[Self::iwram_page(address)];
+
+    if page.is_empty() {
+      return;
+    }
+
+    for key in page.drain(..) {
+      self.blocks.remove(&key);
+    }
+
+    self.current = None;
+    self.generation = self.generation.wrapping_add(1);
+  }
+
+  pub fn clear(&mut self) {
+    self.blocks.clear();
+    self.current = None;
+    self.generation = self.generation.wrapping_add(1);
+
+    for page in &mut self.iwram_pages {
+      page.clear();
+    }
+  }
+
+  fn iwram_page(address: u32) -> usize {
+    ((address & 0x7fff) >> PAGE_SHIFT) as usize
+  }
+}
+
+impl CPU {
+  pub fn set_block_cache_enabled(&mut self, enabled: bool) {
+    self.block_cache.enabled = enabled;
+    self.block_cache.clear();
+  }
+
+  /// runs cached instructions from the current pc until a branch, the end of the block, or anything
+  /// the main loop has to handle. returns false if the code at pc can't be cached
+  pub fn step_block(&mut self) -> bool {
+    let thumb = self.cpsr.contains(PSRRegister::STATE_BIT);
+    let size = if thumb { 2 } else { 4 };
+
+    let mut pc = self.pc & !(size - 1);
+    // the instruction being executed is two fetches behind pc
+    let address = pc.wrapping_sub(2 * size);
+
+    if !BlockCache::is_cacheable(address) {
+      return false;
+    }
+
+    let block = match self.block_cache.find(address, thumb) {
+      Some(block) => block,
+      None => {
+        let block = self.decode_block(address, thumb);
+
+        self.block_cache.insert(block)
+      }
+    };
+
+    let generation = self.block_cache.generation;
+    let mut index = block.index(address);
+
+    while let Some(decoded) = block.instructions.get(index) {
+      let next_instruction = match block.instructions.get(index + 2) {
+        Some(prefetched) => {
+          let width = if thumb { MemoryWidth::Width16 } else { MemoryWidth::Width32 };
+
+          self.update_fetch_cycles(pc, self.next_fetch, width);
+
+          prefetched.instr
+        }
+        None if thumb => self.fetch_16(pc, self.next_fetch) as u32,
+        None => self.fetch_32(pc, self.next_fetch)
+      };
+
+      // the pipeline may hold an opcode that was overwritten after it was fetched, so only
+      // use the decoded handler if it still matches
+      let decoded = if self.pipeline[0] == decoded.instr { Some(decoded) } else { None };
+
+      if thumb {
+        self.advance_thumb_pipeline(pc, next_instruction, decoded);
+      } else {
+        self.advance_arm_pipeline(pc, next_instruction, decoded);
+      }
+
+      index += 1;
+      pc = pc.wrapping_add(size);
+
+      // io writes in the block can schedule an event earlier than the one the block started with
+      if self.pc != pc
+        || self.cycles >= self.scheduler.get_cycles_to_next_event()
+        || self.is_halted
+        || self.is_stopped
+        || self.block_cache.generation != generation
+        || self.dma.has_pending_transfers()
+        || self.irq_pending()
+        || self.idle_loop.detected
+      {
+        break;
+      }
+    }
+
+    true
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.interrupt_master_enable
+      && !self.cpsr.contains(PSRRegister::IRQ_DISABLE)
+      && (self.interrupt_enable.bits() & self.interrupt_request.bits()) != 0
+  }
+
+  fn decode_block(&mut self, start: u32, thumb: bool) -> Block {
+    let mut instructions = Vec::new();
+    let mut address = start;
+
+    loop {
+      let ends_block = if thumb {
+        let instr = self.mem_read::<u16>(address);
+
+        instructions.push(DecodedInstruction {
+          instr: instr as u32,
+          handler: Self::decode_thumb_operands(instr).unwrap_or(Handler::Thumb(self.thumb_lut[(instr >> 8) as usize]))
+        });
+
+        address += 2;
+
+        Self::is_thumb_branch(instr)
+      } else {
+        let instr = self.mem_read::<u32>(address);
+
+        instructions.push(DecodedInstruction {
+          instr,
+          handler: Self::decode_arm_operands(instr)
+            .unwrap_or(Handler::Arm(self.arm_lut[(((instr >> 16) & 0xff0) | ((instr >> 4) & 0xf)) as usize]))
+        });
+
+        address += 4;
+
+        Self::is_arm_branch(instr)
+      };
+
+      if ends_block || instructions.len() == MAX_BLOCK_INSTRUCTIONS || address >> PAGE_SHIFT != start >> PAGE_SHIFT {
+        break;
+      }
+    }
+
+    Block {
+      start,
+      thumb,
+      instructions
+    }
+  }
+
+  fn is_arm_branch(instr: u32) -> bool {
+    // b, bl, swi and bx end the block
+    (instr >> 25) & 0b111 == 0b101 || (instr >> 24) & 0xf == 0xf || instr & 0x0fff_fff0 == 0x012f_ff10
+  }
+
+  fn is_thumb_branch(instr: u16) -> bool {
+    // conditional branches and swi, unconditional branches, bl and bx
+    instr >> 12 == 0b1101 || instr >> 11 == 0b11100 || instr >> 12 == 0b1111 || instr >> 8 == 0b01000111
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  fn cpu_with_code(code: &[u32]) -> CPU {
+    let mut cpu = CPU::new(HeapRb::<f32>::new(1024).split().0);
+
+    let mut rom: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    rom.resize(0x1000, 0);
+
+    cpu.load_game(rom, None);
+    cpu.skip_bios();
+    cpu.set_block_cache_enabled(true);
+
+    cpu
+  }
+
+  fn has_operands(handler: Option<Handler>) -> bool {
+    matches!(handler, Some(Handler::Operands(..)))
+  }
+
+  #[test]
+  fn common_formats_decode_their_operands() {
+    assert!(has_operands(CPU::decode_arm_operands(0xe280_0001))); // add r0, r0, #1
+    assert!(has_operands(CPU::decode_arm_operands(0xe591_2004))); // ldr r2, [r1, #4]
+    assert!(has_operands(CPU::decode_thumb_operands(0x1c40))); // adds r0, r0, #1
+    assert!(has_operands(CPU::decode_thumb_operands(0x6848))); // ldr r0, [r1, #4]
+
+    assert!(!has_operands(CPU::decode_arm_operands(0xe10f_0000))); // mrs r0, cpsr
+    assert!(!has_operands(CPU::decode_arm_operands(0xe000_0291))); // mul r0, r1, r2
+    assert!(!has_operands(CPU::decode_arm_operands(0xe1d0_00b2))); // ldrh r0, [r0, #2]
+    assert!(!has_operands(CPU::decode_arm_operands(0xe12f_ff1e))); // bx lr
+    assert!(!has_operands(CPU::decode_thumb_operands(0x4770))); // bx lr
+  }
+
+  #[test]
+  fn decoded_immediates_set_the_carry_only_when_rotated() {
+    let mut cpu = cpu_with_code(&[
+      0xe3b0_1001, // movs r1, #1
+      0xe10f_2000, // mrs r2, cpsr
+      0xe3b0_0102, // movs r0, #0x80000000
+      0xeaff_fffe  // b .
+    ]);
+
+    cpu.step();
+
+    assert_eq!(cpu.r[0], 0x8000_0000);
+    assert!(!PSRRegister::from_bits_retain(cpu.r[2]).contains(PSRRegister::CARRY));
+    assert!(cpu.cpsr.contains(PSRRegister::CARRY));
+  }
+
+  #[test]
+  fn blocks_stop_at_events_scheduled_inside_them() {
+    let mut code = vec![
+      0xe3a0_0301, // mov r0, #0x4000000
+      0xe280_0c01, // add r0, r0, #0x100
+      0xe3a0_1cff, // mov r1, #0xff00
+      0xe381_10f0, // orr r1, r1, #0xf0
+      0xe381_1880, // orr r1, r1, #0x800000
+      0xe580_1000  // str r1, [r0], timer 0 overflows 16 cycles later
+    ];
+
+    code.resize(48, 0xe1a0_0000); // mov r0, r0
+
+    let mut cpu = cpu_with_code(&code);
+
+    cpu.step();
+
+    // the step ends on the timer overflow, before the next one is due
+    assert!(cpu.cycles < cpu.scheduler.get_cycles_to_next_event());
+  }
+}
@@ -1,6 +1,6 @@
 use crate::{
-  apu::registers::sound_control_dma::SoundControlDma, cartridge::BackupMedia, cpu::{
-    dma::dma_channels::AddressType, registers::interrupt_enable_register::InterruptEnableRegister
+  apu::registers::sound_control_dma::SoundControlDma, cartridge::{tilt_sensor::{TILT_END, TILT_START}, BackupAccess, BackupMedia}, cpu::{
+    dma::dma_channels::AddressType, registers::{interrupt_enable_register::InterruptEnableRegister, interrupt_request_register::InterruptRequestRegister}
   },
   gpu::{
     registers::{
@@ -14,7 +14,7 @@ use crate::{
   number::Number
 };
 
-use super::CPU;
+use super::{hle_bios::BIOS_SIZE, PSRRegister, CPU};
 
 impl CPU {
   pub fn mem_read_32(&mut self, address: u32) -> u32 {
@@ -39,7 +39,14 @@ impl CPU {
 
   pub fn mem_read<T: Number>(&mut self, address: u32) -> T {
     match address {
-      0..=0x3fff => unsafe { *(&self.bios[address as usize] as *const u8 as *const T) },
+      0..=0x3fff => {
+        // the bios can only be read while executing from it, otherwise the last fetched bios opcode is returned
+        if self.pc < BIOS_SIZE as u32 {
+          unsafe { *(&self.bios[address as usize] as *const u8 as *const T) }
+        } else {
+          Self::open_bus_bits(self.last_bios_opcode, address)
+        }
+      }
       0x200_0000..=0x2ff_ffff => unsafe { *(&self.board_wram[(address & 0x3_ffff) as usize] as *const u8 as *const T) },
       0x300_0000..=0x3ff_ffff => {
         unsafe { *(&self.chip_wram[(address & 0x7fff) as usize] as *const u8 as *const T) }
@@ -55,6 +62,7 @@ impl CPU {
         unsafe { *(&self.gpu.vram[offset as usize] as *const u8 as *const T) }
       }
       0x700_0000..=0x7ff_ffff => unsafe { *(&self.gpu.oam_ram[(address & 0x3ff) as usize] as *const u8 as *const T) },
+      0x800_00c4..=0x800_00c9 if self.cartridge.gpio.readable => self.cartridge.read_gpio(address),
       0x800_0000..=0xdff_ffff => {
         let offset = address & 0x01ff_ffff;
         if offset >= self.cartridge.rom.len() as u32 {
@@ -68,11 +76,14 @@ impl CPU {
           unsafe { *(&self.cartridge.rom[offset as usize] as *const u8 as *const T) }
         }
       }
+      TILT_START..=TILT_END if self.cartridge.tilt_sensor.is_some() => self.cartridge.read_tilt(address),
       0xe00_0000..=0xeff_ffff | 0xf00_0000..=0xfff_ffff => {
+        self.cartridge.watch_backup_access(BackupAccess::Read);
+
         if let BackupMedia::Sram(sram) = &mut self.cartridge.backup {
           sram.read((address & 0x7fff) as usize)
         } else if let BackupMedia::Flash(flash) = &mut self.cartridge.backup {
-          flash.read(address)
+          flash.read(address, self.cycles)
         } else {
           num::zero()
         }
@@ -80,11 +91,50 @@ impl CPU {
       // 0x1000_0000..=0xffff_ffff => panic!("unused memory"),
       _ => {
         // println!("reading from unsupported address: {:X}", address);
-        num::zero()
+        self.open_bus(address)
       }
     }
   }
 
+  /// returns the value left on the bus by the last opcode fetch, used for reads from unmapped memory
+  pub fn open_bus<T: Number>(&mut self, address: u32) -> T {
+    let value = if self.cpsr.contains(PSRRegister::STATE_BIT) {
+      // self.pc is the address of the latest fetched opcode ($+4), pipeline[0] is the one before it ($+2)
+      let latest = self.pipeline[1] & 0xffff;
+      let previous = self.pipeline[0] & 0xffff;
+      let aligned = self.pc & 0b10 == 0;
+
+      match self.pc >> 24 {
+        0x0 | 0x7 => if aligned {
+          let next = self.mem_read_16(self.pc.wrapping_add(2)) as u32;
+
+          latest | next << 16
+        } else {
+          previous | latest << 16
+        }
+        0x3 => if aligned {
+          latest | previous << 16
+        } else {
+          previous | latest << 16
+        }
+        _ => latest | latest << 16
+      }
+    } else {
+      self.pipeline[1]
+    };
+
+    Self::open_bus_bits(value, address)
+  }
+
+  fn open_bus_bits<T: Number>(value: u32, address: u32) -> T {
+    let size = std::mem::size_of::<T>() as u32;
+    let value = value >> ((address & (4 - size)) * 8);
+
+    let mask = if size == 4 { u32::MAX } else { (1 << (size * 8)) - 1 };
+
+    T::from_u32(value & mask).unwrap()
+  }
+
   pub fn mem_read_8(&mut self, address: u32) -> u8 {
     match address {
       0x400_0000..=0x4ff_ffff => self.io_read_8(address),
@@ -94,13 +144,21 @@ impl CPU {
   }
 
   fn io_read_16(&mut self, address: u32) -> u16 {
+    match self.io_register(address) {
+      Some(value) => value,
+      None => self.open_bus(address)
+    }
+  }
+
+  /// returns None for write-only and unmapped registers, which read back as open bus
+  fn io_register(&mut self, address: u32) -> Option<u16> {
     let address = if address & 0xfffe == 0x8000 {
       0x400_0800
     } else {
       address
     };
 
-    match address {
+    let value = match address {
       0x400_0000 => self.gpu.dispcnt.bits(),
       0x400_0004 => self.gpu.dispstat.bits(),
       0x400_0006 => self.gpu.vcount,
@@ -116,13 +174,13 @@ impl CPU {
       0x400_00c6 => self.dma.channels[1].dma_control.bits(),
       0x400_00d2 => self.dma.channels[2].dma_control.bits(),
       0x400_00de => self.dma.channels[3].dma_control.bits(),
-      0x400_0100 => self.timers.t[0].value,
+      0x400_0100 => self.timers.t[0].read_value(self.cycles),
       0x400_0102 => self.timers.t[0].timer_ctl.bits(),
-      0x400_0104 => self.timers.t[1].value,
+      0x400_0104 => self.timers.t[1].read_value(self.cycles),
       0x400_0106 => self.timers.t[1].timer_ctl.bits(),
-      0x400_0108 => self.timers.t[2].value,
+      0x400_0108 => self.timers.t[2].read_value(self.cycles),
       0x400_010a => self.timers.t[2].timer_ctl.bits(),
-      0x400_010c => self.timers.t[3].value,
+      0x400_010c => self.timers.t[3].read_value(self.cycles),
       0x400_0080 => self.apu.soundcnt_l.value,
       0x400_0082 => self.apu.soundcnt_h.bits(),
       0x400_0084 => {
@@ -131,17 +189,30 @@ impl CPU {
         value << 7
       }
       0x400_010e => self.timers.t[3].timer_ctl.bits(),
+      0x400_0120..=0x400_012a | 0x400_0134 | 0x400_0140 | 0x400_0150..=0x400_0158 => self.sio.read_register(address),
       0x400_0130 => self.key_input.bits(),
+      0x400_0132 => self.key_interrupt_control.bits(),
       0x400_0200 => self.interrupt_enable.bits(),
       0x400_0202 => self.interrupt_request.bits(),
       0x400_0204 => self.waitcnt.value,
       0x400_0208 => if self.interrupt_master_enable { 1 } else { 0 },
       0x400_0300 => self.post_flag,
+      0x400_0010..=0x400_0046
+        | 0x400_004c
+        | 0x400_0054
+        | 0x400_00a0..=0x400_00a6
+        | 0x400_00b0..=0x400_00b6
+        | 0x400_00bc..=0x400_00c2
+        | 0x400_00c8..=0x400_00ce
+        | 0x400_00d4..=0x400_00da
+        | 0x400_0400..=0x4ff_ffff if address != 0x400_0800 => return None,
       _ => {
         // println!("io register not implemented: {:X}", address);
         0
       }
-    }
+    };
+
+    Some(value)
   }
 
   fn io_read_8(&mut self, address: u32) -> u8 {
@@ -193,11 +264,14 @@ impl CPU {
   pub fn mem_write_8(&mut self, address: u32, val: u8) {
     match address {
       0x400_0000..=0x4ff_ffff => self.io_write_8(address, val),
+      TILT_START..=TILT_END if self.cartridge.tilt_sensor.is_some() => self.cartridge.write_tilt(address, val),
       0xe00_0000..=0xeff_ffff | 0xf00_0000..=0xfff_ffff => {
+        self.cartridge.watch_backup_access(BackupAccess::Write(address, val));
+
         if let BackupMedia::Sram(sram) = &mut self.cartridge.backup {
           sram.write((address & 0x7fff) as usize, val);
         } else if let BackupMedia::Flash(flash) = &mut self.cartridge.backup {
-          flash.write(address, val);
+          flash.write(address, val, self.cycles);
         }
       }
       _ => self.mem_write::<u8>(address, val)
@@ -234,7 +308,18 @@ impl CPU {
       }
       0x300_0000..=0x3ff_ffff => {
         unsafe { *(&mut self.chip_wram[(address & 0x7fff) as usize] as *mut u8 as *mut T) = val };
+
+        if self.block_cache.enabled {
+          self.block_cache.invalidate(address);
+        }
       },
+      0x800_00c4..=0x800_00c9 => {
+        let irq = self.cartridge.write_gpio(address, val);
+
+        if irq {
+          self.interrupt_request.insert(InterruptRequestRegister::GAMEPACK);
+        }
+      }
       _ => {
         // println!("writing go unsupported address: {:X}", address);
       }
@@ -351,28 +436,24 @@ impl CPU {
       0x400_00dc => self.dma.channels[3].word_count = value,
       0x400_00de => self.dma.channels[3].write_control(value),
       0x400_0100 => self.timers.t[0].reload_timer_value(value),
-      0x400_0102 => self.timers.t[0].write_timer_control(value, &mut self.scheduler),
+      0x400_0102 => self.timers.t[0].write_timer_control(value, &mut self.scheduler, self.cycles),
       0x400_0104 => self.timers.t[1].reload_timer_value(value),
-      0x400_0106 => self.timers.t[1].write_timer_control(value, &mut self.scheduler),
+      0x400_0106 => self.timers.t[1].write_timer_control(value, &mut self.scheduler, self.cycles),
       0x400_0108 => self.timers.t[2].reload_timer_value(value),
-      0x400_010a => self.timers.t[2].write_timer_control(value, &mut self.scheduler),
+      0x400_010a => self.timers.t[2].write_timer_control(value, &mut self.scheduler, self.cycles),
       0x400_010c => self.timers.t[3].reload_timer_value(value),
-      0x400_010e => self.timers.t[3].write_timer_control(value, &mut self.scheduler),
+      0x400_010e => self.timers.t[3].write_timer_control(value, &mut self.scheduler, self.cycles),
       0x400_0200 => self.interrupt_enable = InterruptEnableRegister::from_bits_retain(value),
       0x400_0202 => self.clear_interrupts(value),
       0x400_0204 => {
         self.waitcnt.value = value;
         self.cycle_luts.update_tables(&self.waitcnt);
+        self.prefetch.set_enabled(self.waitcnt.prefetch_enabled());
       }
       0x400_0208 => self.interrupt_master_enable = value != 0,
-      0x400_0300 => self.post_flag = if value > 0 { 1 } else { 0 },
-      0x400_0301 => {
-        if value >> 7 & 0b1 == 0 {
-          self.is_halted = true;
-        } else {
-          panic!("STOP not implemented");
-        }
-      }
+      0x400_0120..=0x400_012a | 0x400_0134 | 0x400_0140 | 0x400_0150..=0x400_0158 => self.sio.write_register(address, value, &mut self.scheduler, self.cycles),
+      0x400_0132 => self.key_interrupt_control.write(value),
+      0x400_0300 => self.post_flag = value & 0b1,
       _ => {
         // println!("io register not implemented: {:X}", address)
       }
@@ -395,8 +476,25 @@ impl CPU {
       0x400_00a4..=0x400_00a7 => {
         self.apu.fifo_b.write(value as i8);
       }
+      0x400_0300 => self.post_flag = (value & 0b1) as u16,
+      0x400_0301 => {
+        if value >> 7 & 0b1 == 0 {
+          self.is_halted = true;
+        } else {
+          self.is_stopped = true;
+        }
+      }
       _ => {
-        let mut temp = self.mem_read_16(address & !(0b1));
+        let mut temp = match address & !(0b1) {
+          // the other half of a timer reload comes from the reload value, not the live count
+          0x400_0100 | 0x400_0104 | 0x400_0108 | 0x400_010c => self.timers.t[((address >> 2) & 0b11) as usize].reload_value,
+          // reading SIODATA8 pops the uart receive fifo
+          0x400_012a => self.sio.send_data,
+          // don't acknowledge the joy bus flags in the other half, or clear JOYSTAT's receive flag
+          0x400_0140 => self.sio.joycnt.bits() & !0b111,
+          0x400_0150 | 0x400_0152 => (self.sio.joy_receive >> ((address & 0b10) * 8)) as u16,
+          _ => self.io_register(address & !(0b1)).unwrap_or(0)
+        };
 
         temp = if address & 0b1 == 1 {
           (temp & 0xff) | (value as u16) << 8
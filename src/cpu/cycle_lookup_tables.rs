@@ -57,16 +57,14 @@ impl CycleLookupTables {
   }
 
   pub fn update_tables(&mut self, waitcnt: &WaitstateControlRegister) {
-    let sram_wait_cycles = waitcnt.sram_wait_ctl_cycles() as u32;
-
-    self.n_cycles_32[SRAM_LO_PAGE] = sram_wait_cycles;
-    self.n_cycles_32[SRAM_LO_PAGE] = sram_wait_cycles;
-    self.n_cycles_16[SRAM_HI_PAGE] = sram_wait_cycles;
-    self.n_cycles_16[SRAM_HI_PAGE] = sram_wait_cycles;
-    self.s_cycles_32[SRAM_LO_PAGE] = sram_wait_cycles;
-    self.s_cycles_32[SRAM_LO_PAGE] = sram_wait_cycles;
-    self.s_cycles_16[SRAM_HI_PAGE] = sram_wait_cycles;
-    self.s_cycles_16[SRAM_HI_PAGE] = sram_wait_cycles;
+    let sram_wait_cycles = 1 + waitcnt.sram_wait_ctl_cycles() as u32;
+
+    for page in [SRAM_LO_PAGE, SRAM_HI_PAGE] {
+      self.n_cycles_32[page] = sram_wait_cycles;
+      self.n_cycles_16[page] = sram_wait_cycles;
+      self.s_cycles_32[page] = sram_wait_cycles;
+      self.s_cycles_16[page] = sram_wait_cycles;
+    }
 
     for i in 0..2 {
       self.n_cycles_16[WAITSTATE_0_PAGE + i] = 1 + waitcnt.waitstate_0_first_access_cycles() as u32;
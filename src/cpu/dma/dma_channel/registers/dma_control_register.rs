@@ -7,6 +7,7 @@ bitflags! {
   pub struct DmaControlRegister: u16 {
     const DMA_REPEAT = 0b1 << 9;
     const DMA_TRANSFER_TYPE = 0b1 << 10;
+    // dma 3 only
     const GAME_PAK_DRQ = 0b1 << 11;
     const IRQ_ENABLE = 0b1 << 14;
     const DMA_ENABLE = 0b1 << 15;
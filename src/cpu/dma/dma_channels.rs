@@ -1,16 +1,22 @@
 use serde::{Deserialize, Serialize};
 
-use crate::cpu::CPU;
-
 use super::dma_channel::{DmaChannel, registers::dma_control_register::DmaControlRegister};
 
 pub const VBLANK_TIMING: u16 = 1;
 pub const HBLANK_TIMING: u16 = 2;
-const FIFO_TIMING: u16 = 3;
+// sound fifo transfers on channels 1 and 2, video capture on channel 3
+const SPECIAL_TIMING: u16 = 3;
+
+const VIDEO_CAPTURE_START_LINE: u16 = 2;
+const VIDEO_CAPTURE_END_LINE: u16 = 162;
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct DmaChannels {
-  pub channels: [DmaChannel; 4]
+  pub channels: [DmaChannel; 4],
+  // the last value read by any channel, returned when reading from an invalid source
+  pub open_bus: u32,
+  // the channel that performed the last transfer, so a resumed channel starts with a non sequential access
+  pub last_channel: Option<usize>
 }
 
 pub enum AddressType {
@@ -26,7 +32,9 @@ impl DmaChannels {
         DmaChannel::new(1),
         DmaChannel::new(2),
         DmaChannel::new(3)
-      ]
+      ],
+      open_bus: 0,
+      last_channel: None
     }
   }
 
@@ -42,36 +50,37 @@ impl DmaChannels {
     for channel in &mut self.channels {
       if channel.dma_control.contains(DmaControlRegister::DMA_ENABLE)
         && channel.running
-        && channel.dma_control.dma_start_timing() == FIFO_TIMING
+        && channel.dma_control.dma_start_timing() == SPECIAL_TIMING
         && channel.destination_address == address {
           channel.pending = true;
         }
     }
   }
 
-  pub fn do_transfers(&mut self, cpu: &mut CPU) -> Vec<bool> {
-    let mut trigger_irqs = Vec::new();
-    for channel in &mut self.channels {
-      if channel.pending {
-        let should_trigger_irq = channel.transfer(cpu);
-        trigger_irqs.push(should_trigger_irq);
-        channel.pending = false;
-      } else {
-        trigger_irqs.push(false);
-      }
+  /// called at the start of every scanline. in video capture mode channel 3 transfers once per
+  /// line from line 2 to 161, then disables itself at line 162
+  pub fn notify_video_capture(&mut self, vcount: u16) {
+    let channel = &mut self.channels[3];
+
+    if !channel.dma_control.contains(DmaControlRegister::DMA_ENABLE) || channel.dma_control.dma_start_timing() != SPECIAL_TIMING {
+      return;
     }
 
-    trigger_irqs
+    if (VIDEO_CAPTURE_START_LINE..VIDEO_CAPTURE_END_LINE).contains(&vcount) {
+      channel.pending = true;
+    } else if vcount == VIDEO_CAPTURE_END_LINE {
+      channel.running = false;
+      channel.dma_control.remove(DmaControlRegister::DMA_ENABLE);
+    }
   }
 
-  pub fn has_pending_transfers(&self) -> bool {
-    for channel in self.channels {
-      if channel.pending {
-        return true;
-      }
-    }
+  /// the highest priority channel waiting for the bus. channel 0 has the highest priority
+  pub fn next_channel(&self) -> Option<usize> {
+    self.channels.iter().position(|channel| channel.pending)
+  }
 
-    false
+  pub fn has_pending_transfers(&self) -> bool {
+    self.channels.iter().any(|channel| channel.pending)
   }
 
   pub fn set_source_address(&mut self, channel_id: usize, value: u16, address_type: AddressType) {
@@ -95,4 +104,69 @@ impl DmaChannels {
       }
     }
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // channel 3 in video capture mode
+  fn capturing() -> DmaChannels {
+    let mut dma = DmaChannels::new();
+
+    dma.channels[3].word_count = 120;
+    dma.channels[3].write_control(0x8000 | 0x0200 | SPECIAL_TIMING << 12);
+
+    dma
+  }
+
+  #[test]
+  fn video_capture_transfers_on_lines_2_to_161() {
+    let mut dma = capturing();
+
+    for vcount in 0..VIDEO_CAPTURE_END_LINE {
+      dma.notify_video_capture(vcount);
+
+      assert_eq!(dma.channels[3].pending, vcount >= 2, "line {vcount}");
+
+      dma.channels[3].pending = false;
+    }
+  }
+
+  #[test]
+  fn video_capture_stops_at_line_162() {
+    let mut dma = capturing();
+
+    dma.notify_video_capture(VIDEO_CAPTURE_END_LINE);
+
+    assert!(!dma.channels[3].running);
+    assert!(!dma.channels[3].dma_control.contains(DmaControlRegister::DMA_ENABLE));
+
+    dma.notify_video_capture(VIDEO_CAPTURE_START_LINE);
+
+    assert!(!dma.channels[3].pending);
+  }
+
+  #[test]
+  fn video_capture_is_only_on_the_special_timing() {
+    let mut dma = DmaChannels::new();
+
+    dma.channels[3].write_control(0x8000 | 0x0200 | HBLANK_TIMING << 12);
+
+    dma.notify_video_capture(VIDEO_CAPTURE_START_LINE);
+
+    assert!(!dma.channels[3].pending);
+  }
+
+  #[test]
+  fn only_channel_3_keeps_the_gamepak_drq_bit() {
+    let mut dma = DmaChannels::new();
+
+    for channel in &mut dma.channels {
+      channel.write_control(DmaControlRegister::GAME_PAK_DRQ.bits());
+    }
+
+    let drq = dma.channels.map(|channel| channel.dma_control.contains(DmaControlRegister::GAME_PAK_DRQ));
+
+    assert_eq!(drq, [false, false, false, true]);
+  }
+}
@@ -0,0 +1,825 @@
+use std::f64::consts::PI;
+
+use super::{CPU, MemoryAccess, OperatingMode, PSRRegister};
+
+pub const BIOS_SIZE: usize = 0x4000;
+
+// the bios keeps its own copy of IF here for IntrWait and friends
+const BIOS_IF_ADDRESS: u32 = 0x300_7ff8;
+const RESET_FLAG_ADDRESS: u32 = 0x300_7ffa;
+
+const BIOS_CHECKSUM: u32 = 0xbaae_187f;
+
+// layout mirrors the real bios so games that peek at it see familiar opcodes
+const HLE_BIOS_CODE: &[(usize, u32)] = &[
+  (0x00, 0xe3a0_f302), // mov pc, #0x8000000
+  (0x08, 0xe1b0_f00e), // movs pc, lr
+  (0x18, 0xea00_0042), // b 0x128
+  (0x128, 0xe92d_500f), // stmfd sp!, {r0-r3, r12, lr}
+  (0x12c, 0xe3a0_0301), // mov r0, #0x4000000
+  (0x130, 0xe28f_e000), // add lr, pc, #0
+  (0x134, 0xe510_f004), // ldr pc, [r0, #-4]
+  (0x138, 0xe8bd_500f), // ldmfd sp!, {r0-r3, r12, lr}
+  (0x13c, 0xe25e_f004)  // subs pc, lr, #4
+];
+
+pub fn generate_hle_bios() -> Vec<u8> {
+  let mut bios = vec![0; BIOS_SIZE];
+
+  for (offset, opcode) in HLE_BIOS_CODE {
+    bios[*offset..*offset + 4].copy_from_slice(&opcode.to_le_bytes());
+  }
+
+  bios
+}
+
+impl CPU {
+  pub fn handle_swi(&mut self, comment: u8, return_address: u32) {
+    // println!("executing hle swi {:X}", comment);
+
+    match comment {
+      0x00 => {
+        self.swi_soft_reset();
+
+        return;
+      }
+      0x01 => self.swi_register_ram_reset(self.r[0]),
+      0x02 => self.is_halted = true,
+      0x03 => self.is_stopped = true,
+      0x04 | 0x05 => {
+        if comment == 0x05 {
+          self.r[0] = 1;
+          self.r[1] = 1;
+        }
+
+        if !self.swi_intr_wait(self.r[0] != 0, self.r[1] as u16) {
+          // execute the swi again once an interrupt has been serviced
+          let word_size = if self.cpsr.contains(PSRRegister::STATE_BIT) { 2 } else { 4 };
+          self.return_from_swi(return_address.wrapping_sub(word_size));
+
+          return;
+        }
+      }
+      0x06 => self.swi_div(self.r[0] as i32, self.r[1] as i32),
+      0x07 => self.swi_div(self.r[1] as i32, self.r[0] as i32),
+      0x08 => self.r[0] = Self::integer_sqrt(self.r[0]),
+      0x09 => {
+        let (result, r1, r3) = Self::arc_tan(self.r[0] as i16 as i32);
+
+        self.r[0] = result as u32;
+        self.r[1] = r1 as u32;
+        self.r[3] = r3 as u32;
+      }
+      0x0a => self.r[0] = Self::arc_tan2(self.r[0] as i16 as i32, self.r[1] as i16 as i32) as u32 & 0xffff,
+      0x0b => self.swi_cpu_set(self.r[0], self.r[1], self.r[2]),
+      0x0c => self.swi_cpu_fast_set(self.r[0], self.r[1], self.r[2]),
+      0x0d => self.r[0] = BIOS_CHECKSUM,
+      0x0e => self.swi_bg_affine_set(self.r[0], self.r[1], self.r[2]),
+      0x0f => self.swi_obj_affine_set(self.r[0], self.r[1], self.r[2], self.r[3]),
+      0x10 => self.swi_bit_unpack(self.r[0], self.r[1], self.r[2]),
+      0x11 | 0x12 => {
+        let data = self.lz77_decompress(self.r[0]);
+        self.write_decompressed(self.r[1], &data, if comment == 0x12 { 2 } else { 1 });
+      }
+      0x13 => {
+        let data = self.huffman_decompress(self.r[0]);
+        self.write_decompressed(self.r[1], &data, 4);
+      }
+      0x14 | 0x15 => {
+        let data = self.run_length_decompress(self.r[0]);
+        self.write_decompressed(self.r[1], &data, if comment == 0x15 { 2 } else { 1 });
+      }
+      0x16 | 0x17 => {
+        let data = self.diff_unfilter(self.r[0], false);
+        self.write_decompressed(self.r[1], &data, if comment == 0x17 { 2 } else { 1 });
+      }
+      0x18 => {
+        let data = self.diff_unfilter(self.r[0], true);
+        self.write_decompressed(self.r[1], &data, 2);
+      }
+      0x19 => {
+        let level = if self.r[0] != 0 { 0x200 } else { 0 };
+
+        self.apu.write_sound_bias((self.apu.sound_bias & !0x3fe) | level);
+      }
+      0x1f => self.swi_midi_key_to_freq(self.r[0], self.r[1], self.r[2]),
+      _ => {
+        // sound driver and multiboot calls aren't emulated, treat them as no-ops
+      }
+    }
+
+    self.return_from_swi(return_address);
+  }
+
+  fn return_from_swi(&mut self, address: u32) {
+    self.pc = address;
+
+    // the real bios leaves this opcode in the bios latch after returning from a swi
+    self.last_bios_opcode = 0xe3a0_2004;
+
+    if self.cpsr.contains(PSRRegister::STATE_BIT) {
+      self.reload_pipeline16();
+    } else {
+      self.reload_pipeline32();
+    }
+  }
+
+  fn swi_soft_reset(&mut self) {
+    let reset_flag = self.mem_read_8(RESET_FLAG_ADDRESS);
+
+    for address in (0x300_7e00..0x300_8000).step_by(4) {
+      self.mem_write_32(address, 0);
+    }
+
+    self.set_mode(OperatingMode::Supervisor);
+    self.r[13] = 0x300_7fe0;
+    self.r[14] = 0;
+    self.spsr = PSRRegister::from_bits_retain(0);
+
+    self.set_mode(OperatingMode::IRQ);
+    self.r[13] = 0x300_7fa0;
+    self.r[14] = 0;
+    self.spsr = PSRRegister::from_bits_retain(0);
+
+    self.set_mode(OperatingMode::System);
+
+    for r in 0..13 {
+      self.r[r] = 0;
+    }
+
+    self.r[13] = 0x300_7f00;
+    self.r[14] = 0;
+
+    self.cpsr = PSRRegister::from_bits_retain(OperatingMode::System as u32);
+
+    self.pc = if reset_flag != 0 { 0x200_0000 } else { 0x800_0000 };
+
+    self.reload_pipeline32();
+  }
+
+  fn swi_register_ram_reset(&mut self, flags: u32) {
+    if flags & 0b1 != 0 {
+      self.board_wram.fill(0);
+    }
+    if (flags >> 1) & 0b1 != 0 {
+      // the last 0x200 bytes hold the stacks and bios variables
+      self.chip_wram[..0x7e00].fill(0);
+      self.block_cache.clear();
+    }
+    if (flags >> 2) & 0b1 != 0 {
+      self.gpu.palette_ram.fill(0);
+    }
+    if (flags >> 3) & 0b1 != 0 {
+      self.gpu.vram[..0x1_8000].fill(0);
+    }
+    if (flags >> 4) & 0b1 != 0 {
+      self.gpu.oam_ram.fill(0);
+    }
+    if (flags >> 5) & 0b1 != 0 {
+      for address in (0x400_0120..0x400_0130).step_by(2) {
+        self.io_write_16(address, 0);
+      }
+      self.io_write_16(0x400_0134, 0x8000);
+    }
+    if (flags >> 6) & 0b1 != 0 {
+      for address in (0x400_0060..0x400_00a8).step_by(2) {
+        self.io_write_16(address, 0);
+      }
+    }
+    if (flags >> 7) & 0b1 != 0 {
+      for address in (0x400_0000..0x400_0060).step_by(2) {
+        self.io_write_16(address, 0);
+      }
+      for address in (0x400_00b0..0x400_0110).step_by(2) {
+        self.io_write_16(address, 0);
+      }
+      self.io_write_16(0x400_0200, 0);
+      self.io_write_16(0x400_0202, 0xffff);
+      self.io_write_16(0x400_0204, 0);
+      self.io_write_16(0x400_0208, 0);
+    }
+
+    // forced blank is always turned on
+    self.gpu.write_dispcnt(0x80);
+  }
+
+  // returns true once one of the requested interrupts has been serviced
+  fn swi_intr_wait(&mut self, discard_old: bool, flags: u16) -> bool {
+    self.interrupt_master_enable = true;
+
+    let mut bios_if = self.mem_read_16(BIOS_IF_ADDRESS);
+
+    // only discard old flags on the first pass, not when re-entering after an irq
+    if discard_old && !self.hle_intr_wait {
+      bios_if &= !flags;
+    }
+
+    if bios_if & flags != 0 {
+      self.mem_write_16(BIOS_IF_ADDRESS, bios_if & !flags);
+      self.hle_intr_wait = false;
+
+      return true;
+    }
+
+    self.mem_write_16(BIOS_IF_ADDRESS, bios_if);
+
+    self.hle_intr_wait = true;
+    self.is_halted = true;
+
+    false
+  }
+
+  fn swi_div(&mut self, numerator: i32, denominator: i32) {
+    if denominator == 0 {
+      // the real bios never returns here, so pick something that won't crash the game
+      self.r[0] = if numerator < 0 { -1i32 as u32 } else { 1 };
+      self.r[1] = numerator as u32;
+      self.r[3] = 1;
+
+      return;
+    }
+
+    let quotient = numerator.wrapping_div(denominator);
+
+    self.r[0] = quotient as u32;
+    self.r[1] = numerator.wrapping_rem(denominator) as u32;
+    self.r[3] = quotient.unsigned_abs();
+  }
+
+  fn integer_sqrt(value: u32) -> u32 {
+    let mut result = (value as f64).sqrt() as u32;
+
+    // guard against rounding at the edges of the f64 estimate
+    while result * result > value {
+      result -= 1;
+    }
+    while (result + 1) as u64 * (result + 1) as u64 <= value as u64 {
+      result += 1;
+    }
+
+    result
+  }
+
+  fn arc_tan(tan: i32) -> (i32, i32, i32) {
+    let a = -(tan.wrapping_mul(tan) >> 14);
+
+    let mut b = (0xa9i32.wrapping_mul(a) >> 14) + 0x390;
+
+    for coefficient in [0x91c, 0xfb6, 0x16aa, 0x2081, 0x3651, 0xa2f9] {
+      b = (b.wrapping_mul(a) >> 14) + coefficient;
+    }
+
+    (tan.wrapping_mul(b) >> 16, a, b)
+  }
+
+  fn arc_tan2(x: i32, y: i32) -> i32 {
+    if y == 0 {
+      return if x >= 0 { 0 } else { 0x8000 };
+    }
+    if x == 0 {
+      return if y >= 0 { 0x4000 } else { 0xc000 };
+    }
+
+    let (tan_y, _, _) = Self::arc_tan((y << 14) / x);
+
+    if y >= 0 {
+      if x >= 0 {
+        if x >= y {
+          return tan_y;
+        }
+      } else if -x >= y {
+        return tan_y + 0x8000;
+      }
+    } else if x <= 0 {
+      if -x > -y {
+        return tan_y + 0x8000;
+      }
+    } else if x >= -y {
+      return tan_y + 0x1_0000;
+    }
+
+    let (tan_x, _, _) = Self::arc_tan((x << 14) / y);
+
+    if y >= 0 {
+      0x4000 - tan_x
+    } else {
+      0xc000 - tan_x
+    }
+  }
+
+  fn swi_cpu_set(&mut self, source: u32, destination: u32, control: u32) {
+    // the bios refuses to copy out of its own address space
+    if source & 0xe00_0000 == 0 {
+      return;
+    }
+
+    let count = control & 0x1f_ffff;
+    let fixed_source = (control >> 24) & 0b1 == 1;
+    let word_size = if (control >> 26) & 0b1 == 1 { 4 } else { 2 };
+
+    let mut source = source & !(word_size - 1);
+    let mut destination = destination & !(word_size - 1);
+
+    let mut access = MemoryAccess::NonSequential;
+
+    for _ in 0..count {
+      if word_size == 4 {
+        let value = self.load_32(source, access);
+        self.store_32(destination, value, access);
+      } else {
+        let value = self.load_16(source, access);
+        self.store_16(destination, value, access);
+      }
+
+      access = MemoryAccess::Sequential;
+
+      if !fixed_source {
+        source = source.wrapping_add(word_size);
+      }
+      destination = destination.wrapping_add(word_size);
+    }
+  }
+
+  fn swi_cpu_fast_set(&mut self, source: u32, destination: u32, control: u32) {
+    if source & 0xe00_0000 == 0 {
+      return;
+    }
+
+    // transfers happen in blocks of 8 words
+    let count = ((control & 0x1f_ffff) + 7) & !0b111;
+    let fixed_source = (control >> 24) & 0b1 == 1;
+
+    let mut source = source & !(0b11);
+    let mut destination = destination & !(0b11);
+
+    let mut access = MemoryAccess::NonSequential;
+
+    for _ in 0..count {
+      let value = self.load_32(source, access);
+      self.store_32(destination, value, access);
+
+      access = MemoryAccess::Sequential;
+
+      if !fixed_source {
+        source = source.wrapping_add(4);
+      }
+      destination = destination.wrapping_add(4);
+    }
+  }
+
+  fn swi_bg_affine_set(&mut self, source: u32, destination: u32, count: u32) {
+    for i in 0..count {
+      let source = source + i * 20;
+      let destination = destination + i * 16;
+
+      let origin_x = self.load_32(source, MemoryAccess::NonSequential) as i32 as f64 / 256.0;
+      let origin_y = self.load_32(source + 4, MemoryAccess::Sequential) as i32 as f64 / 256.0;
+      let display_x = self.load_16(source + 8, MemoryAccess::Sequential) as i16 as f64;
+      let display_y = self.load_16(source + 10, MemoryAccess::Sequential) as i16 as f64;
+      let scale_x = self.load_16(source + 12, MemoryAccess::Sequential) as i16 as f64 / 256.0;
+      let scale_y = self.load_16(source + 14, MemoryAccess::Sequential) as i16 as f64 / 256.0;
+      let theta = (self.load_16(source + 16, MemoryAccess::Sequential) >> 8) as f64 / 128.0 * PI;
+
+      let pa = theta.cos() * scale_x;
+      let pb = -theta.sin() * scale_x;
+      let pc = theta.sin() * scale_y;
+      let pd = theta.cos() * scale_y;
+
+      let start_x = origin_x - (pa * display_x + pb * display_y);
+      let start_y = origin_y - (pc * display_x + pd * display_y);
+
+      self.store_16(destination, (pa * 256.0) as i16 as u16, MemoryAccess::NonSequential);
+      self.store_16(destination + 2, (pb * 256.0) as i16 as u16, MemoryAccess::Sequential);
+      self.store_16(destination + 4, (pc * 256.0) as i16 as u16, MemoryAccess::Sequential);
+      self.store_16(destination + 6, (pd * 256.0) as i16 as u16, MemoryAccess::Sequential);
+      self.store_32(destination + 8, (start_x * 256.0) as i32 as u32, MemoryAccess::Sequential);
+      self.store_32(destination + 12, (start_y * 256.0) as i32 as u32, MemoryAccess::Sequential);
+    }
+  }
+
+  fn swi_obj_affine_set(&mut self, source: u32, destination: u32, count: u32, offset: u32) {
+    for i in 0..count {
+      let source = source + i * 8;
+      let destination = destination + i * offset * 4;
+
+      let scale_x = self.load_16(source, MemoryAccess::NonSequential) as i16 as f64 / 256.0;
+      let scale_y = self.load_16(source + 2, MemoryAccess::Sequential) as i16 as f64 / 256.0;
+      let theta = (self.load_16(source + 4, MemoryAccess::Sequential) >> 8) as f64 / 128.0 * PI;
+
+      let pa = theta.cos() * scale_x;
+      let pb = -theta.sin() * scale_x;
+      let pc = theta.sin() * scale_y;
+      let pd = theta.cos() * scale_y;
+
+      self.store_16(destination, (pa * 256.0) as i16 as u16, MemoryAccess::NonSequential);
+      self.store_16(destination + offset, (pb * 256.0) as i16 as u16, MemoryAccess::NonSequential);
+      self.store_16(destination + offset * 2, (pc * 256.0) as i16 as u16, MemoryAccess::NonSequential);
+      self.store_16(destination + offset * 3, (pd * 256.0) as i16 as u16, MemoryAccess::NonSequential);
+    }
+  }
+
+  fn swi_bit_unpack(&mut self, source: u32, destination: u32, info: u32) {
+    let length = self.load_16(info, MemoryAccess::NonSequential) as u32;
+    let source_width = self.load_8(info + 2, MemoryAccess::Sequential) as u32;
+    let destination_width = self.load_8(info + 3, MemoryAccess::Sequential) as u32;
+    let data_offset = self.load_32(info + 4, MemoryAccess::Sequential);
+
+    if !matches!(source_width, 1 | 2 | 4 | 8) || !matches!(destination_width, 1 | 2 | 4 | 8 | 16 | 32) {
+      return;
+    }
+
+    let offset = data_offset & 0x7fff_ffff;
+    let offset_zero = (data_offset >> 31) & 0b1 == 1;
+
+    let source_mask = (1 << source_width) - 1;
+    let destination_mask = if destination_width == 32 { u32::MAX } else { (1 << destination_width) - 1 };
+
+    let mut destination = destination & !(0b11);
+    let mut word = 0;
+    let mut bits = 0;
+
+    for i in 0..length {
+      let byte = self.load_8(source + i, MemoryAccess::Sequential) as u32;
+
+      for shift in (0..8).step_by(source_width as usize) {
+        let mut value = (byte >> shift) & source_mask;
+
+        if value != 0 || offset_zero {
+          value = value.wrapping_add(offset);
+        }
+
+        word |= (value & destination_mask) << bits;
+        bits += destination_width;
+
+        if bits == 32 {
+          self.store_32(destination, word, MemoryAccess::Sequential);
+
+          destination += 4;
+          word = 0;
+          bits = 0;
+        }
+      }
+    }
+  }
+
+  fn lz77_decompress(&mut self, source: u32) -> Vec<u8> {
+    let header = self.load_32(source & !(0b11), MemoryAccess::NonSequential);
+    let size = (header >> 8) as usize;
+
+    let mut source = (source & !(0b11)) + 4;
+    let mut data = Vec::with_capacity(size);
+
+    while data.len() < size {
+      let flags = self.read_source_byte(&mut source);
+
+      for bit in (0..8).rev() {
+        if data.len() >= size {
+          break;
+        }
+
+        if (flags >> bit) & 0b1 == 1 {
+          let upper = self.read_source_byte(&mut source) as usize;
+          let lower = self.read_source_byte(&mut source) as usize;
+
+          let length = (upper >> 4) + 3;
+          let displacement = ((upper & 0xf) << 8 | lower) + 1;
+
+          for _ in 0..length {
+            let byte = if displacement <= data.len() { data[data.len() - displacement] } else { 0 };
+            data.push(byte);
+          }
+        } else {
+          let byte = self.read_source_byte(&mut source);
+          data.push(byte);
+        }
+      }
+    }
+
+    data.truncate(size);
+
+    data
+  }
+
+  fn huffman_decompress(&mut self, source: u32) -> Vec<u8> {
+    let source = source & !(0b11);
+    let header = self.load_32(source, MemoryAccess::NonSequential);
+
+    let data_bits = header & 0xf;
+    let size = (header >> 8) as usize;
+
+    let mut data = Vec::with_capacity(size);
+
+    if data_bits != 4 && data_bits != 8 {
+      return data;
+    }
+
+    let tree_size = self.load_8(source + 4, MemoryAccess::Sequential) as u32;
+    let tree_root = source + 5;
+
+    let mut bitstream = source + 4 + (tree_size + 1) * 2;
+
+    let mut node_address = tree_root;
+    let mut node = self.load_8(node_address, MemoryAccess::Sequential);
+
+    let mut word: u32 = 0;
+    let mut word_bits = 0;
+
+    while data.len() < size {
+      let bits = self.load_32(bitstream, MemoryAccess::Sequential);
+      bitstream += 4;
+
+      for bit in (0..32).rev() {
+        let offset = (node & 0x3f) as u32;
+        let next_address = (node_address & !(0b1)) + offset * 2 + 2;
+
+        let (child_address, is_data) = if (bits >> bit) & 0b1 == 0 {
+          (next_address, (node >> 7) & 0b1 == 1)
+        } else {
+          (next_address + 1, (node >> 6) & 0b1 == 1)
+        };
+
+        if is_data {
+          let value = self.load_8(child_address, MemoryAccess::Sequential) as u32;
+
+          word |= (value & ((1 << data_bits) - 1)) << word_bits;
+          word_bits += data_bits;
+
+          if word_bits == 32 {
+            data.extend_from_slice(&word.to_le_bytes());
+
+            word = 0;
+            word_bits = 0;
+
+            if data.len() >= size {
+              break;
+            }
+          }
+
+          node_address = tree_root;
+        } else {
+          node_address = child_address;
+        }
+
+        node = self.load_8(node_address, MemoryAccess::Sequential);
+      }
+    }
+
+    data.truncate(size);
+
+    data
+  }
+
+  fn run_length_decompress(&mut self, source: u32) -> Vec<u8> {
+    let header = self.load_32(source & !(0b11), MemoryAccess::NonSequential);
+    let size = (header >> 8) as usize;
+
+    let mut source = (source & !(0b11)) + 4;
+    let mut data = Vec::with_capacity(size);
+
+    while data.len() < size {
+      let flag = self.read_source_byte(&mut source);
+
+      if (flag >> 7) & 0b1 == 1 {
+        let length = (flag & 0x7f) as usize + 3;
+        let byte = self.read_source_byte(&mut source);
+
+        data.extend(std::iter::repeat_n(byte, length));
+      } else {
+        let length = (flag & 0x7f) as usize + 1;
+
+        for _ in 0..length {
+          let byte = self.read_source_byte(&mut source);
+          data.push(byte);
+        }
+      }
+    }
+
+    data.truncate(size);
+
+    data
+  }
+
+  fn diff_unfilter(&mut self, source: u32, is_16bit: bool) -> Vec<u8> {
+    let header = self.load_32(source & !(0b11), MemoryAccess::NonSequential);
+    let size = (header >> 8) as usize;
+
+    let mut source = (source & !(0b11)) + 4;
+    let mut data = Vec::with_capacity(size);
+
+    if is_16bit {
+      let mut accumulator: u16 = 0;
+
+      while data.len() < size {
+        accumulator = accumulator.wrapping_add(self.load_16(source, MemoryAccess::Sequential));
+        source += 2;
+
+        data.extend_from_slice(&accumulator.to_le_bytes());
+      }
+    } else {
+      let mut accumulator: u8 = 0;
+
+      while data.len() < size {
+        let byte = self.read_source_byte(&mut source);
+        accumulator = accumulator.wrapping_add(byte);
+
+        data.push(accumulator);
+      }
+    }
+
+    data.truncate(size);
+
+    data
+  }
+
+  fn read_source_byte(&mut self, source: &mut u32) -> u8 {
+    let byte = self.load_8(*source, MemoryAccess::Sequential);
+    *source += 1;
+
+    byte
+  }
+
+  // vram can't take byte writes, so the vram variants of each call write halfwords. huffman
+  // always writes whole words
+  fn write_decompressed(&mut self, destination: u32, data: &[u8], unit_size: usize) {
+    let destination = destination & !(unit_size as u32 - 1);
+
+    for (i, chunk) in data.chunks(unit_size).enumerate() {
+      let address = destination.wrapping_add((i * unit_size) as u32);
+      let value = chunk.iter().rev().fold(0u32, |value, byte| value << 8 | *byte as u32);
+
+      match unit_size {
+        4 => self.store_32(address, value, MemoryAccess::Sequential),
+        2 => self.store_16(address, value as u16, MemoryAccess::Sequential),
+        _ => self.store_8(address, value as u8, MemoryAccess::Sequential)
+      }
+    }
+  }
+
+  fn swi_midi_key_to_freq(&mut self, wave_data: u32, midi_key: u32, fine_pitch: u32) {
+    let frequency = self.load_32(wave_data + 4, MemoryAccess::NonSequential) as f64;
+
+    let exponent = (180.0 - midi_key as f64 - fine_pitch as f64 / 256.0) / 12.0;
+
+    self.r[0] = (frequency / 2f64.powf(exponent)) as u32;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  const SOURCE: u32 = 0x200_0000;
+  const DESTINATION: u32 = 0x200_1000;
+
+  fn cpu() -> CPU {
+    CPU::new(HeapRb::<f32>::new(1024).split().0)
+  }
+
+  fn write_bytes(cpu: &mut CPU, address: u32, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+      cpu.store_8(address + i as u32, *byte, MemoryAccess::NonSequential);
+    }
+  }
+
+  fn read_bytes(cpu: &mut CPU, address: u32, len: u32) -> Vec<u8> {
+    (0..len).map(|i| cpu.load_8(address + i, MemoryAccess::NonSequential)).collect()
+  }
+
+  fn swi(cpu: &mut CPU, comment: u8, r0: u32, r1: u32, r2: u32) {
+    cpu.r[0] = r0;
+    cpu.r[1] = r1;
+    cpu.r[2] = r2;
+
+    cpu.handle_swi(comment, 0x800_0000);
+  }
+
+  #[test]
+  fn lz77_copies_back_references() {
+    let mut cpu = cpu();
+
+    write_bytes(&mut cpu, SOURCE, &[0x10, 6, 0, 0, 0x10, b'A', b'B', b'C', 0x00, 0x02]);
+
+    swi(&mut cpu, 0x11, SOURCE, DESTINATION, 0);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION, 6), b"ABCABC");
+  }
+
+  #[test]
+  fn run_length_expands_runs_and_literals() {
+    let mut cpu = cpu();
+
+    write_bytes(&mut cpu, SOURCE, &[0x30, 7, 0, 0, 0x82, b'x', 0x01, b'y', b'z']);
+
+    swi(&mut cpu, 0x14, SOURCE, DESTINATION, 0);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION, 7), b"xxxxxyz");
+  }
+
+  #[test]
+  fn diff8_adds_up_the_deltas() {
+    let mut cpu = cpu();
+
+    write_bytes(&mut cpu, SOURCE, &[0x81, 3, 0, 0, 1, 1, 1]);
+
+    swi(&mut cpu, 0x16, SOURCE, DESTINATION, 0);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION, 3), [1, 2, 3]);
+  }
+
+  #[test]
+  fn huffman_writes_whole_words_to_vram() {
+    let mut cpu = cpu();
+
+    // 8 bit data, one node with two data children 0x11 and 0x22, bitstream 0110
+    write_bytes(&mut cpu, SOURCE, &[0x28, 4, 0, 0, 1, 0xc0, 0x11, 0x22]);
+    cpu.store_32(SOURCE + 8, 0x6000_0000, MemoryAccess::NonSequential);
+
+    swi(&mut cpu, 0x13, SOURCE, 0x600_0000, 0);
+
+    assert_eq!(cpu.load_32(0x600_0000, MemoryAccess::NonSequential), 0x1122_2211);
+  }
+
+  #[test]
+  fn div_rounds_towards_zero() {
+    let mut cpu = cpu();
+
+    swi(&mut cpu, 0x06, 7, -2i32 as u32, 0);
+
+    assert_eq!(cpu.r[0] as i32, -3);
+    assert_eq!(cpu.r[1], 1);
+    assert_eq!(cpu.r[3], 3);
+
+    // the arm variant swaps the operands
+    swi(&mut cpu, 0x07, -2i32 as u32, 7, 0);
+
+    assert_eq!(cpu.r[0] as i32, -3);
+  }
+
+  #[test]
+  fn sqrt_rounds_down() {
+    let mut cpu = cpu();
+
+    swi(&mut cpu, 0x08, 0x10000, 0, 0);
+    assert_eq!(cpu.r[0], 0x100);
+
+    swi(&mut cpu, 0x08, 15, 0, 0);
+    assert_eq!(cpu.r[0], 3);
+
+    swi(&mut cpu, 0x08, u32::MAX, 0, 0);
+    assert_eq!(cpu.r[0], 0xffff);
+  }
+
+  #[test]
+  fn arc_tan2_covers_the_axes() {
+    let mut cpu = cpu();
+
+    for (x, y, angle) in [(1, 0, 0), (0, 1, 0x4000), (-1, 0, 0x8000), (0, -1, 0xc000)] {
+      swi(&mut cpu, 0x0a, x as u32, y as u32, 0);
+
+      assert_eq!(cpu.r[0], angle, "arctan2({}, {})", x, y);
+    }
+
+    swi(&mut cpu, 0x0a, 0x100, 0x100, 0);
+
+    assert_eq!(cpu.r[0], 0x2000);
+  }
+
+  #[test]
+  fn cpu_set_copies_and_fills() {
+    let mut cpu = cpu();
+
+    write_bytes(&mut cpu, SOURCE, &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // four halfwords
+    swi(&mut cpu, 0x0b, SOURCE, DESTINATION, 4);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION, 8), [1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // two words filled from the first one
+    swi(&mut cpu, 0x0b, SOURCE, DESTINATION + 0x10, 2 | 1 << 24 | 1 << 26);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION + 0x10, 8), [1, 2, 3, 4, 1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn cpu_fast_set_copies_in_blocks_of_eight_words() {
+    let mut cpu = cpu();
+
+    write_bytes(&mut cpu, SOURCE, &(0..32).collect::<Vec<u8>>());
+
+    // a count of one still copies a whole block
+    swi(&mut cpu, 0x0c, SOURCE, DESTINATION, 1);
+
+    assert_eq!(read_bytes(&mut cpu, DESTINATION, 32), (0..32).collect::<Vec<u8>>());
+  }
+
+  #[test]
+  fn cpu_set_wraps_around_the_address_space() {
+    let mut cpu = cpu();
+
+    swi(&mut cpu, 0x0b, 0xffff_fff8, 0xffff_fff8, 4 | 1 << 26);
+    swi(&mut cpu, 0x0c, 0xffff_fff8, 0xffff_fff8, 8);
+  }
+}
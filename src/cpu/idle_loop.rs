@@ -0,0 +1,154 @@
+// detects busy-wait loops, like polling VCOUNT or an irq flag, so the cpu can skip ahead to the
+// next scheduler event instead of spinning through them. a loop counts as idle once execution
+// arrives back at its start with exactly the same register state and nothing was written to
+// memory during the iteration, because then every following iteration will do the same thing
+// until an event changes what the loop reads. that doesn't hold for loops reading something that
+// changes when it's read, like the serial fifos or the save chip's status, so those never count.
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::BackupMedia;
+
+use super::CPU;
+
+// only short backwards branches are considered
+const MAX_IDLE_LOOP_SIZE: u32 = 0x20;
+
+/// set per game in the game database, for games where skipping idle loops causes problems
+#[derive(Clone, Serialize, Deserialize)]
+pub enum IdleLoopOverride {
+  // the detector misfires on this game, so turn it off entirely
+  Disabled,
+  // loops starting at these addresses are never treated as idle
+  Ignore(Vec<u32>)
+}
+
+pub struct IdleLoopDetector {
+  pub enabled: bool,
+  pub ignored_addresses: Vec<u32>,
+  loop_address: Option<u32>,
+  snapshot: [u32; 16],
+  pub memory_written: bool,
+  volatile_read: bool,
+  pub detected: bool
+}
+
+impl Default for IdleLoopDetector {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl IdleLoopDetector {
+  pub fn new() -> Self {
+    Self {
+      enabled: true,
+      ignored_addresses: Vec::new(),
+      loop_address: None,
+      snapshot: [0; 16],
+      memory_written: false,
+      volatile_read: false,
+      detected: false
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.loop_address = None;
+    self.memory_written = false;
+    self.detected = false;
+  }
+
+  pub fn apply_overrides(&mut self, idle_loop_override: Option<&IdleLoopOverride>) {
+    self.enabled = true;
+    self.ignored_addresses.clear();
+
+    match idle_loop_override {
+      Some(IdleLoopOverride::Disabled) => self.enabled = false,
+      Some(IdleLoopOverride::Ignore(addresses)) => self.ignored_addresses.extend_from_slice(addresses),
+      None => ()
+    }
+  }
+}
+
+impl CPU {
+  pub fn set_idle_loop_detection(&mut self, enabled: bool) {
+    self.idle_loop.enabled = enabled;
+    self.idle_loop.reset();
+  }
+
+  pub fn ignore_idle_loop(&mut self, address: u32) {
+    self.idle_loop.ignored_addresses.push(address);
+  }
+
+  /// called whenever a branch is taken, with the address of the branch and its target
+  pub fn check_idle_loop(&mut self, branch_address: u32, target: u32) {
+    if !self.idle_loop.enabled {
+      return;
+    }
+
+    if target > branch_address || branch_address - target > MAX_IDLE_LOOP_SIZE || self.idle_loop.ignored_addresses.contains(&target) {
+      self.idle_loop.loop_address = None;
+
+      return;
+    }
+
+    let mut snapshot = [0; 16];
+
+    snapshot[..15].copy_from_slice(&self.r);
+    snapshot[15] = self.cpsr.bits();
+
+    if self.idle_loop.loop_address == Some(target)
+      && !self.idle_loop.memory_written
+      && !self.idle_loop.volatile_read
+      && snapshot == self.idle_loop.snapshot
+    {
+      self.idle_loop.detected = true;
+    }
+
+    self.idle_loop.loop_address = Some(target);
+    self.idle_loop.snapshot = snapshot;
+    self.idle_loop.memory_written = false;
+    self.idle_loop.volatile_read = false;
+  }
+
+  /// called for every load, to rule out loops whose reads change what they read next time
+  pub fn check_idle_loop_read(&mut self, address: u32) {
+    let volatile = match address {
+      // timer counters count up between reads, so a loop waiting on one isn't idle
+      0x400_0100..=0x400_010f => true,
+      // the uart fifo and joy bus receive registers, which are consumed by reading them
+      0x400_012a | 0x400_0150..=0x400_0153 => true,
+      // the eeprom shifts out a bit on every read
+      0xd00_0000..=0xdff_ffff => matches!(self.cartridge.backup, BackupMedia::Eeprom(_)),
+      // the rtc and other gpio devices
+      0x800_00c4..=0x800_00c9 => self.cartridge.gpio.readable,
+      // flash status bits toggle on every read, and the tilt sensor latches its samples
+      0xe00_0000..=0xfff_ffff => true,
+      _ => false
+    };
+
+    if volatile {
+      self.idle_loop.volatile_read = true;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  #[test]
+  fn timer_reads_are_volatile() {
+    let mut cpu = CPU::new(HeapRb::<f32>::new(1024).split().0);
+
+    cpu.check_idle_loop_read(0x300_0000);
+
+    assert!(!cpu.idle_loop.volatile_read);
+
+    cpu.check_idle_loop_read(0x400_0104);
+
+    assert!(cpu.idle_loop.volatile_read);
+  }
+}
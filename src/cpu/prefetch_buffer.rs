@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+use super::cycle_lookup_tables::CycleLookupTables;
+
+// the buffer holds up to 8 halfwords
+const PREFETCH_BUFFER_SIZE: u32 = 8;
+
+/// gamepak prefetch unit. while the cpu isn't using the gamepak bus it keeps reading
+/// sequential halfwords ahead of the last opcode fetched from rom, so that later
+/// opcode fetches can be served without paying the rom waitstates.
+#[derive(Serialize, Deserialize, Default)]
+pub struct PrefetchBuffer {
+  pub enabled: bool,
+  active: bool,
+  // address of the next halfword the cpu is expected to fetch
+  head: u32,
+  // address of the halfword currently being fetched by the prefetch unit
+  tail: u32,
+  count: u32,
+  // cycles left until the halfword at tail arrives in the buffer
+  countdown: u32
+}
+
+impl PrefetchBuffer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn is_gamepak_rom(address: u32) -> bool {
+    (0x800_0000..=0xdff_ffff).contains(&address)
+  }
+
+  pub fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+
+    if !enabled {
+      self.stop();
+    }
+  }
+
+  /// any other use of the gamepak bus interrupts the prefetch unit and empties the buffer
+  pub fn stop(&mut self) {
+    self.active = false;
+    self.count = 0;
+  }
+
+  /// starts prefetching at address after an opcode fetch missed the buffer
+  pub fn restart(&mut self, address: u32, cycle_luts: &CycleLookupTables) {
+    self.active = Self::is_gamepak_rom(address);
+    self.head = address;
+    self.tail = address;
+    self.count = 0;
+    self.countdown = Self::sequential_cycles(address, cycle_luts);
+  }
+
+  /// advances the prefetch unit by cycles where the gamepak bus was free
+  pub fn step(&mut self, mut cycles: u32, cycle_luts: &CycleLookupTables) {
+    if !self.active {
+      return;
+    }
+
+    while cycles > 0 && self.count < PREFETCH_BUFFER_SIZE {
+      if cycles < self.countdown {
+        self.countdown -= cycles;
+        break;
+      }
+
+      cycles -= self.countdown;
+
+      self.count += 1;
+      self.tail = self.tail.wrapping_add(2);
+      self.countdown = Self::sequential_cycles(self.tail, cycle_luts);
+    }
+  }
+
+  /// consumes the halfword at address from the buffer. returns None on a miss, otherwise the
+  /// number of cycles the cpu had to wait for it (0 if it was already buffered)
+  pub fn read(&mut self, address: u32, cycle_luts: &CycleLookupTables) -> Option<u32> {
+    if !self.active || address != self.head {
+      return None;
+    }
+
+    self.head = self.head.wrapping_add(2);
+
+    if self.count > 0 {
+      self.count -= 1;
+
+      return Some(0);
+    }
+
+    // the halfword is still being fetched, so wait for it to arrive
+    let cycles = self.countdown;
+
+    self.tail = self.tail.wrapping_add(2);
+    self.countdown = Self::sequential_cycles(self.tail, cycle_luts);
+
+    Some(cycles)
+  }
+
+  fn sequential_cycles(address: u32, cycle_luts: &CycleLookupTables) -> u32 {
+    cycle_luts.s_cycles_16[((address >> 24) & 0xf) as usize]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // rom taking 3 cycles per sequential halfword
+  fn cycle_luts() -> CycleLookupTables {
+    let mut cycle_luts = CycleLookupTables::new();
+
+    cycle_luts.s_cycles_16[0x8] = 3;
+
+    cycle_luts
+  }
+
+  fn started_at(address: u32, cycle_luts: &CycleLookupTables) -> PrefetchBuffer {
+    let mut prefetch_buffer = PrefetchBuffer::new();
+
+    prefetch_buffer.set_enabled(true);
+    prefetch_buffer.restart(address, cycle_luts);
+
+    prefetch_buffer
+  }
+
+  #[test]
+  fn fills_a_halfword_per_sequential_access() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x800_0000, &cycle_luts);
+
+    prefetch_buffer.step(7, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.count, 2);
+
+    // the next halfword is 2 cycles away from arriving
+    assert_eq!(prefetch_buffer.read(0x800_0000, &cycle_luts), Some(0));
+    assert_eq!(prefetch_buffer.read(0x800_0002, &cycle_luts), Some(0));
+    assert_eq!(prefetch_buffer.read(0x800_0004, &cycle_luts), Some(2));
+
+    // and the one after that starts over
+    prefetch_buffer.step(1, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.read(0x800_0006, &cycle_luts), Some(2));
+  }
+
+  #[test]
+  fn holds_at_most_8_halfwords() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x800_0000, &cycle_luts);
+
+    prefetch_buffer.step(1000, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.count, PREFETCH_BUFFER_SIZE);
+
+    for i in 0..PREFETCH_BUFFER_SIZE {
+      assert_eq!(prefetch_buffer.read(0x800_0000 + i * 2, &cycle_luts), Some(0));
+    }
+
+    assert_eq!(prefetch_buffer.read(0x800_0010, &cycle_luts), Some(3));
+  }
+
+  #[test]
+  fn misses_on_anything_but_the_next_halfword() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x800_0000, &cycle_luts);
+
+    prefetch_buffer.step(10, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.read(0x800_0002, &cycle_luts), None);
+    assert_eq!(prefetch_buffer.read(0x800_0000, &cycle_luts), Some(0));
+  }
+
+  #[test]
+  fn stopping_empties_the_buffer() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x800_0000, &cycle_luts);
+
+    prefetch_buffer.step(10, &cycle_luts);
+    prefetch_buffer.stop();
+
+    assert_eq!(prefetch_buffer.count, 0);
+    assert_eq!(prefetch_buffer.read(0x800_0000, &cycle_luts), None);
+
+    // nothing comes in until an opcode fetch restarts it
+    prefetch_buffer.step(10, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.count, 0);
+  }
+
+  #[test]
+  fn disabling_stops_it() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x800_0000, &cycle_luts);
+
+    prefetch_buffer.step(10, &cycle_luts);
+    prefetch_buffer.set_enabled(false);
+
+    assert_eq!(prefetch_buffer.read(0x800_0000, &cycle_luts), None);
+  }
+
+  #[test]
+  fn only_prefetches_from_rom() {
+    let cycle_luts = cycle_luts();
+    let mut prefetch_buffer = started_at(0x300_0000, &cycle_luts);
+
+    prefetch_buffer.step(10, &cycle_luts);
+
+    assert_eq!(prefetch_buffer.count, 0);
+    assert_eq!(prefetch_buffer.read(0x300_0000, &cycle_luts), None);
+  }
+}
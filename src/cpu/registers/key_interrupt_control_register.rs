@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+
+use super::key_input_register::KeyInputRegister;
+
+bitflags! {
+  #[derive(Clone, Copy, Serialize, Deserialize)]
+  pub struct KeyInterruptControlRegister: u16 {
+    const IRQ_ENABLE = 0b1 << 14;
+    const IRQ_CONDITION = 0b1 << 15;
+  }
+}
+
+impl KeyInterruptControlRegister {
+  pub fn write(&mut self, value: u16) {
+    *self = Self::from_bits_retain(value & 0xc3ff);
+  }
+
+  pub fn irq_triggered(&self, key_input: KeyInputRegister) -> bool {
+    if !self.contains(Self::IRQ_ENABLE) {
+      return false;
+    }
+
+    let selected = self.bits() & 0x3ff;
+    // keys are active low in KEYINPUT
+    let pressed = !key_input.bits() & 0x3ff;
+
+    if self.contains(Self::IRQ_CONDITION) {
+      // logical AND, all of the selected keys have to be pressed
+      selected != 0 && pressed & selected == selected
+    } else {
+      // logical OR, any of the selected keys
+      pressed & selected != 0
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // KEYINPUT with the given keys held down
+  fn pressed(keys: KeyInputRegister) -> KeyInputRegister {
+    KeyInputRegister::from_bits_retain(0x3ff & !keys.bits())
+  }
+
+  fn keycnt(value: u16) -> KeyInterruptControlRegister {
+    let mut keycnt = KeyInterruptControlRegister::from_bits_retain(0);
+
+    keycnt.write(value);
+
+    keycnt
+  }
+
+  #[test]
+  fn or_condition_takes_any_selected_key() {
+    let keycnt = keycnt(0x4003);
+
+    assert!(keycnt.irq_triggered(pressed(KeyInputRegister::ButtonB)));
+    assert!(!keycnt.irq_triggered(pressed(KeyInputRegister::Start)));
+  }
+
+  #[test]
+  fn and_condition_takes_every_selected_key() {
+    let keycnt = keycnt(0xc00f);
+
+    assert!(!keycnt.irq_triggered(pressed(KeyInputRegister::ButtonA | KeyInputRegister::ButtonB)));
+    assert!(keycnt.irq_triggered(pressed(KeyInputRegister::all())));
+  }
+
+  #[test]
+  fn and_condition_with_no_keys_selected_never_triggers() {
+    assert!(!keycnt(0xc000).irq_triggered(pressed(KeyInputRegister::all())));
+  }
+
+  #[test]
+  fn nothing_is_raised_while_disabled() {
+    assert!(!keycnt(0x0003).irq_triggered(pressed(KeyInputRegister::ButtonA)));
+  }
+}
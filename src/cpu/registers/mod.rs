@@ -1,4 +1,5 @@
 pub mod interrupt_enable_register;
 pub mod interrupt_request_register;
 pub mod key_input_register;
+pub mod key_interrupt_control_register;
 pub mod waitstate_control_register;
\ No newline at end of file
@@ -46,4 +46,8 @@ impl WaitstateControlRegister {
   pub fn waitstate_2_second_access_cycles(&self) -> u16 {
     WAITSTATE2_SECOND_ACCESS_CYCLES[((self.value >> 10) & 0b1) as usize]
   }
-}
\ No newline at end of file
+
+  pub fn prefetch_enabled(&self) -> bool {
+    (self.value >> 14) & 0b1 == 1
+  }
+}
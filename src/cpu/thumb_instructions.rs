@@ -1,4 +1,7 @@
-use super::{CPU, PSRRegister, PC_REGISTER, SP_REGISTER, LR_REGISTER, MemoryAccess};
+use super::{
+  block_cache::{Handler, OperandHandler, Operands},
+  CPU, PSRRegister, PC_REGISTER, SP_REGISTER, LR_REGISTER, MemoryAccess
+};
 
 impl CPU {
   fn decode_thumb(&mut self, format: u16) -> fn(&mut CPU, instruction: u16) -> Option<MemoryAccess> {
@@ -45,6 +48,27 @@ impl CPU {
     }
   }
 
+  /// the handler and operands for the formats the block cache decodes ahead of time
+  pub fn decode_thumb_operands(instr: u16) -> Option<Handler> {
+    let format = instr >> 8;
+
+    let (handler, operands): (OperandHandler, Operands) = if format & 0b11111000 == 0b00011000 {
+      (CPU::execute_add_subtract, Self::add_subtract_operands(instr))
+    } else if format & 0b11100000 == 0 {
+      (CPU::execute_move_shifted_register, Self::move_shifted_register_operands(instr))
+    } else if format & 0b11100000 == 0b00100000 {
+      (CPU::execute_move_compare_add_sub_imm, Self::move_compare_add_sub_imm_operands(instr))
+    } else if format & 0b11111100 == 0b01000000 {
+      (CPU::execute_alu_operations, Self::alu_operations_operands(instr))
+    } else if format & 0b11100000 == 0b01100000 {
+      (CPU::execute_load_store_immediate_offset, Self::load_store_immediate_offset_operands(instr))
+    } else {
+      return None;
+    };
+
+    Some(Handler::Operands(handler, operands))
+  }
+
   pub fn populate_thumb_lut(&mut self) {
     for i in 0..256 {
       let instr_fn = self.decode_thumb(i);
@@ -57,11 +81,25 @@ impl CPU {
   }
 
   fn move_shifted_register(&mut self, instr: u16) -> Option<MemoryAccess> {
+    self.execute_move_shifted_register(&Self::move_shifted_register_operands(instr))
+  }
+
+  fn move_shifted_register_operands(instr: u16) -> Operands {
+    Operands {
+      op_code: ((instr >> 11) & 0x3) as u8,
+      shift: ((instr >> 6) & 0x1f) as u8,
+      rs: ((instr >> 3) & 0x7) as u8,
+      rd: (instr & 0x7) as u8,
+      ..Default::default()
+    }
+  }
+
+  fn execute_move_shifted_register(&mut self, operands: &Operands) -> Option<MemoryAccess> {
     // println!("inside move shifted register");
-    let op_code = ((instr >> 11) & 0x3) as u8;
-    let offset5 = ((instr >> 6) & 0x1f) as u8;
-    let rs = ((instr >> 3) & 0x7) as u8;
-    let rd = (instr & 0x7) as u8;
+    let op_code = operands.op_code;
+    let offset5 = operands.shift;
+    let rs = operands.rs;
+    let rd = operands.rd;
 
     // println!("rs = {rs}, rd = {rd}, offset = {offset5}, op_code = {op_code}");
     // println!("rs value = {:X}, rd value = {:X}", self.r[rs as usize], self.r[rd as usize]);
@@ -79,16 +117,33 @@ impl CPU {
   }
 
   fn add_subtract(&mut self, instr: u16) -> Option<MemoryAccess> {
-    // println!("inside add subtract");
-    let op_code = (instr >> 9) & 0b1;
+    self.execute_add_subtract(&Self::add_subtract_operands(instr))
+  }
+
+  fn add_subtract_operands(instr: u16) -> Operands {
     let rn_offset = (instr >> 6) & 0x7;
-    let is_immediate = (instr >> 10) & 0b1 == 1;
 
-    let rs = (instr >> 3) & 0x7;
-    let rd = instr & 0x7;
+    Operands {
+      op_code: ((instr >> 9) & 0b1) as u8,
+      rn: rn_offset as u8,
+      rs: ((instr >> 3) & 0x7) as u8,
+      rd: (instr & 0x7) as u8,
+      immediate: rn_offset as u32,
+      flags: ((instr >> 10) & 0b1) as u8,
+      ..Default::default()
+    }
+  }
+
+  fn execute_add_subtract(&mut self, operands: &Operands) -> Option<MemoryAccess> {
+    // println!("inside add subtract");
+    let op_code = operands.op_code;
+    let is_immediate = operands.flags == 1;
+
+    let rs = operands.rs;
+    let rd = operands.rd;
 
     let operand1 = self.r[rs as usize];
-    let operand2 = if is_immediate { rn_offset as u32 } else { self.r[rn_offset as usize] };
+    let operand2 = if is_immediate { operands.immediate } else { self.r[operands.rn as usize] };
 
     // println!("rs = {rs}, rd = {rd}");
 
@@ -106,20 +161,33 @@ impl CPU {
   }
 
   fn move_compare_add_sub_imm(&mut self, instr: u16) -> Option<MemoryAccess> {
+    self.execute_move_compare_add_sub_imm(&Self::move_compare_add_sub_imm_operands(instr))
+  }
+
+  fn move_compare_add_sub_imm_operands(instr: u16) -> Operands {
+    Operands {
+      op_code: ((instr >> 11) & 0b11) as u8,
+      rd: ((instr >> 8) & 0x7) as u8,
+      immediate: (instr & 0xff) as u32,
+      ..Default::default()
+    }
+  }
+
+  fn execute_move_compare_add_sub_imm(&mut self, operands: &Operands) -> Option<MemoryAccess> {
     // println!("inside move compare add sub imm");
-    let op_code = (instr >> 11) & 0b11;
-    let rd = (instr >> 8) & 0x7;
-    let offset = instr & 0xff;
+    let op_code = operands.op_code as u16;
+    let rd = operands.rd as u16;
+    let offset = operands.immediate;
 
     // println!("r{rd} = {}", self.r[rd as usize]);
 
     let op_name = self.get_move_compare_op_name(op_code);
 
     match op_code {
-      0 => self.mov(rd, offset as u32, true),
-      1 => self.cmp(self.r[rd as usize], offset as u32),
-      2 => self.r[rd as usize] = self.add(self.r[rd as usize], offset as u32),
-      3 => self.r[rd as usize] = self.subtract(self.r[rd as usize], offset as u32),
+      0 => self.mov(rd, offset, true),
+      1 => self.cmp(self.r[rd as usize], offset),
+      2 => self.r[rd as usize] = self.add(self.r[rd as usize], offset),
+      3 => self.r[rd as usize] = self.subtract(self.r[rd as usize], offset),
       _ => unreachable!("impossible")
     }
 
@@ -131,10 +199,23 @@ impl CPU {
   }
 
   fn alu_operations(&mut self, instr: u16) -> Option<MemoryAccess> {
+    self.execute_alu_operations(&Self::alu_operations_operands(instr))
+  }
+
+  fn alu_operations_operands(instr: u16) -> Operands {
+    Operands {
+      op_code: ((instr >> 6) & 0xf) as u8,
+      rs: ((instr >> 3) & 0x7) as u8,
+      rd: (instr & 0x7) as u8,
+      ..Default::default()
+    }
+  }
+
+  fn execute_alu_operations(&mut self, operands: &Operands) -> Option<MemoryAccess> {
     // println!("inside alu ops");
-    let op_code = (instr >> 6) & 0xf;
-    let rs = (instr >> 3) & 0x7;
-    let rd = instr & 0x7;
+    let op_code = operands.op_code;
+    let rs = operands.rs;
+    let rd = operands.rd;
 
     // println!("rs = {rs} rd = {rd} op code = {op_code}");
     // println!("r{rs} = {:X}, r{rd} = {:X}", self.r[rs as usize], self.r[rd as usize]);
@@ -279,7 +360,7 @@ impl CPU {
 
     let address = self.r[rb as usize].wrapping_add(self.r[ro as usize]);
 
-    self.load_store_offset(address, b, instr)
+    self.load_store_offset(address, b, (instr >> 11) & 0b1, instr & 0x7)
   }
 
   fn load_store_signed_byte_halfword(&mut self, instr: u16) -> Option<MemoryAccess> {
@@ -332,8 +413,10 @@ impl CPU {
   }
 
   fn load_store_immediate_offset(&mut self, instr: u16) -> Option<MemoryAccess> {
-    // println!("inside load store immediate offset");
+    self.execute_load_store_immediate_offset(&Self::load_store_immediate_offset_operands(instr))
+  }
 
+  fn load_store_immediate_offset_operands(instr: u16) -> Operands {
     let b = (instr >> 12) & 0b1;
 
     let offset = if b == 1 {
@@ -342,13 +425,22 @@ impl CPU {
       ((instr >> 6) & 0x1f) << 2
     };
 
-    // println!("offset is {offset}");
+    Operands {
+      rn: ((instr >> 3) & 0x7) as u8,
+      rd: (instr & 0x7) as u8,
+      immediate: offset as u32,
+      // b and l
+      flags: ((instr >> 11) & 0b11) as u8,
+      ..Default::default()
+    }
+  }
 
-    let rb = (instr >> 3) & 0x7;
+  fn execute_load_store_immediate_offset(&mut self, operands: &Operands) -> Option<MemoryAccess> {
+    // println!("inside load store immediate offset");
 
-    let address = self.r[rb as usize].wrapping_add(offset as u32);
+    let address = self.r[operands.rn as usize].wrapping_add(operands.immediate);
 
-    self.load_store_offset(address, b, instr)
+    self.load_store_offset(address, (operands.flags >> 1) as u16, (operands.flags & 0b1) as u16, operands.rd as u16)
   }
 
   fn load_store_halfword(&mut self, instr: u16) -> Option<MemoryAccess> {
@@ -362,16 +454,12 @@ impl CPU {
 
     // println!("rd = {rd} and rb = {rb}, address = {:X} and offset = {offset}", address);
 
-    let mut result = Some(MemoryAccess::Sequential);
-
     if l == 0 {
       let value = self.r[rd as usize] as u16;
 
       self.store_16(address & !(0b1), value, MemoryAccess::NonSequential);
 
       // println!("stored {:X} at address {:X}", value, address & !(0b1));
-
-      result = Some(MemoryAccess::NonSequential);
     } else {
       let value = self.ldr_halfword(address) as u32;
 
@@ -384,7 +472,7 @@ impl CPU {
 
     self.pc = self.pc.wrapping_add(2);
 
-    result
+    Some(MemoryAccess::NonSequential)
   }
 
   fn sp_relative_load_store(&mut self, instr: u16) -> Option<MemoryAccess> {
@@ -395,12 +483,8 @@ impl CPU {
 
     let address = self.r[SP_REGISTER].wrapping_add(word8 as u32);
 
-    let mut result = Some(MemoryAccess::Sequential);
-
     if l == 0 {
       self.store_32(address & !(0b11), self.r[rd as usize], MemoryAccess::NonSequential);
-
-      result = Some(MemoryAccess::NonSequential);
     } else {
       let value = self.ldr_word(address);
 
@@ -411,7 +495,7 @@ impl CPU {
 
     self.pc = self.pc.wrapping_add(2);
 
-    result
+    Some(MemoryAccess::NonSequential)
   }
 
   fn load_address(&mut self, instr: u16) -> Option<MemoryAccess> {
@@ -457,7 +541,7 @@ impl CPU {
     let register_list = instr & 0xff;
 
     let mut should_update_pc = true;
-    let mut result = Some(MemoryAccess::Sequential);
+    let mut result = Some(MemoryAccess::NonSequential);
 
     let mut access = MemoryAccess::NonSequential;
 
@@ -467,6 +551,7 @@ impl CPU {
         // push LR to the stack
         // println!("pushing register 14 to the stack");
         self.push(self.r[LR_REGISTER], access);
+        access = MemoryAccess::Sequential;
       }
       for i in (0..8).rev() {
         if (register_list >> i) & 0b1 == 1 {
@@ -487,17 +572,19 @@ impl CPU {
       if r == 1 {
         // pop PC off the stack
         // println!("popping the pc off the stack");
-        self.pc = self.pop(MemoryAccess::Sequential);
+        self.pc = self.pop(access);
         self.pc &= !(1);
 
+        self.add_cycles(1);
+
         // reload the pipeline
         self.reload_pipeline16();
 
         should_update_pc = false;
         result = None;
+      } else {
+        self.add_cycles(1);
       }
-
-      self.add_cycles(1);
     }
 
     if should_update_pc {
@@ -553,11 +640,11 @@ impl CPU {
           if (rlist >> r) & 0b1 == 1 {
             let val = self.load_32(address, access);
 
+            access = MemoryAccess::Sequential;
+
             self.r[r] = val;
             address += 4;
           }
-
-          access = MemoryAccess::Sequential;
         }
         if (rlist >> rb) & 0b1 == 0 {
           self.r[rb as usize] = address + align_preserve;
@@ -576,6 +663,8 @@ impl CPU {
 
         self.pc = val & !(0b1);
 
+        self.add_cycles(1);
+
         // reload the pipeline
         self.reload_pipeline16();
 
@@ -606,10 +695,10 @@ impl CPU {
     self.branch_if(self.arm_condition_met(cond as u8), signed_offset)
   }
 
-  fn thumb_software_interrupt(&mut self, _instr: u16) -> Option<MemoryAccess> {
+  fn thumb_software_interrupt(&mut self, instr: u16) -> Option<MemoryAccess> {
     // println!("inside software interrupt");
 
-    self.software_interrupt();
+    self.software_interrupt((instr & 0xff) as u8);
 
     None
   }
@@ -618,8 +707,12 @@ impl CPU {
     // println!("inside unconditional branch");
     let address = ((((instr & 0x7ff) as i32) << 21)) >> 20;
 
+    let branch_address = self.pc.wrapping_sub(4);
+
     self.pc = (self.pc as i32).wrapping_add((address) as i32) as u32;
 
+    self.check_idle_loop(branch_address, self.pc);
+
     self.reload_pipeline16();
 
     None
@@ -795,7 +888,7 @@ impl CPU {
   fn mul(&mut self, operand1: u32, operand2: u32) -> u32 {
     let result = operand1.wrapping_mul(operand2);
 
-    let cycles = self.get_multiplier_cycles(operand2);
+    let cycles = self.get_multiplier_cycles(operand2, true);
 
     self.add_cycles(cycles);
 
@@ -863,8 +956,12 @@ impl CPU {
   fn branch_if(&mut self, cond: bool, offset: i32) -> Option<MemoryAccess> {
     if cond {
       // println!("branching");
+      let branch_address = self.pc.wrapping_sub(4);
+
       self.pc = (self.pc as i32).wrapping_add(offset) as u32;
 
+      self.check_idle_loop(branch_address, self.pc);
+
       // reload pipeline
       self.reload_pipeline16();
 
@@ -878,29 +975,20 @@ impl CPU {
     Some(MemoryAccess::Sequential)
   }
 
-  fn load_store_offset(&mut self, address: u32, b: u16, instr: u16) -> Option<MemoryAccess> {
-    let l = (instr >> 11) & 0b1;
-    let rd = instr & 0x7;
-
+  fn load_store_offset(&mut self, address: u32, b: u16, l: u16, rd: u16) -> Option<MemoryAccess> {
     if l == 0 {
       // println!("writing to address {:X} value {:X}", address, self.r[rd as usize]);
     } else {
       // println!("reading from address {:X}", address);
     }
 
-    let mut result = Some(MemoryAccess::Sequential);
-
     match (l, b) {
       (0, 0) => {
         self.store_32(address & !(0b11), self.r[rd as usize], MemoryAccess::NonSequential);
-
-        result = Some(MemoryAccess::NonSequential);
       }
       (0, 1) => {
         // println!("storing byte {:X} from r{rd}", self.r[rd as usize] as u8);
         self.store_8(address, self.r[rd as usize] as u8, MemoryAccess::NonSequential);
-
-        result = Some(MemoryAccess::NonSequential);
       }
       (1, 0) => {
         let value = self.ldr_word(address);
@@ -924,7 +1012,7 @@ impl CPU {
 
     self.pc = self.pc.wrapping_add(2);
 
-    result
+    Some(MemoryAccess::NonSequential)
   }
 
   /*
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{apu::APU, scheduler::Scheduler};
 
-use self::timer::{Timer, TimerControl};
+use self::timer::Timer;
 
 use super::{dma::dma_channels::DmaChannels, registers::interrupt_request_register::InterruptRequestRegister};
 
@@ -20,6 +20,19 @@ impl Timers {
     }
   }
 
+  pub fn rebase_cycles(&mut self, to_subtract: usize) {
+    for timer in &mut self.t {
+      timer.rebase_cycles(to_subtract);
+    }
+  }
+
+  /// holds the running timers' counts back by the given cycles, for while the clock was stopped
+  pub fn delay(&mut self, cycles: usize) {
+    for timer in &mut self.t {
+      timer.delay(cycles);
+    }
+  }
+
   pub fn handle_overflow(
     &mut self,
     timer_id: usize,
@@ -34,7 +47,7 @@ impl Timers {
 
       let next_timer = &mut self.t[next_timer_id];
 
-      if next_timer.timer_ctl.contains(TimerControl::COUNT_UP_TIMING) && next_timer.count_up_timer(scheduler, interrupt_request, cycles_left) {
+      if next_timer.is_cascading() && next_timer.count_up_timer(scheduler, interrupt_request, cycles_left) {
         self.handle_overflow(next_timer_id, dma, scheduler, apu, interrupt_request, cycles_left);
       }
       if timer_id == 0 || timer_id == 1 {
@@ -42,4 +55,93 @@ impl Timers {
       }
     }
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  struct Harness {
+    timers: Timers,
+    dma: DmaChannels,
+    scheduler: Scheduler,
+    apu: APU,
+    interrupt_request: InterruptRequestRegister
+  }
+
+  impl Harness {
+    fn new() -> Self {
+      Self {
+        timers: Timers::new(),
+        dma: DmaChannels::new(),
+        scheduler: Scheduler::new(),
+        apu: APU::new(HeapRb::<f32>::new(1024).split().0),
+        interrupt_request: InterruptRequestRegister::from_bits_retain(0)
+      }
+    }
+
+    fn start(&mut self, timer_id: usize, control: u16, reload_value: u16) {
+      let timer = &mut self.timers.t[timer_id];
+
+      timer.reload_timer_value(reload_value);
+      timer.write_timer_control(control, &mut self.scheduler, 0);
+    }
+
+    // timer 0 overflowing, the way the cpu handles it
+    fn overflow_timer_0(&mut self) {
+      self.timers.t[0].handle_overflow(&mut self.scheduler, &mut self.interrupt_request, 0);
+      self.timers.handle_overflow(0, &mut self.dma, &mut self.scheduler, &mut self.apu, &mut self.interrupt_request, 0);
+    }
+  }
+
+  #[test]
+  fn cascading_timers_count_the_overflows_of_the_one_before() {
+    let mut harness = Harness::new();
+
+    harness.start(0, 0x80, 0);
+    harness.start(1, 0x84, 0x10);
+
+    harness.overflow_timer_0();
+    harness.overflow_timer_0();
+
+    // no matter how much time has passed
+    assert_eq!(harness.timers.t[1].read_value(100_000), 0x12);
+  }
+
+  #[test]
+  fn cascading_timers_arent_scheduled() {
+    let mut harness = Harness::new();
+
+    harness.start(1, 0x84, 0);
+
+    assert_eq!(harness.scheduler.get_cycles_to_next_event(), 0);
+  }
+
+  #[test]
+  fn cascades_carry_through_several_timers() {
+    let mut harness = Harness::new();
+
+    harness.start(0, 0x80, 0);
+    harness.start(1, 0xc4, 0xffff);
+    harness.start(2, 0x84, 0);
+
+    harness.overflow_timer_0();
+
+    assert_eq!(harness.timers.t[1].value, 0xffff);
+    assert_eq!(harness.timers.t[2].value, 1);
+    assert!(harness.interrupt_request.contains(InterruptRequestRegister::TIMER_1_OVERFLOW));
+  }
+
+  #[test]
+  fn stopped_timers_dont_cascade() {
+    let mut harness = Harness::new();
+
+    harness.start(0, 0x80, 0);
+    harness.start(1, 0x04, 0);
+
+    harness.overflow_timer_0();
+
+    assert_eq!(harness.timers.t[1].value, 0);
+  }
+}
@@ -22,7 +22,7 @@ impl Timer {
       reload_value: 0,
       value: 0,
       timer_ctl: TimerControl::from_bits_retain(0),
-      prescalar_frequency: 0,
+      prescalar_frequency: 1,
       running: false,
       cycles: 0,
       id,
@@ -30,6 +30,32 @@ impl Timer {
     }
   }
 
+  /// the current counter value. free running timers are only updated when they overflow,
+  /// so count the ticks since they started from the cycle counter instead
+  pub fn read_value(&self, cycles: usize) -> u16 {
+    if !self.running || self.is_cascading() {
+      return self.value;
+    }
+
+    let ticks = (cycles.saturating_sub(self.start_cycles) / self.prescalar_frequency as usize) as u32;
+
+    let count = self.value as u32 + ticks;
+
+    if count < 0x1_0000 {
+      count as u16
+    } else {
+      // the overflow event hasn't been handled yet, so wrap around to the reload value
+      let period = 0x1_0000 - self.reload_value as u32;
+
+      (self.reload_value as u32 + (count - 0x1_0000) % period) as u16
+    }
+  }
+
+  pub fn is_cascading(&self) -> bool {
+    // timer 0 has no previous timer to count up from, so it ignores the flag
+    self.id != 0 && self.timer_ctl.contains(TimerControl::COUNT_UP_TIMING)
+  }
+
   pub fn count_up_timer(
     &mut self,
     scheduler: &mut Scheduler,
@@ -38,10 +64,9 @@ impl Timer {
   ) -> bool {
     let mut return_val = false;
     if self.running {
-      let temp = self.value.wrapping_add(1);
+      let (temp, overflowed) = self.value.overflowing_add(1);
 
-      // overflow has happened
-      if temp < self.value {
+      if overflowed {
         self.handle_overflow(scheduler, interrupt_request, cycles_left);
 
         return_val = true;
@@ -59,13 +84,15 @@ impl Timer {
     &mut InterruptRequestRegister,
     cycles_left: usize
   ) {
-    if !self.timer_ctl.contains(TimerControl::COUNT_UP_TIMING) {
-      let event_type = EventType::Timer(self.id);
+    self.value = self.reload_value;
 
-      self.value = self.reload_value;
-      let cycles_till_overflow = self.prescalar_frequency * (0x1_0000 - self.value as u32);
-      scheduler.schedule(event_type, cycles_till_overflow as usize - cycles_left);
-      self.start_cycles = scheduler.cycles;
+    if !self.is_cascading() {
+      // cycles_left is how late the event is being handled, so count from the actual overflow
+      let overflow_cycles = scheduler.cycles - cycles_left;
+
+      self.start_cycles = overflow_cycles;
+
+      scheduler.schedule_at(EventType::Timer(self.id), overflow_cycles + self.cycles_till_overflow());
     }
 
     if self.timer_ctl.contains(TimerControl::IRQ_ENABLE) {
@@ -78,24 +105,42 @@ impl Timer {
     self.reload_value = value;
   }
 
-  pub fn write_timer_control(&mut self, value: u16, scheduler: &mut Scheduler) {
+  pub fn write_timer_control(&mut self, value: u16, scheduler: &mut Scheduler, cycles: usize) {
+    // writes to the control register take effect one cycle later
+    let cycles = cycles + 1;
+
     let new_ctl = TimerControl::from_bits_retain(value);
 
-    self.prescalar_frequency = CYCLE_LUT[new_ctl.prescalar_selection() as usize];
+    // keep the count reached so far when the prescaler or cascade mode changes while running
+    self.value = self.read_value(cycles);
+
+    scheduler.remove(EventType::Timer(self.id));
 
     if new_ctl.contains(TimerControl::ENABLED) && !self.timer_ctl.contains(TimerControl::ENABLED) {
       self.value = self.reload_value;
+    }
 
-      let cycles_till_overflow = self.prescalar_frequency * (0x1_0000 - self.value as u32);
-
-      scheduler.schedule(EventType::Timer(self.id), cycles_till_overflow as usize);
+    self.timer_ctl = new_ctl;
+    self.prescalar_frequency = CYCLE_LUT[new_ctl.prescalar_selection() as usize];
+    self.running = new_ctl.contains(TimerControl::ENABLED);
+    self.start_cycles = cycles;
 
-      self.running = true;
-    } else if !new_ctl.contains(TimerControl::ENABLED) || new_ctl.contains(TimerControl::COUNT_UP_TIMING) {
-      scheduler.remove(EventType::Timer(self.id));
+    // cascading timers are only ticked by the previous timer overflowing
+    if self.running && !self.is_cascading() {
+      scheduler.schedule_at(EventType::Timer(self.id), cycles + self.cycles_till_overflow());
     }
+  }
 
-    self.timer_ctl = new_ctl;
+  pub fn rebase_cycles(&mut self, to_subtract: usize) {
+    self.start_cycles = self.start_cycles.saturating_sub(to_subtract);
+  }
+
+  pub fn delay(&mut self, cycles: usize) {
+    self.start_cycles += cycles;
+  }
+
+  fn cycles_till_overflow(&self) -> usize {
+    (self.prescalar_frequency * (0x1_0000 - self.value as u32)) as usize
   }
 }
 
@@ -113,4 +158,82 @@ impl TimerControl {
   pub fn prescalar_selection(&self) -> u16 {
     self.bits() & 0b11
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a timer started at cycle 0 with the given control bits, so it counts from cycle 1
+  fn started_timer(control: u16, reload_value: u16, scheduler: &mut Scheduler) -> Timer {
+    let mut timer = Timer::new(0);
+
+    timer.reload_timer_value(reload_value);
+    timer.write_timer_control(control, scheduler, 0);
+
+    timer
+  }
+
+  #[test]
+  fn reads_count_the_cycles_since_it_started() {
+    let mut scheduler = Scheduler::new();
+    let timer = started_timer(0x80, 0, &mut scheduler);
+
+    // the control write takes effect a cycle late
+    assert_eq!(timer.read_value(1), 0);
+    assert_eq!(timer.read_value(2), 1);
+    assert_eq!(timer.read_value(101), 100);
+  }
+
+  #[test]
+  fn reads_go_by_the_prescaler() {
+    let mut scheduler = Scheduler::new();
+    let timer = started_timer(0x81, 0, &mut scheduler);
+
+    assert_eq!(timer.read_value(64), 0);
+    assert_eq!(timer.read_value(65), 1);
+    assert_eq!(timer.read_value(1 + 64 * 5 + 10), 5);
+  }
+
+  #[test]
+  fn reads_wrap_to_the_reload_value_before_the_overflow_is_handled() {
+    let mut scheduler = Scheduler::new();
+    let timer = started_timer(0x80, 0xfff0, &mut scheduler);
+
+    assert_eq!(timer.read_value(1 + 0xf), 0xffff);
+    assert_eq!(timer.read_value(1 + 0x10), 0xfff0);
+    assert_eq!(timer.read_value(1 + 0x12), 0xfff2);
+  }
+
+  #[test]
+  fn changing_the_prescaler_keeps_the_count() {
+    let mut scheduler = Scheduler::new();
+    let mut timer = started_timer(0x80, 0, &mut scheduler);
+
+    timer.write_timer_control(0x81, &mut scheduler, 99);
+
+    assert_eq!(timer.read_value(100), 99);
+    assert_eq!(timer.read_value(100 + 64), 100);
+  }
+
+  #[test]
+  fn overflows_count_from_when_they_happened() {
+    let mut scheduler = Scheduler::new();
+    let mut interrupt_request = InterruptRequestRegister::from_bits_retain(0);
+    let mut timer = started_timer(0xc0, 0xfff0, &mut scheduler);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 1 + 0x10);
+
+    // the event is handled a few cycles late
+    scheduler.update_cycles(1 + 0x10 + 3);
+
+    let (event_type, cycles_left) = scheduler.get_next_event().unwrap();
+
+    assert_eq!(event_type, EventType::Timer(0));
+
+    timer.handle_overflow(&mut scheduler, &mut interrupt_request, cycles_left);
+
+    assert!(interrupt_request.contains(InterruptRequestRegister::TIMER_0_OVERFLOW));
+    assert_eq!(timer.read_value(1 + 0x10 + 3), 0xfff3);
+    assert_eq!(scheduler.get_cycles_to_next_event(), 1 + 0x20);
+  }
+}
@@ -238,6 +238,8 @@ impl GPU {
     } else {
       self.handle_vblank_hblank(interrupt_request);
     }
+
+    dma.notify_video_capture(self.vcount);
   }
 
   fn handle_visible_hblank(&mut self, interrupt_request: &mut InterruptRequestRegister, dma: &mut DmaChannels) {
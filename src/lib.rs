@@ -6,4 +6,6 @@ pub mod gpu;
 pub mod cartridge;
 pub mod apu;
 pub mod scheduler;
+pub mod sio;
+pub mod linked_system;
 pub mod number;
\ No newline at end of file
@@ -0,0 +1,171 @@
+// several consoles linked together inside one process. instead of going over a socket, every
+// console's link port lives here and the consoles are run one after another in slices. at the
+// end of each slice every console has stopped at its LinkSync event, so the pending transfers of
+// all of them are resolved together the same way SocketLink does it, just in memory.
+
+use std::{cell::{Cell, RefCell}, rc::Rc, sync::Arc};
+
+use ringbuf::{storage::Heap, wrap::caching::Caching, SharedRb};
+
+use crate::{
+  cpu::CPU,
+  gpu::CYCLES_PER_FRAME,
+  sio::{link_port::{resolve, LinkPort, MAX_PLAYERS}, transport::LinkTransport}
+};
+
+// evenly divides a frame so slices line up with frame boundaries
+const SLICE_CYCLES: usize = CYCLES_PER_FRAME as usize / 16;
+
+struct LocalLink {
+  port: Rc<RefCell<LinkPort>>,
+  at_sync_point: Rc<Cell<bool>>
+}
+
+impl LinkTransport for LocalLink {
+  fn connected(&self) -> bool {
+    self.port.borrow().connected()
+  }
+
+  fn player_id(&self) -> usize {
+    self.port.borrow().player_id()
+  }
+
+  fn players(&self) -> usize {
+    self.port.borrow().players()
+  }
+
+  fn transfer_normal(&mut self, value: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+    self.port.borrow_mut().transfer_normal(value, bits, internal_clock)
+  }
+
+  fn transfer_multiplayer(&mut self, value: u16) -> Option<[u16; 4]> {
+    self.port.borrow_mut().transfer_multiplayer(value)
+  }
+
+  fn send_uart(&mut self, byte: u8) {
+    self.port.borrow_mut().send_uart(byte);
+  }
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    self.port.borrow_mut().receive_uart()
+  }
+
+  fn sync_interval(&self) -> Option<usize> {
+    Some(SLICE_CYCLES)
+  }
+
+  fn sync(&mut self) {
+    // the actual sync happens in LinkedSystem once every console has reached this point
+    self.at_sync_point.set(true);
+  }
+}
+
+pub struct LinkedSystem {
+  pub consoles: Vec<CPU>,
+  ports: Vec<Rc<RefCell<LinkPort>>>,
+  sync_points: Vec<Rc<Cell<bool>>>
+}
+
+impl LinkedSystem {
+  /// creates one linked console per audio producer, console 0 being the multiplayer parent.
+  /// games still need to be loaded into each console before stepping
+  pub fn new(producers: Vec<Caching<Arc<SharedRb<Heap<f32>>>, true, false>>) -> Self {
+    let players = producers.len();
+
+    assert!((2..=MAX_PLAYERS).contains(&players), "a link needs 2 to {MAX_PLAYERS} players");
+
+    let mut consoles = Vec::new();
+    let mut ports = Vec::new();
+    let mut sync_points = Vec::new();
+
+    for (player_id, producer) in producers.into_iter().enumerate() {
+      let port = Rc::new(RefCell::new(LinkPort::new(player_id, players)));
+      let at_sync_point = Rc::new(Cell::new(false));
+
+      let mut cpu = CPU::new(producer);
+
+      cpu.set_link_transport(Box::new(LocalLink { port: port.clone(), at_sync_point: at_sync_point.clone() }));
+
+      consoles.push(cpu);
+      ports.push(port);
+      sync_points.push(at_sync_point);
+    }
+
+    Self {
+      consoles,
+      ports,
+      sync_points
+    }
+  }
+
+  /// runs every console up to its next sync point, then exchanges whatever they sent over the link
+  pub fn step_slice(&mut self) {
+    for (cpu, at_sync_point) in self.consoles.iter_mut().zip(&self.sync_points) {
+      while !at_sync_point.get() {
+        cpu.step();
+      }
+    }
+
+    let states: Vec<_> = self.ports.iter().map(|port| port.borrow_mut().take_state()).collect();
+
+    for (port, result) in self.ports.iter().zip(resolve(&states)) {
+      port.borrow_mut().apply_result(result);
+    }
+
+    for at_sync_point in &self.sync_points {
+      at_sync_point.set(false);
+    }
+  }
+
+  /// runs slices until every console has finished a frame
+  pub fn step_frame(&mut self) {
+    while !self.consoles.iter().all(|cpu| cpu.gpu.frame_finished) {
+      self.step_slice();
+    }
+
+    for cpu in &mut self.consoles {
+      if cpu.scheduler.cycles >= 0xfff0_0000 {
+        cpu.rebase_cycles();
+      }
+
+      cpu.gpu.frame_finished = false;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ringbuf::{traits::Split, HeapRb};
+
+  use super::*;
+
+  fn load_code(cpu: &mut CPU, code: &[u32]) {
+    let mut rom: Vec<u8> = code.iter().flat_map(|word| word.to_le_bytes()).collect();
+
+    rom.resize(0x1000, 0);
+
+    cpu.load_game(rom, None);
+    cpu.skip_bios();
+  }
+
+  #[test]
+  fn stopped_consoles_stay_in_lockstep() {
+    let mut system = LinkedSystem::new((0..2).map(|_| HeapRb::<f32>::new(1024).split().0).collect());
+
+    load_code(&mut system.consoles[0], &[0xeaff_fffe]); // b .
+    load_code(&mut system.consoles[1], &[
+      0xe3a0_1080, // mov r1, #0x80
+      0xe3a0_2301, // mov r2, #0x4000000
+      0xe282_2c03, // add r2, r2, #0x300
+      0xe5c2_1001, // strb r1, [r2, #1], stop
+      0xeaff_fffe  // b .
+    ]);
+
+    for _ in 0..3 {
+      system.step_frame();
+
+      assert!(system.consoles[1].is_stopped());
+      assert!(system.consoles[0].cycles.abs_diff(system.consoles[1].cycles) < SLICE_CYCLES);
+    }
+  }
+}
@@ -1,40 +1,122 @@
-use std::{cmp::Reverse, collections::HashMap};
-
-use priority_queue::PriorityQueue;
 use serde::{Deserialize, Serialize};
 
+// one slot per event, with room to spare for new event types
+const EVENT_SLOTS: usize = 16;
+
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum EventType {
   Hblank,
   Hdraw,
   Timer(usize),
-  SampleAudio
+  SampleAudio,
+  Serial,
+  LinkSync,
+  RtcTick,
+  SaveFlush
+}
+
+impl EventType {
+  fn slot(self) -> usize {
+    match self {
+      EventType::Hblank => 0,
+      EventType::Hdraw => 1,
+      EventType::Timer(id) => {
+        assert!(id < 4, "there are only 4 timers");
+
+        2 + id
+      }
+      EventType::SampleAudio => 6,
+      EventType::Serial => 7,
+      EventType::LinkSync => 8,
+      EventType::RtcTick => 9,
+      EventType::SaveFlush => 10
+    }
+  }
+
+  fn from_slot(slot: usize) -> Option<Self> {
+    match slot {
+      0 => Some(EventType::Hblank),
+      1 => Some(EventType::Hdraw),
+      2..=5 => Some(EventType::Timer(slot - 2)),
+      6 => Some(EventType::SampleAudio),
+      7 => Some(EventType::Serial),
+      8 => Some(EventType::LinkSync),
+      9 => Some(EventType::RtcTick),
+      10 => Some(EventType::SaveFlush),
+      _ => None
+    }
+  }
 }
 
+/// every event type can be scheduled at most once, so events live in a fixed array indexed by
+/// type holding the cycle they fire at. the earliest slot is cached since it's checked constantly
 #[derive(Serialize, Deserialize)]
 pub struct Scheduler {
   pub cycles: usize,
-  #[serde(skip_serializing)]
-  #[serde(skip_deserializing)]
-  pub queue: PriorityQueue<EventType, Reverse<usize>>,
-  queue_serialized: HashMap<EventType, usize>
+  slots: [Option<usize>; EVENT_SLOTS],
+  next_slot: Option<usize>,
+  // events held back with the cycles they had left when paused
+  paused: [Option<usize>; EVENT_SLOTS]
+}
+
+impl Default for Scheduler {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Scheduler {
   pub fn new() -> Self {
     Self {
       cycles: 0,
-      queue: PriorityQueue::new(),
-      queue_serialized: HashMap::new()
+      slots: [None; EVENT_SLOTS],
+      next_slot: None,
+      paused: [None; EVENT_SLOTS]
     }
   }
 
   pub fn schedule(&mut self, event_type: EventType, time: usize) {
-    self.queue.push(event_type, Reverse(self.cycles + time));
+    self.schedule_at(event_type, self.cycles + time);
+  }
+
+  /// schedules an event at an absolute cycle count rather than relative to the last update,
+  /// for events scheduled by the cpu in the middle of a step
+  pub fn schedule_at(&mut self, event_type: EventType, cycles: usize) {
+    let slot = event_type.slot();
+
+    self.slots[slot] = Some(cycles);
+
+    match self.next_slot.and_then(|next| self.slots[next].map(|next_cycles| (next, next_cycles))) {
+      // rescheduling the next event to later could make another event the earliest
+      Some((next, _)) if next == slot => self.update_next_slot(),
+      Some((_, next_cycles)) if next_cycles <= cycles => (),
+      _ => self.next_slot = Some(slot)
+    }
   }
 
   pub fn remove(&mut self, event_type: EventType) {
-    self.queue.remove(&event_type);
+    let slot = event_type.slot();
+
+    if self.slots[slot].take().is_some() && self.next_slot == Some(slot) {
+      self.update_next_slot();
+    }
+  }
+
+  /// takes an event off the schedule until it's resumed, keeping how long it had left
+  pub fn pause(&mut self, event_type: EventType) {
+    let slot = event_type.slot();
+
+    if let Some(cycles) = self.slots[slot] {
+      self.paused[slot] = Some(cycles.saturating_sub(self.cycles));
+
+      self.remove(event_type);
+    }
+  }
+
+  pub fn resume(&mut self, event_type: EventType) {
+    if let Some(cycles_left) = self.paused[event_type.slot()].take() {
+      self.schedule(event_type, cycles_left);
+    }
   }
 
   pub fn update_cycles(&mut self, cycles: usize) {
@@ -42,12 +124,14 @@ impl Scheduler {
   }
 
   pub fn get_next_event(&mut self) -> Option<(EventType, usize)> {
-    let (_, Reverse(cycles)) = self.queue.peek().unwrap();
+    let slot = self.next_slot?;
+    let cycles = self.slots[slot]?;
+
+    if self.cycles >= cycles {
+      self.slots[slot] = None;
+      self.update_next_slot();
 
-    if self.cycles >= *cycles {
-      let cycles_left = self.cycles - *cycles;
-      let (event_type, _) = self.queue.pop().unwrap();
-      return Some((event_type, cycles_left));
+      return EventType::from_slot(slot).map(|event_type| (event_type, self.cycles - cycles));
     }
 
     None
@@ -58,38 +142,109 @@ impl Scheduler {
 
     self.cycles = 0;
 
-    let mut vec: Vec<(EventType, usize)> = Vec::new();
+    for cycles in self.slots.iter_mut().flatten() {
+      *cycles = cycles.saturating_sub(to_subtract);
+    }
 
-    while let Some((event_type, Reverse(cycles)))= self.queue.pop() {
-      let new_cycles = cycles - to_subtract;
+    to_subtract
+  }
 
-      vec.push((event_type, new_cycles));
-    }
+  pub fn get_cycles_to_next_event(&self) -> usize {
+    self.next_slot.and_then(|slot| self.slots[slot]).unwrap_or(0)
+  }
+
+  fn update_next_slot(&mut self) {
+    self.next_slot = self.slots
+      .iter()
+      .enumerate()
+      .filter_map(|(slot, cycles)| cycles.map(|cycles| (slot, cycles)))
+      .min_by_key(|(_, cycles)| *cycles)
+      .map(|(slot, _)| slot);
+  }
+}
 
-    for (event_type, cycles) in vec {
-      self.queue.push(event_type, Reverse(cycles));
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn drain(scheduler: &mut Scheduler) -> Vec<(EventType, usize)> {
+    let mut events = Vec::new();
+
+    while let Some(event) = scheduler.get_next_event() {
+      events.push(event);
     }
 
-    to_subtract
+    events
   }
 
-  pub fn create_save_state(&mut self) {
-    for (event_type, Reverse(cycles)) in self.queue.iter() {
-      self.queue_serialized.insert(*event_type, *cycles);
-    }
+  #[test]
+  fn events_fire_in_cycle_order() {
+    let mut scheduler = Scheduler::new();
+
+    scheduler.schedule(EventType::SaveFlush, 10);
+    scheduler.schedule(EventType::Timer(3), 30);
+    scheduler.schedule(EventType::Hblank, 20);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 10);
+
+    scheduler.update_cycles(25);
+
+    // events come out with how many cycles late they are
+    assert_eq!(drain(&mut scheduler), [(EventType::SaveFlush, 15), (EventType::Hblank, 5)]);
+    assert_eq!(scheduler.get_cycles_to_next_event(), 30);
   }
 
-  pub fn load_save_state(&mut self) {
-    for (event_type, cycles) in self.queue_serialized.iter() {
-      self.queue.push(*event_type, Reverse(*cycles));
-    }
+  #[test]
+  fn rescheduling_the_next_event_later_finds_the_new_earliest() {
+    let mut scheduler = Scheduler::new();
+
+    scheduler.schedule(EventType::Hdraw, 10);
+    scheduler.schedule(EventType::Serial, 20);
+    scheduler.schedule(EventType::Hdraw, 40);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 20);
+
+    scheduler.remove(EventType::Serial);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 40);
   }
 
-  pub fn get_cycles_to_next_event(&mut self) -> usize {
-    if let Some((_, Reverse(cycles))) = self.queue.peek() {
-      *cycles
-    } else {
-      0
-    }
+  #[test]
+  fn rebase_keeps_the_cycles_left() {
+    let mut scheduler = Scheduler::new();
+
+    scheduler.update_cycles(1000);
+    scheduler.schedule(EventType::Hblank, 50);
+    scheduler.schedule(EventType::SampleAudio, 80);
+
+    assert_eq!(scheduler.rebase_cycles(), 1000);
+    assert_eq!(scheduler.cycles, 0);
+    assert_eq!(scheduler.get_cycles_to_next_event(), 50);
+
+    scheduler.update_cycles(80);
+
+    assert_eq!(drain(&mut scheduler), [(EventType::Hblank, 30), (EventType::SampleAudio, 0)]);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn paused_events_resume_with_the_cycles_they_had_left() {
+    let mut scheduler = Scheduler::new();
+
+    scheduler.schedule(EventType::LinkSync, 100);
+    scheduler.update_cycles(40);
+    scheduler.pause(EventType::LinkSync);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 0);
+
+    scheduler.update_cycles(500);
+    scheduler.resume(EventType::LinkSync);
+
+    assert_eq!(scheduler.get_cycles_to_next_event(), 560);
+  }
+
+  #[test]
+  #[should_panic]
+  fn timer_ids_past_three_are_rejected() {
+    Scheduler::new().schedule(EventType::Timer(4), 10);
+  }
+}
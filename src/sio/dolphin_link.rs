@@ -0,0 +1,221 @@
+// joy bus link to dolphin's "GBA (TCP)" controller port device, so a gamecube game running in a
+// local dolphin can talk to this gba. dolphin listens on two ports: one carries commands and
+// replies, the other a clock slice sent just before every command telling the gba how many of
+// its cycles have passed on the gamecube since the last one. the gba runs exactly that many
+// cycles before answering, which keeps both sides in lockstep while it's in joy bus mode.
+
+use std::{
+  io::{self, ErrorKind, Read, Write},
+  net::TcpStream,
+  time::Duration
+};
+
+use crate::gpu::CYCLES_PER_FRAME;
+
+use super::transport::LinkTransport;
+
+const DATA_PORT: u16 = 0xd6ba;
+const CLOCK_PORT: u16 = 0xc10c;
+
+// the only command with data attached, the other commands are a single byte
+const JOY_WRITE: u8 = 0x15;
+
+// dolphin sends the command right after the clock slice, so anything longer means there isn't one
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+// how long to wait for the next clock slice before letting the gba run on without one, so a
+// paused or stalled dolphin doesn't freeze the emulator
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(10);
+// how often to check the sockets when not in lockstep, about once a frame
+const IDLE_SYNC_CYCLES: usize = CYCLES_PER_FRAME as usize;
+
+pub struct DolphinLink {
+  data: TcpStream,
+  clock: TcpStream,
+  connected: bool,
+  error: Option<io::Error>,
+  // lockstep only happens while the game has the link port in joy bus mode
+  joybus: bool,
+  // no clock slice arrived in time at the last sync
+  stalled: bool,
+  slice: usize,
+  slice_done: bool,
+  // received with the current slice, answered once it's been run
+  pending: Option<[u8; 5]>,
+  ready: Option<[u8; 5]>
+}
+
+impl DolphinLink {
+  /// connects to dolphin running on host, usually 127.0.0.1
+  pub fn connect(host: &str) -> io::Result<Self> {
+    let data = TcpStream::connect((host, DATA_PORT))?;
+    let clock = TcpStream::connect((host, CLOCK_PORT))?;
+
+    data.set_nodelay(true)?;
+    data.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+    clock.set_nodelay(true)?;
+    clock.set_read_timeout(Some(CLOCK_TIMEOUT))?;
+
+    Ok(Self {
+      data,
+      clock,
+      connected: true,
+      error: None,
+      joybus: false,
+      stalled: false,
+      slice: 0,
+      slice_done: true,
+      pending: None,
+      ready: None
+    })
+  }
+
+  /// returns whether a clock slice arrived before the timeout
+  fn wait_for_command(&mut self) -> io::Result<bool> {
+    let mut slice = [0; 4];
+
+    match self.clock.read(&mut slice) {
+      Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+      // the rest of the slice follows straight away
+      Ok(len) => self.clock.read_exact(&mut slice[len..])?,
+      Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(false),
+      Err(error) => return Err(error)
+    }
+
+    self.slice = u32::from_be_bytes(slice) as usize;
+
+    let mut command = [0; 5];
+
+    self.pending = match self.data.read(&mut command[..1]) {
+      Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+      Ok(_) => {
+        if command[0] == JOY_WRITE {
+          self.data.read_exact(&mut command[1..])?;
+        }
+
+        Some(command)
+      }
+      Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => None,
+      Err(error) => return Err(error)
+    };
+
+    Ok(true)
+  }
+
+  /// throws away the slices and commands dolphin sent while the gba wasn't listening
+  fn discard_received(&mut self) -> io::Result<()> {
+    let mut buf = [0; 64];
+
+    for stream in [&mut self.clock, &mut self.data] {
+      stream.set_nonblocking(true)?;
+
+      let result = loop {
+        match stream.read(&mut buf) {
+          Ok(0) => break Err(io::Error::from(ErrorKind::UnexpectedEof)),
+          Ok(_) => (),
+          Err(error) if error.kind() == ErrorKind::WouldBlock => break Ok(()),
+          Err(error) => break Err(error)
+        }
+      };
+
+      stream.set_nonblocking(false)?;
+
+      result?;
+    }
+
+    Ok(())
+  }
+
+  fn disconnect(&mut self, error: io::Error) {
+    self.error = Some(error);
+    self.connected = false;
+    self.ready = None;
+  }
+}
+
+impl LinkTransport for DolphinLink {
+  fn connected(&self) -> bool {
+    self.connected
+  }
+
+  // the gamecube is the only one who can start a joy bus transfer, none of the other modes work
+
+  fn transfer_normal(&mut self, _value: u32, _bits: u32, _internal_clock: bool) -> Option<u32> {
+    None
+  }
+
+  fn transfer_multiplayer(&mut self, _value: u16) -> Option<[u16; 4]> {
+    None
+  }
+
+  fn send_uart(&mut self, _byte: u8) {}
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    None
+  }
+
+  fn receive_joybus(&mut self) -> Option<[u8; 5]> {
+    self.ready.take()
+  }
+
+  fn send_joybus(&mut self, reply: &[u8]) {
+    if let Err(error) = self.data.write_all(reply) {
+      self.disconnect(error);
+    }
+  }
+
+  fn set_joybus_mode(&mut self, enabled: bool) {
+    self.joybus = enabled;
+
+    // a command that arrived before leaving joy bus mode goes unanswered
+    self.slice_done = true;
+    self.pending = None;
+    self.ready = None;
+  }
+
+  fn sync_interval(&self) -> Option<usize> {
+    if !self.joybus || self.stalled {
+      return self.connected.then_some(IDLE_SYNC_CYCLES);
+    }
+
+    // once a slice has been run the next sync is straight away, to wait for the next one
+    self.connected.then_some(if self.slice_done { 1 } else { self.slice.max(1) })
+  }
+
+  fn sync(&mut self) {
+    if !self.connected {
+      return;
+    }
+
+    if !self.joybus {
+      if let Err(error) = self.discard_received() {
+        self.disconnect(error);
+      }
+
+      return;
+    }
+
+    if !self.slice_done {
+      self.slice_done = true;
+      self.ready = self.pending.take();
+
+      // give the gba a chance to answer before blocking on the next slice
+      if self.ready.is_some() {
+        return;
+      }
+    }
+
+    self.ready = None;
+
+    match self.wait_for_command() {
+      Ok(received) => {
+        self.stalled = !received;
+        self.slice_done = !received;
+      }
+      Err(error) => self.disconnect(error)
+    }
+  }
+
+  fn take_error(&mut self) -> Option<io::Error> {
+    self.error.take()
+  }
+}
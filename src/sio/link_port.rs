@@ -0,0 +1,288 @@
+// one end of a link shared by several emulated consoles. transfers started by the console are
+// collected here until the next sync point, where the states of every port on the link are
+// resolved together and the results handed back. the sync itself is up to whoever owns the
+// ports, whether that's over a socket or in memory.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use super::transport::{LinkTransport, NoCable};
+
+pub const MAX_PLAYERS: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct NormalTransfer {
+  value: u32,
+  bits: u32,
+  internal_clock: bool
+}
+
+/// what a console has pending at a sync point
+#[derive(Serialize, Deserialize)]
+pub struct UnitState {
+  multiplayer_value: u16,
+  multiplayer_request: bool,
+  normal: Option<NormalTransfer>,
+  uart: Vec<u8>
+}
+
+/// what a console receives once every state at a sync point has been resolved
+#[derive(Serialize, Deserialize, Default)]
+pub struct SyncResult {
+  multiplayer: Option<[u16; 4]>,
+  normal: Option<u32>,
+  uart: Vec<u8>
+}
+
+pub struct LinkPort {
+  pub player_id: usize,
+  pub players: usize,
+  pub connected: bool,
+  multiplayer_value: u16,
+  multiplayer_request: bool,
+  multiplayer_result: Option<[u16; 4]>,
+  normal_request: Option<NormalTransfer>,
+  normal_result: Option<u32>,
+  uart_send: Vec<u8>,
+  uart_receive: VecDeque<u8>
+}
+
+impl LinkPort {
+  pub fn new(player_id: usize, players: usize) -> Self {
+    Self {
+      player_id,
+      players,
+      connected: true,
+      multiplayer_value: 0xffff,
+      multiplayer_request: false,
+      multiplayer_result: None,
+      normal_request: None,
+      normal_result: None,
+      uart_send: Vec::new(),
+      uart_receive: VecDeque::new()
+    }
+  }
+
+  pub fn take_state(&mut self) -> UnitState {
+    // requests that already have a result waiting haven't been picked up yet, so don't send them again
+    UnitState {
+      multiplayer_value: self.multiplayer_value,
+      multiplayer_request: self.multiplayer_request && self.multiplayer_result.is_none(),
+      normal: if self.normal_result.is_none() { self.normal_request } else { None },
+      uart: std::mem::take(&mut self.uart_send)
+    }
+  }
+
+  pub fn apply_result(&mut self, result: SyncResult) {
+    if result.multiplayer.is_some() {
+      self.multiplayer_result = result.multiplayer;
+    }
+    if result.normal.is_some() {
+      self.normal_result = result.normal;
+    }
+
+    self.uart_receive.extend(result.uart);
+  }
+}
+
+/// works out what every console receives from the transfers pending at a sync point.
+/// states[0] is always the multiplayer parent
+pub fn resolve(states: &[UnitState]) -> Vec<SyncResult> {
+  let mut results: Vec<SyncResult> = states.iter().map(|_| SyncResult::default()).collect();
+
+  // multiplayer transfers are started by the parent and everyone receives everyone's data
+  if states[0].multiplayer_request {
+    let mut data = [0xffff; 4];
+
+    for (value, state) in data.iter_mut().zip(states) {
+      *value = state.multiplayer_value;
+    }
+
+    for result in &mut results {
+      result.multiplayer = Some(data);
+    }
+  }
+
+  // normal mode only works between two units, the one driving the clock and the other one
+  if let Some(master) = states.iter().position(|state| matches!(state.normal, Some(transfer) if transfer.internal_clock)) {
+    let slave = if master == 0 { 1 } else { 0 };
+
+    if let Some(transfer) = states[master].normal {
+      match states.get(slave).and_then(|state| state.normal) {
+        Some(slave_transfer) if !slave_transfer.internal_clock => {
+          results[master].normal = Some(slave_transfer.value);
+          results[slave].normal = Some(transfer.value);
+        }
+        // nobody is listening, so the input line stays high
+        _ => results[master].normal = Some(if transfer.bits == 32 { u32::MAX } else { (1 << transfer.bits) - 1 })
+      }
+    }
+  }
+
+  // everyone receives the uart bytes sent by everyone else
+  for (i, result) in results.iter_mut().enumerate() {
+    for (j, state) in states.iter().enumerate() {
+      if i != j {
+        result.uart.extend_from_slice(&state.uart);
+      }
+    }
+  }
+
+  results
+}
+
+impl LinkTransport for LinkPort {
+  fn connected(&self) -> bool {
+    self.connected
+  }
+
+  fn player_id(&self) -> usize {
+    self.player_id
+  }
+
+  fn players(&self) -> usize {
+    self.players
+  }
+
+  fn transfer_normal(&mut self, value: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+    if !self.connected {
+      return NoCable.transfer_normal(value, bits, internal_clock);
+    }
+
+    if let Some(received) = self.normal_result.take() {
+      self.normal_request = None;
+
+      return Some(received);
+    }
+
+    self.normal_request = Some(NormalTransfer { value, bits, internal_clock });
+
+    None
+  }
+
+  fn transfer_multiplayer(&mut self, value: u16) -> Option<[u16; 4]> {
+    self.multiplayer_value = value;
+
+    if let Some(data) = self.multiplayer_result.take() {
+      self.multiplayer_request = false;
+
+      return Some(data);
+    }
+
+    if self.player_id == 0 {
+      self.multiplayer_request = true;
+    }
+
+    None
+  }
+
+  fn send_uart(&mut self, byte: u8) {
+    self.uart_send.push(byte);
+  }
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    self.uart_receive.pop_front()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ports(players: usize) -> Vec<LinkPort> {
+    (0..players).map(|player_id| LinkPort::new(player_id, players)).collect()
+  }
+
+  fn sync(ports: &mut [LinkPort]) {
+    let states: Vec<UnitState> = ports.iter_mut().map(|port| port.take_state()).collect();
+
+    for (port, result) in ports.iter_mut().zip(resolve(&states)) {
+      port.apply_result(result);
+    }
+  }
+
+  #[test]
+  fn multiplayer_transfers_give_everyone_everyones_data() {
+    let mut ports = ports(3);
+
+    for (i, port) in ports.iter_mut().enumerate() {
+      assert_eq!(port.transfer_multiplayer(0x1111 * (i as u16 + 1)), None);
+    }
+
+    sync(&mut ports);
+
+    // the missing fourth player reads as all ones
+    for (i, port) in ports.iter_mut().enumerate() {
+      assert_eq!(port.transfer_multiplayer(0x1111 * (i as u16 + 1)), Some([0x1111, 0x2222, 0x3333, 0xffff]));
+    }
+  }
+
+  #[test]
+  fn only_the_parent_starts_multiplayer_transfers() {
+    let mut ports = ports(2);
+
+    ports[1].transfer_multiplayer(0x1234);
+
+    sync(&mut ports);
+
+    assert_eq!(ports[1].transfer_multiplayer(0x1234), None);
+
+    ports[0].transfer_multiplayer(0x5678);
+
+    sync(&mut ports);
+
+    assert_eq!(ports[1].transfer_multiplayer(0x1234), Some([0x5678, 0x1234, 0xffff, 0xffff]));
+  }
+
+  #[test]
+  fn results_waiting_to_be_picked_up_arent_sent_again() {
+    let mut ports = ports(2);
+
+    ports[0].transfer_multiplayer(0x5678);
+
+    sync(&mut ports);
+
+    let state = ports[0].take_state();
+
+    assert!(!state.multiplayer_request);
+  }
+
+  #[test]
+  fn normal_transfers_swap_between_the_clock_and_the_other_end() {
+    let mut ports = ports(2);
+
+    assert_eq!(ports[1].transfer_normal(0xbbbb_bbbb, 32, false), None);
+    assert_eq!(ports[0].transfer_normal(0xaaaa_aaaa, 32, true), None);
+
+    sync(&mut ports);
+
+    assert_eq!(ports[0].transfer_normal(0xaaaa_aaaa, 32, true), Some(0xbbbb_bbbb));
+    assert_eq!(ports[1].transfer_normal(0xbbbb_bbbb, 32, false), Some(0xaaaa_aaaa));
+  }
+
+  #[test]
+  fn normal_transfers_with_nobody_listening_read_all_ones() {
+    let mut ports = ports(2);
+
+    ports[0].transfer_normal(0x12, 8, true);
+
+    sync(&mut ports);
+
+    assert_eq!(ports[0].transfer_normal(0x12, 8, true), Some(0xff));
+  }
+
+  #[test]
+  fn uart_bytes_go_to_everyone_else() {
+    let mut ports = ports(3);
+
+    ports[0].send_uart(1);
+    ports[2].send_uart(3);
+
+    sync(&mut ports);
+
+    let received: Vec<Vec<u8>> = ports.iter_mut().map(|port| std::iter::from_fn(|| port.receive_uart()).collect()).collect();
+
+    assert_eq!(received, [vec![3], vec![1, 3], vec![1]]);
+  }
+}
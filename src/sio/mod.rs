@@ -0,0 +1,444 @@
+use std::{collections::VecDeque, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  cpu::{registers::interrupt_request_register::InterruptRequestRegister, CPU_CLOCK_SPEED},
+  scheduler::{EventType, Scheduler}
+};
+
+use self::{
+  registers::{
+    joybus_control_register::JoyBusControlRegister,
+    joybus_status_register::JoyBusStatusRegister,
+    serial_control_register::SerialControlRegister
+  },
+  transport::LinkTransport
+};
+
+pub mod dolphin_link;
+pub mod link_port;
+pub mod registers;
+pub mod socket_link;
+pub mod transport;
+
+// the internal clock in normal mode runs at either 256KHz or 2MHz
+const SLOW_CYCLES_PER_BIT: usize = 64;
+const FAST_CYCLES_PER_BIT: usize = 8;
+
+// each unit on a multiplayer link sends a start bit, 16 data bits and a stop bit
+const MULTIPLAYER_BITS_PER_UNIT: usize = 18;
+
+// a start bit, 8 data bits and a stop bit
+const UART_BITS_PER_BYTE: usize = 10;
+const UART_FIFO_SIZE: usize = 4;
+
+// how often to check the transport when waiting on the other side of the link
+const POLL_CYCLES: usize = 1024;
+
+// commands a joy bus master like the gamecube can send
+const JOY_STATUS: u8 = 0x00;
+const JOY_READ: u8 = 0x14;
+const JOY_WRITE: u8 = 0x15;
+const JOY_RESET: u8 = 0xff;
+
+// what the gba identifies itself as in reply to status and reset commands
+const JOY_DEVICE_TYPE: [u8; 2] = [0x00, 0x04];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SerialMode {
+  Normal8,
+  Normal32,
+  Multiplayer,
+  Uart,
+  GeneralPurpose,
+  JoyBus
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerialIO {
+  pub siocnt: SerialControlRegister,
+  pub rcnt: u16,
+  // SIODATA32 in normal mode, SIOMULTI0-3 in multiplayer mode
+  pub data: [u16; 4],
+  // SIODATA8 in normal and uart mode, SIOMLT_SEND in multiplayer mode
+  pub send_data: u16,
+  uart_send: Option<u8>,
+  uart_receive: VecDeque<u8>,
+  pub joycnt: JoyBusControlRegister,
+  pub joystat: JoyBusStatusRegister,
+  // JOY_RECV is written by the other side, JOY_TRANS is read by it
+  pub joy_receive: u32,
+  pub joy_send: u32,
+  #[serde(skip_serializing)]
+  #[serde(skip_deserializing)]
+  transport: Box<dyn LinkTransport>
+}
+
+impl Default for SerialIO {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl SerialIO {
+  pub fn new() -> Self {
+    Self {
+      siocnt: SerialControlRegister::from_bits_retain(0),
+      rcnt: 0,
+      data: [0; 4],
+      send_data: 0,
+      uart_send: None,
+      uart_receive: VecDeque::new(),
+      joycnt: JoyBusControlRegister::from_bits_retain(0),
+      joystat: JoyBusStatusRegister::from_bits_retain(0),
+      joy_receive: 0,
+      joy_send: 0,
+      transport: Box::default()
+    }
+  }
+
+  pub fn set_transport(&mut self, transport: Box<dyn LinkTransport>, scheduler: &mut Scheduler) {
+    self.transport = transport;
+    self.transport.set_joybus_mode(self.mode() == SerialMode::JoyBus);
+
+    match self.transport.sync_interval() {
+      Some(interval) => scheduler.schedule(EventType::LinkSync, interval),
+      None => scheduler.remove(EventType::LinkSync)
+    }
+  }
+
+  pub fn take_transport(&mut self) -> Box<dyn LinkTransport> {
+    std::mem::take(&mut self.transport)
+  }
+
+  pub fn take_link_error(&mut self) -> Option<io::Error> {
+    self.transport.take_error()
+  }
+
+  pub fn mode(&self) -> SerialMode {
+    if self.rcnt >> 15 & 0b1 == 0 {
+      match self.siocnt.mode_bits() {
+        0 => SerialMode::Normal8,
+        1 => SerialMode::Normal32,
+        2 => SerialMode::Multiplayer,
+        _ => SerialMode::Uart
+      }
+    } else if self.rcnt >> 14 & 0b1 == 0 {
+      SerialMode::GeneralPurpose
+    } else {
+      SerialMode::JoyBus
+    }
+  }
+
+  fn is_parent(&self) -> bool {
+    self.transport.connected() && self.transport.player_id() == 0
+  }
+
+  pub fn read_register(&mut self, address: u32) -> u16 {
+    match address {
+      0x400_0120 => self.data[0],
+      0x400_0122 => self.data[1],
+      0x400_0124 => self.data[2],
+      0x400_0126 => self.data[3],
+      0x400_0128 => self.read_control(),
+      0x400_012a => {
+        if self.mode() == SerialMode::Uart {
+          if let Some(byte) = self.uart_receive.pop_front() {
+            self.send_data = byte as u16;
+          }
+        }
+
+        self.send_data
+      }
+      0x400_0134 => self.rcnt,
+      0x400_0140 => self.joycnt.bits(),
+      0x400_0150 | 0x400_0152 => {
+        self.joystat.remove(JoyBusStatusRegister::RECEIVE);
+
+        (self.joy_receive >> ((address & 0b10) * 8)) as u16
+      }
+      0x400_0154 | 0x400_0156 => (self.joy_send >> ((address & 0b10) * 8)) as u16,
+      0x400_0158 => self.joystat.bits(),
+      _ => 0
+    }
+  }
+
+  fn read_control(&self) -> u16 {
+    let mut value = self.siocnt;
+
+    match self.mode() {
+      SerialMode::Normal8 | SerialMode::Normal32 => {
+        // SI is pulled high when nothing is connected
+        value.set(SerialControlRegister::SI_STATE, !self.transport.connected());
+      }
+      SerialMode::Multiplayer => {
+        value.set(SerialControlRegister::CHILD, !self.is_parent());
+        value.set(SerialControlRegister::ALL_READY, self.transport.connected());
+
+        value = SerialControlRegister::from_bits_retain(value.bits() & !(0b11 << 4) | (self.transport.player_id() as u16 & 0b11) << 4);
+      }
+      SerialMode::Uart => {
+        value.set(SerialControlRegister::SEND_FULL, self.uart_send.is_some());
+        value.set(SerialControlRegister::RECEIVE_EMPTY, self.uart_receive.is_empty());
+      }
+      _ => ()
+    }
+
+    value.bits()
+  }
+
+  pub fn write_register(&mut self, address: u32, value: u16, scheduler: &mut Scheduler, cycles: usize) {
+    match address {
+      0x400_0120 => self.data[0] = value,
+      0x400_0122 => self.data[1] = value,
+      0x400_0124 => self.data[2] = value,
+      0x400_0126 => self.data[3] = value,
+      0x400_0128 => self.write_control(value, scheduler, cycles),
+      0x400_012a => {
+        self.send_data = value;
+
+        if self.mode() == SerialMode::Uart && self.siocnt.contains(SerialControlRegister::SEND_ENABLE) {
+          self.uart_send = Some(value as u8);
+
+          scheduler.schedule_at(EventType::Serial, cycles + self.uart_byte_cycles());
+        }
+      }
+      0x400_0134 => {
+        let was_joybus = self.mode() == SerialMode::JoyBus;
+
+        self.rcnt = value & 0xc1ff;
+
+        if (self.mode() == SerialMode::JoyBus) != was_joybus {
+          self.transport.set_joybus_mode(!was_joybus);
+        }
+
+        // transports that sync handle joy bus commands at their sync points instead
+        if self.mode() == SerialMode::JoyBus && self.transport.connected() && self.transport.sync_interval().is_none() {
+          scheduler.schedule_at(EventType::Serial, cycles + POLL_CYCLES);
+        }
+      }
+      0x400_0140 => {
+        let flags = self.joycnt.bits() & !value & 0b111;
+
+        self.joycnt = JoyBusControlRegister::from_bits_retain(flags | value & JoyBusControlRegister::IRQ_ENABLE.bits());
+      }
+      0x400_0150 => self.joy_receive = (self.joy_receive & 0xffff_0000) | value as u32,
+      0x400_0152 => self.joy_receive = (self.joy_receive & 0xffff) | (value as u32) << 16,
+      0x400_0154 | 0x400_0156 => {
+        if address == 0x400_0154 {
+          self.joy_send = (self.joy_send & 0xffff_0000) | value as u32;
+        } else {
+          self.joy_send = (self.joy_send & 0xffff) | (value as u32) << 16;
+        }
+
+        self.joystat.insert(JoyBusStatusRegister::SEND);
+      }
+      0x400_0158 => {
+        let general_purpose = JoyBusStatusRegister::GENERAL_PURPOSE.bits();
+
+        self.joystat = JoyBusStatusRegister::from_bits_retain(self.joystat.bits() & !general_purpose | value & general_purpose);
+      }
+      _ => ()
+    }
+  }
+
+  fn write_control(&mut self, value: u16, scheduler: &mut Scheduler, cycles: usize) {
+    let was_started = self.siocnt.contains(SerialControlRegister::START);
+
+    self.siocnt = SerialControlRegister::from_bits_retain(value);
+
+    let started = self.siocnt.contains(SerialControlRegister::START) && !was_started;
+
+    match self.mode() {
+      SerialMode::Normal8 | SerialMode::Normal32 if started => {
+        if self.siocnt.contains(SerialControlRegister::INTERNAL_CLOCK) {
+          let bits = if self.mode() == SerialMode::Normal32 { 32 } else { 8 };
+
+          let cycles_per_bit = if self.siocnt.contains(SerialControlRegister::FAST_CLOCK) {
+            FAST_CYCLES_PER_BIT
+          } else {
+            SLOW_CYCLES_PER_BIT
+          };
+
+          scheduler.schedule_at(EventType::Serial, cycles + bits * cycles_per_bit);
+        } else if self.transport.connected() {
+          // the transfer happens whenever the other side clocks it
+          scheduler.schedule_at(EventType::Serial, cycles + POLL_CYCLES);
+        }
+      }
+      SerialMode::Multiplayer => {
+        if !self.is_parent() {
+          // only the parent can start a transfer, for children the bit is a read only busy flag
+          self.siocnt.set(SerialControlRegister::START, was_started);
+
+          if self.transport.connected() {
+            scheduler.schedule_at(EventType::Serial, cycles + POLL_CYCLES);
+          }
+        } else if started {
+          let cycles_per_bit = (CPU_CLOCK_SPEED / self.siocnt.baud_rate()) as usize;
+
+          scheduler.schedule_at(EventType::Serial, cycles + self.transport.players() * MULTIPLAYER_BITS_PER_UNIT * cycles_per_bit);
+        }
+      }
+      SerialMode::Uart if self.siocnt.contains(SerialControlRegister::RECEIVE_ENABLE) && self.transport.connected() => {
+        scheduler.schedule_at(EventType::Serial, cycles + POLL_CYCLES);
+      }
+      _ => ()
+    }
+  }
+
+  fn uart_byte_cycles(&self) -> usize {
+    UART_BITS_PER_BYTE * (CPU_CLOCK_SPEED / self.siocnt.baud_rate()) as usize
+  }
+
+  pub fn handle_link_sync(&mut self, scheduler: &mut Scheduler, interrupt_request: &mut InterruptRequestRegister, cycles_left: usize) {
+    self.transport.sync();
+
+    if self.mode() == SerialMode::JoyBus {
+      self.handle_joybus(interrupt_request);
+    }
+
+    if let Some(interval) = self.transport.sync_interval() {
+      scheduler.schedule_at(EventType::LinkSync, scheduler.cycles - cycles_left + interval);
+    }
+  }
+
+  /// answers a command from the joy bus master if one has arrived. unlike the other modes the gba
+  /// never starts a transfer itself, it only replies
+  fn handle_joybus(&mut self, interrupt_request: &mut InterruptRequestRegister) {
+    let Some(command) = self.transport.receive_joybus() else {
+      return;
+    };
+
+    let mut reply = Vec::new();
+
+    match command[0] {
+      JOY_STATUS | JOY_RESET => {
+        if command[0] == JOY_RESET {
+          self.joycnt.insert(JoyBusControlRegister::RESET);
+        }
+
+        reply.extend_from_slice(&JOY_DEVICE_TYPE);
+      }
+      JOY_READ => {
+        reply.extend_from_slice(&self.joy_send.to_le_bytes());
+
+        self.joycnt.insert(JoyBusControlRegister::SEND_COMPLETE);
+      }
+      JOY_WRITE => {
+        self.joy_receive = u32::from_le_bytes([command[1], command[2], command[3], command[4]]);
+
+        self.joystat.insert(JoyBusStatusRegister::RECEIVE);
+        self.joycnt.insert(JoyBusControlRegister::RECEIVE_COMPLETE);
+      }
+      // unknown commands don't get a reply
+      _ => return
+    }
+
+    reply.push(self.joystat.bits() as u8);
+
+    // the send flag stays set until the status including it has gone out
+    if command[0] == JOY_READ {
+      self.joystat.remove(JoyBusStatusRegister::SEND);
+    }
+
+    self.transport.send_joybus(&reply);
+
+    if command[0] != JOY_STATUS && self.joycnt.contains(JoyBusControlRegister::IRQ_ENABLE) {
+      interrupt_request.insert(InterruptRequestRegister::SERIAL_COMM);
+    }
+  }
+
+  /// finishes a transfer, or checks the transport again if still waiting on the other side
+  pub fn handle_event(&mut self, scheduler: &mut Scheduler, interrupt_request: &mut InterruptRequestRegister) {
+    let mut completed = false;
+    let mut poll = false;
+
+    match self.mode() {
+      mode @ (SerialMode::Normal8 | SerialMode::Normal32) if self.siocnt.contains(SerialControlRegister::START) => {
+        let internal_clock = self.siocnt.contains(SerialControlRegister::INTERNAL_CLOCK);
+
+        let (value, bits) = if mode == SerialMode::Normal32 {
+          (self.data[0] as u32 | (self.data[1] as u32) << 16, 32)
+        } else {
+          (self.send_data as u32 & 0xff, 8)
+        };
+
+        match self.transport.transfer_normal(value, bits, internal_clock) {
+          Some(received) => {
+            if mode == SerialMode::Normal32 {
+              self.data[0] = received as u16;
+              self.data[1] = (received >> 16) as u16;
+            } else {
+              self.send_data = (self.send_data & 0xff00) | (received as u16 & 0xff);
+            }
+
+            completed = true;
+          }
+          None => poll = self.transport.connected()
+        }
+      }
+      SerialMode::Multiplayer if self.siocnt.contains(SerialControlRegister::START) || !self.is_parent() => {
+        match self.transport.transfer_multiplayer(self.send_data) {
+          Some(data) => {
+            self.data = data;
+            self.siocnt.remove(SerialControlRegister::ERROR);
+
+            completed = true;
+          }
+          None => poll = self.transport.connected()
+        }
+
+        // children keep listening for the next transfer from the parent
+        if !self.is_parent() {
+          poll = self.transport.connected();
+        }
+      }
+      SerialMode::Uart => {
+        if let Some(byte) = self.uart_send.take() {
+          self.transport.send_uart(byte);
+
+          completed = true;
+        }
+
+        if self.siocnt.contains(SerialControlRegister::RECEIVE_ENABLE) {
+          let capacity = if self.siocnt.contains(SerialControlRegister::FIFO_ENABLE) { UART_FIFO_SIZE } else { 1 };
+
+          while self.uart_receive.len() < capacity {
+            match self.transport.receive_uart() {
+              Some(byte) => {
+                self.uart_receive.push_back(byte);
+
+                completed = true;
+              }
+              None => break
+            }
+          }
+
+          poll = self.transport.connected();
+        }
+      }
+      SerialMode::JoyBus => {
+        self.handle_joybus(interrupt_request);
+
+        poll = self.transport.connected() && self.transport.sync_interval().is_none();
+      }
+      _ => ()
+    }
+
+    if completed {
+      if self.mode() != SerialMode::Uart {
+        self.siocnt.remove(SerialControlRegister::START);
+      }
+
+      if self.siocnt.contains(SerialControlRegister::IRQ_ENABLE) {
+        interrupt_request.insert(InterruptRequestRegister::SERIAL_COMM);
+      }
+    }
+
+    if poll {
+      scheduler.schedule(EventType::Serial, POLL_CYCLES);
+    }
+  }
+}
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+bitflags! {
+  // the three flags are set by commands from the other side and cleared by writing 1 to them
+  #[derive(Copy, Clone, Serialize, Deserialize)]
+  #[serde(transparent)]
+  pub struct JoyBusControlRegister: u16 {
+    const RESET = 0b1;
+    const RECEIVE_COMPLETE = 0b1 << 1;
+    const SEND_COMPLETE = 0b1 << 2;
+    const IRQ_ENABLE = 0b1 << 6;
+  }
+}
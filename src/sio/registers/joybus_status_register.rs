@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+bitflags! {
+  #[derive(Copy, Clone, Serialize, Deserialize)]
+  #[serde(transparent)]
+  pub struct JoyBusStatusRegister: u16 {
+    // JOY_RECV has been written and not read yet
+    const RECEIVE = 0b1 << 1;
+    // JOY_TRANS has been written and not read by the other side yet
+    const SEND = 0b1 << 3;
+    const GENERAL_PURPOSE = 0b11 << 4;
+  }
+}
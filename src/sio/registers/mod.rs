@@ -0,0 +1,3 @@
+pub mod joybus_control_register;
+pub mod joybus_status_register;
+pub mod serial_control_register;
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+// baud rates for multiplayer and uart mode
+const BAUD_RATES: [u32; 4] = [9600, 38400, 57600, 115200];
+
+bitflags! {
+  // the meaning of most bits depends on the mode selected, so some of these overlap
+  #[derive(Copy, Clone, Serialize, Deserialize)]
+  #[serde(transparent)]
+  pub struct SerialControlRegister: u16 {
+    // normal mode
+    const INTERNAL_CLOCK = 0b1;
+    const FAST_CLOCK = 0b1 << 1;
+    const SI_STATE = 0b1 << 2;
+    const TRANSFER_32BIT = 0b1 << 12;
+    // multiplayer mode
+    const CHILD = 0b1 << 2;
+    const ALL_READY = 0b1 << 3;
+    const ERROR = 0b1 << 6;
+    // uart mode
+    const CTS = 0b1 << 2;
+    const SEND_FULL = 0b1 << 4;
+    const RECEIVE_EMPTY = 0b1 << 5;
+    const FIFO_ENABLE = 0b1 << 8;
+    const SEND_ENABLE = 0b1 << 10;
+    const RECEIVE_ENABLE = 0b1 << 11;
+    // normal and multiplayer mode
+    const START = 0b1 << 7;
+    const IRQ_ENABLE = 0b1 << 14;
+  }
+}
+
+impl SerialControlRegister {
+  pub fn mode_bits(&self) -> u16 {
+    (self.bits() >> 12) & 0b11
+  }
+
+  pub fn baud_rate(&self) -> u32 {
+    BAUD_RATES[(self.bits() & 0b11) as usize]
+  }
+
+  pub fn multiplayer_id(&self) -> usize {
+    ((self.bits() >> 4) & 0b11) as usize
+  }
+}
@@ -0,0 +1,338 @@
+// link cable between emulator instances over a tcp or unix domain socket. the host is always the
+// multiplayer parent and every other instance connects to it. the instances run in lockstep:
+// every LINK_SYNC_CYCLES each one stops and sends the host whatever transfers it has pending,
+// then waits for the host to send back the results. a transfer therefore completes at the same
+// sync point on every instance no matter how fast each one is running.
+
+use std::{
+  io::{self, Read, Write},
+  net::{TcpListener, TcpStream},
+  time::Duration
+};
+
+#[cfg(unix)]
+use std::{fs, os::unix::net::{UnixListener, UnixStream}};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{link_port::{resolve, LinkPort, SyncResult, MAX_PLAYERS}, transport::LinkTransport};
+
+const LINK_SYNC_CYCLES: usize = 16384;
+
+// states and results only hold a few hundred uart bytes at most, so anything bigger is garbage
+const MAX_MESSAGE_SIZE: usize = 0x1_0000;
+
+// every instance waits on the others at each sync point, so one that's silent for this long has
+// crashed or hung rather than just fallen behind
+const LINK_TIMEOUT: Duration = Duration::from_secs(30);
+
+enum LinkStream {
+  Tcp(TcpStream),
+  #[cfg(unix)]
+  Unix(UnixStream)
+}
+
+impl LinkStream {
+  fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+    match self {
+      LinkStream::Tcp(stream) => stream.set_read_timeout(timeout),
+      #[cfg(unix)]
+      LinkStream::Unix(stream) => stream.set_read_timeout(timeout)
+    }
+  }
+}
+
+impl Read for LinkStream {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    match self {
+      LinkStream::Tcp(stream) => stream.read(buf),
+      #[cfg(unix)]
+      LinkStream::Unix(stream) => stream.read(buf)
+    }
+  }
+}
+
+impl Write for LinkStream {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match self {
+      LinkStream::Tcp(stream) => stream.write(buf),
+      #[cfg(unix)]
+      LinkStream::Unix(stream) => stream.write(buf)
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    match self {
+      LinkStream::Tcp(stream) => stream.flush(),
+      #[cfg(unix)]
+      LinkStream::Unix(stream) => stream.flush()
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Handshake {
+  player_id: usize,
+  players: usize
+}
+
+pub struct SocketLink {
+  // the host has one stream per child, children have a single stream to the host
+  streams: Vec<LinkStream>,
+  port: LinkPort,
+  error: Option<io::Error>
+}
+
+impl SocketLink {
+  /// listens on address and blocks until players - 1 other instances have connected. addresses
+  /// starting with unix: are unix domain socket paths, anything else is a tcp address
+  pub fn host(address: &str, players: usize) -> io::Result<Self> {
+    if !(2..=MAX_PLAYERS).contains(&players) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("a link needs 2 to {MAX_PLAYERS} players")));
+    }
+
+    let mut streams = Vec::new();
+
+    match address.strip_prefix("unix:") {
+      #[cfg(unix)]
+      Some(path) => {
+        remove_stale_socket(path)?;
+
+        let listener = UnixListener::bind(path)?;
+
+        let accepted = (1..players)
+          .map(|_| listener.accept().map(|(stream, _)| LinkStream::Unix(stream)))
+          .collect::<io::Result<Vec<_>>>();
+
+        // nobody else connects once everyone is in, so the socket file isn't needed anymore
+        let _ = fs::remove_file(path);
+
+        streams = accepted?;
+      }
+      #[cfg(not(unix))]
+      Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "unix domain sockets aren't supported on this platform")),
+      None => {
+        let listener = TcpListener::bind(address)?;
+
+        while streams.len() < players - 1 {
+          let stream = listener.accept()?.0;
+
+          stream.set_nodelay(true)?;
+
+          streams.push(LinkStream::Tcp(stream));
+        }
+      }
+    }
+
+    for (i, stream) in streams.iter_mut().enumerate() {
+      stream.set_read_timeout(Some(LINK_TIMEOUT))?;
+
+      write_message(stream, &Handshake { player_id: i + 1, players })?;
+    }
+
+    Ok(Self::new(streams, 0, players))
+  }
+
+  /// connects to an instance started with host
+  pub fn connect(address: &str) -> io::Result<Self> {
+    let mut stream = match address.strip_prefix("unix:") {
+      #[cfg(unix)]
+      Some(path) => LinkStream::Unix(UnixStream::connect(path)?),
+      #[cfg(not(unix))]
+      Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "unix domain sockets aren't supported on this platform")),
+      None => {
+        let stream = TcpStream::connect(address)?;
+
+        stream.set_nodelay(true)?;
+
+        LinkStream::Tcp(stream)
+      }
+    };
+
+    stream.set_read_timeout(Some(LINK_TIMEOUT))?;
+
+    let handshake: Handshake = read_message(&mut stream)?;
+
+    Ok(Self::new(vec![stream], handshake.player_id, handshake.players))
+  }
+
+  fn new(streams: Vec<LinkStream>, player_id: usize, players: usize) -> Self {
+    Self {
+      streams,
+      port: LinkPort::new(player_id, players),
+      error: None
+    }
+  }
+
+  fn sync_host(&mut self) -> io::Result<()> {
+    let mut states = vec![self.port.take_state()];
+
+    for stream in &mut self.streams {
+      states.push(read_message(stream)?);
+    }
+
+    let mut results = resolve(&states);
+
+    for (stream, result) in self.streams.iter_mut().zip(results.drain(1..)) {
+      write_message(stream, &result)?;
+    }
+
+    if let Some(result) = results.pop() {
+      self.port.apply_result(result);
+    }
+
+    Ok(())
+  }
+
+  fn sync_child(&mut self) -> io::Result<()> {
+    let state = self.port.take_state();
+
+    write_message(&mut self.streams[0], &state)?;
+
+    let result: SyncResult = read_message(&mut self.streams[0])?;
+
+    self.port.apply_result(result);
+
+    Ok(())
+  }
+}
+
+/// removes a socket file left behind by a host that didn't shut down cleanly. anything that isn't
+/// a socket, or a socket something is still listening on, is left alone
+#[cfg(unix)]
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+  use std::os::unix::fs::FileTypeExt;
+
+  match fs::symlink_metadata(path) {
+    Ok(metadata) if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() => fs::remove_file(path),
+    _ => Ok(())
+  }
+}
+
+fn write_message<T: Serialize>(stream: &mut LinkStream, message: &T) -> io::Result<()> {
+  let bytes = bincode::serialize(message).map_err(io::Error::other)?;
+
+  stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+  stream.write_all(&bytes)?;
+  stream.flush()
+}
+
+fn read_message<T: DeserializeOwned>(stream: &mut LinkStream) -> io::Result<T> {
+  let mut len = [0; 4];
+
+  stream.read_exact(&mut len)?;
+
+  let len = u32::from_le_bytes(len) as usize;
+
+  if len > MAX_MESSAGE_SIZE {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("link message of {len} bytes is too big")));
+  }
+
+  let mut bytes = vec![0; len];
+
+  stream.read_exact(&mut bytes)?;
+
+  bincode::deserialize(&bytes).map_err(io::Error::other)
+}
+
+impl LinkTransport for SocketLink {
+  fn connected(&self) -> bool {
+    self.port.connected()
+  }
+
+  fn player_id(&self) -> usize {
+    self.port.player_id()
+  }
+
+  fn players(&self) -> usize {
+    self.port.players()
+  }
+
+  fn transfer_normal(&mut self, value: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+    self.port.transfer_normal(value, bits, internal_clock)
+  }
+
+  fn transfer_multiplayer(&mut self, value: u16) -> Option<[u16; 4]> {
+    self.port.transfer_multiplayer(value)
+  }
+
+  fn send_uart(&mut self, byte: u8) {
+    self.port.send_uart(byte);
+  }
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    self.port.receive_uart()
+  }
+
+  fn sync_interval(&self) -> Option<usize> {
+    self.port.connected.then_some(LINK_SYNC_CYCLES)
+  }
+
+  fn sync(&mut self) {
+    if !self.port.connected {
+      return;
+    }
+
+    let result = if self.port.player_id == 0 { self.sync_host() } else { self.sync_child() };
+
+    if let Err(error) = result {
+      // unix sockets report a read timeout as WouldBlock, which doesn't say much
+      self.error = Some(match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(io::ErrorKind::TimedOut, "the other end of the link stopped responding"),
+        _ => error
+      });
+      self.port.connected = false;
+    }
+  }
+
+  fn take_error(&mut self) -> Option<io::Error> {
+    self.error.take()
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+
+  fn pair() -> (LinkStream, LinkStream) {
+    let (a, b) = UnixStream::pair().unwrap();
+
+    (LinkStream::Unix(a), LinkStream::Unix(b))
+  }
+
+  #[test]
+  fn messages_round_trip() {
+    let (mut a, mut b) = pair();
+
+    write_message(&mut a, &Handshake { player_id: 2, players: 3 }).unwrap();
+
+    let handshake: Handshake = read_message(&mut b).unwrap();
+
+    assert_eq!((handshake.player_id, handshake.players), (2, 3));
+  }
+
+  #[test]
+  fn oversized_messages_are_rejected() {
+    let (mut a, mut b) = pair();
+
+    a.write_all(&u32::MAX.to_le_bytes()).unwrap();
+
+    let error = read_message::<Handshake>(&mut b).err().unwrap();
+
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn a_silent_host_times_out() {
+    let (a, _host) = pair();
+
+    a.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+
+    let mut link = SocketLink::new(vec![a], 1, 2);
+
+    link.sync();
+
+    assert!(!link.connected());
+    assert_eq!(link.take_error().map(|error| error.kind()), Some(io::ErrorKind::TimedOut));
+  }
+}
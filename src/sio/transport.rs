@@ -0,0 +1,119 @@
+use std::{collections::VecDeque, io};
+
+/// the other end of the link port. a transport carries data between this gba and whatever is
+/// plugged in, whether that's nothing at all, a cable looping back on itself, or another emulator
+pub trait LinkTransport {
+  /// whether anything is plugged into the link port
+  fn connected(&self) -> bool;
+
+  /// this unit's position on a multiplayer link, 0 being the parent
+  fn player_id(&self) -> usize {
+    0
+  }
+
+  /// number of units on a multiplayer link, including this one
+  fn players(&self) -> usize {
+    1
+  }
+
+  /// shifts value out and returns the bits shifted in from the other side. returns None when the
+  /// transfer can't happen yet, like when waiting on an external clock that hasn't arrived
+  fn transfer_normal(&mut self, value: u32, bits: u32, internal_clock: bool) -> Option<u32>;
+
+  /// sends this unit's halfword and returns the halfwords of every unit on the link, with 0xffff in
+  /// unused slots. only the parent can start a transfer, children return None until one happens
+  fn transfer_multiplayer(&mut self, value: u16) -> Option<[u16; 4]>;
+
+  fn send_uart(&mut self, byte: u8);
+
+  fn receive_uart(&mut self) -> Option<u8>;
+
+  /// the next command from a joy bus master like a gamecube, as the command byte followed by
+  /// up to 4 bytes of data
+  fn receive_joybus(&mut self) -> Option<[u8; 5]> {
+    None
+  }
+
+  /// replies to the last joy bus command received
+  fn send_joybus(&mut self, _reply: &[u8]) {}
+
+  /// called when the game switches the link port in or out of joy bus mode
+  fn set_joybus_mode(&mut self, _enabled: bool) {}
+
+  /// how often, in cycles, sync has to be called to keep linked instances in lockstep
+  fn sync_interval(&self) -> Option<usize> {
+    None
+  }
+
+  /// exchanges pending transfers with the other side, blocking until it reaches the same point
+  fn sync(&mut self) {}
+
+  /// why the link was lost, kept for the frontend to pick up
+  fn take_error(&mut self) -> Option<io::Error> {
+    None
+  }
+}
+
+impl Default for Box<dyn LinkTransport> {
+  fn default() -> Self {
+    Box::new(NoCable)
+  }
+}
+
+/// nothing plugged in. the input lines are pulled high, so anything clocked in reads as all ones
+pub struct NoCable;
+
+impl LinkTransport for NoCable {
+  fn connected(&self) -> bool {
+    false
+  }
+
+  fn transfer_normal(&mut self, _value: u32, bits: u32, internal_clock: bool) -> Option<u32> {
+    // with no cable there's nobody to provide an external clock
+    internal_clock.then_some(if bits == 32 { u32::MAX } else { (1 << bits) - 1 })
+  }
+
+  fn transfer_multiplayer(&mut self, _value: u16) -> Option<[u16; 4]> {
+    None
+  }
+
+  fn send_uart(&mut self, _byte: u8) {}
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    None
+  }
+}
+
+/// a cable connecting the port to itself, so everything sent is received right back
+#[derive(Default)]
+pub struct Loopback {
+  uart_bytes: VecDeque<u8>
+}
+
+impl Loopback {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl LinkTransport for Loopback {
+  fn connected(&self) -> bool {
+    true
+  }
+
+  fn transfer_normal(&mut self, value: u32, _bits: u32, internal_clock: bool) -> Option<u32> {
+    internal_clock.then_some(value)
+  }
+
+  fn transfer_multiplayer(&mut self, value: u16) -> Option<[u16; 4]> {
+    Some([value, 0xffff, 0xffff, 0xffff])
+  }
+
+  fn send_uart(&mut self, byte: u8) {
+    self.uart_bytes.push_back(byte);
+  }
+
+  fn receive_uart(&mut self) -> Option<u8> {
+    self.uart_bytes.pop_front()
+  }
+}
@@ -511,24 +511,26 @@ export class UI {
     let rom = await this.getBinaryData(e)
 
     if (rom != null) {
-      this.emulator!.load(new Uint8Array(rom))
-      this.gameData = new Uint8Array(rom)
-
       this.gameName = this.fileName.split('/').pop() ?? ""
       this.gameName = this.gameName.substring(0, this.gameName.lastIndexOf('.'))
 
-      this.stateManager = new StateManager(this.emulator!, this.wasm, this.gameName, this.db)
+      const gameName = this.gameName
 
-      let saveData = this.cloudService.usingCloud ? (await this.cloudService.getSave(this.gameName!)).data : new Uint8Array(JSON.parse(localStorage.getItem(this.gameName) ?? "null"))
+      const saveData = this.cloudService.usingCloud ? (await this.cloudService.getSave(gameName)).data : JSON.parse(localStorage.getItem(gameName) ?? "null")
 
-      if (saveData != null) {
-        if (saveData.length == 0) {
-          saveData = new Uint8Array(this.emulator!.backup_file_size())
-        }
-        this.emulator!.load_save(saveData)
-      } else {
-        this.emulator!.load_save(new Uint8Array(this.emulator!.backup_file_size()))
-      }
+      // the emulator starts a blank save when there isn't one
+      const save = saveData != null && saveData.length > 0 ? new Uint8Array(saveData) : undefined
+
+      this.emulator!.load_save(save, (saveMemory: Uint8Array) => {
+        const clonedSave = new Uint8Array(saveMemory)
+
+        this.cloudService.usingCloud ? this.cloudService.uploadSave(gameName, clonedSave) : localStorage.setItem(gameName, JSON.stringify(Array.from(clonedSave)))
+      })
+
+      this.emulator!.load(new Uint8Array(rom))
+      this.gameData = new Uint8Array(rom)
+
+      this.stateManager = new StateManager(this.emulator!, this.wasm, this.gameName, this.db)
 
       this.audioManager!.startAudio()
       this.frameNumber = requestAnimationFrame((time) => this.run(time))
@@ -540,7 +542,14 @@ export class UI {
 
     if (bios != null) {
       const biosUintArray = new Uint8Array(bios)
-      this.emulator!.load_bios(biosUintArray)
+
+      try {
+        this.emulator!.load_bios(biosUintArray)
+      } catch (error) {
+        alert(`couldn't load bios: ${error}`)
+
+        return
+      }
 
       this.biosData = biosUintArray
 
@@ -606,19 +615,6 @@ export class UI {
 
       this.joypad!.handleJoypadInput()
     }
-    if (this.emulator!.has_saved()) {
-      this.emulator!.set_saved(false)
-
-      const saveMemory = new Uint8Array(this.wasm!.memory.buffer, this.emulator!.backup_file_pointer(), this.emulator!.backup_file_size())
-
-      let gameName = this.fileName.split('/').pop()
-      gameName = gameName!.substring(0, gameName!.lastIndexOf('.'))
-
-      const clonedSave = new Uint8Array(Array.from(saveMemory))
-
-      this.cloudService.usingCloud ?  this.cloudService.uploadSave(gameName, clonedSave) : localStorage.setItem(gameName, JSON.stringify(Array.from(saveMemory)))
-    }
-
     this.frames++
 
     this.frameNumber = requestAnimationFrame((time) => this.run(time))
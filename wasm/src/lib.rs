@@ -3,7 +3,7 @@ extern crate console_error_panic_hook;
 
 use std::{collections::HashMap, panic, sync::Arc};
 
-use gba_emulator::{apu::NUM_SAMPLES, cartridge::BackupMedia, cpu::{registers::key_input_register::KeyInputRegister, CPU}, gpu::CYCLES_PER_FRAME};
+use gba_emulator::{apu::NUM_SAMPLES, cartridge::{save_format::SaveFormat, save_storage::CallbackStorage, time_source::TimeSource}, cpu::{registers::key_input_register::KeyInputRegister, CPU}, gpu::CYCLES_PER_FRAME};
 use ringbuf::{storage::Heap, traits::{Consumer, Split}, wrap::caching::Caching, HeapRb, SharedRb};
 use wasm_bindgen::prelude::*;
 
@@ -11,6 +11,34 @@ use wasm_bindgen::prelude::*;
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
+
+    type Date;
+
+    #[wasm_bindgen(constructor)]
+    fn new() -> Date;
+
+    #[wasm_bindgen(method, js_name = getTimezoneOffset)]
+    fn get_timezone_offset(this: &Date) -> f64;
+
+    #[wasm_bindgen(static_method_of = Date)]
+    fn now() -> f64;
+
+    #[wasm_bindgen(typescript_type = "(save: Uint8Array) => void")]
+    pub type SaveListener;
+
+    #[wasm_bindgen(method, js_name = call)]
+    fn call(this: &SaveListener, context: &JsValue, save: &[u8]);
+}
+
+// the browser's local time, since wasm has no system clock of its own
+struct JsClock;
+
+impl TimeSource for JsClock {
+  fn now(&self) -> i64 {
+    let timezone_offset = Date::new().get_timezone_offset() as i64 * 60;
+
+    (Date::now() / 1000.0) as i64 - timezone_offset
+  }
 }
 
 macro_rules! console_log {
@@ -62,8 +90,12 @@ impl WasmEmulator {
     key_map.insert(ButtonEvent::Left, KeyInputRegister::Left);
     key_map.insert(ButtonEvent::Right, KeyInputRegister::Right);
 
+    let mut cpu = CPU::new(producer);
+
+    cpu.set_block_cache_enabled(true);
+
     WasmEmulator {
-      cpu: CPU::new(producer),
+      cpu,
       key_map,
       state_len: 0,
       consumer,
@@ -105,79 +137,31 @@ impl WasmEmulator {
     self.cpu.reload_game(rom.to_vec());
   }
 
-  pub fn load_save(&mut self, data: &[u8]) {
-    match &mut self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.buffer = data.to_vec();
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.buffer = data.to_vec();
-      }
-      BackupMedia::Sram(file) => {
-        file.buffer = data.to_vec();
-      }
-      _ => ()
-    }
-  }
+  /// the save the next game starts with, if it has one, set before loading it. the listener is
+  /// handed the whole save each time the game finishes writing to it, and has to copy it to keep it
+  pub fn load_save(&mut self, save: Option<Vec<u8>>, listener: SaveListener) {
+    let storage = CallbackStorage::new(save, Box::new(move |data, _| {
+      listener.call(&JsValue::NULL, data);
 
-  pub fn backup_file_pointer(&self) -> *const u8 {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.buffer.as_ptr()
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.buffer.as_ptr()
-      }
-      BackupMedia::Sram(file) => {
-        file.buffer.as_ptr()
-      }
-      _ => [].as_ptr()
-    }
-  }
+      Ok(())
+    }));
 
-  pub fn backup_file_size(&self) -> usize {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.size
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.size
-      }
-      BackupMedia::Sram(file) => {
-        file.size
-      }
-      _ => 0
-    }
+    self.cpu.cartridge.set_save_storage(Box::new(storage));
   }
 
-  pub fn has_saved(&self) -> bool {
-    match &self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.has_saved
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.has_saved
-      }
-      BackupMedia::Sram(file) => {
-        file.has_saved
-      }
-      _ => false
-    }
+  /// replaces the save with one from another emulator or backup device. the format goes by name or
+  /// extension, like "sps" or "swapped-eeprom", and is guessed when it isn't given
+  pub fn import_save(&mut self, data: &[u8], format: Option<String>) -> Result<(), String> {
+    let save_format = format.map(|format| SaveFormat::from_name(&format).ok_or(format!("unknown save format {format}"))).transpose()?;
+
+    self.cpu.cartridge.import_save(data, save_format).map(|_| ())
   }
 
-  pub fn set_saved(&mut self, val: bool) {
-    match &mut self.cpu.cartridge.backup {
-      BackupMedia::Eeprom(eeprom) => {
-        eeprom.chip.memory.has_saved = val;
-      }
-      BackupMedia::Flash(flash) => {
-        flash.memory.has_saved = val;
-      }
-      BackupMedia::Sram(file) => {
-        file.has_saved = val;
-      }
-      _ => ()
-    }
+  /// the save converted to the given format, by name or extension like "sps"
+  pub fn export_save(&self, format: &str) -> Result<Vec<u8>, String> {
+    let save_format = SaveFormat::from_name(format).ok_or(format!("unknown save format {format}"))?;
+
+    self.cpu.cartridge.export_save(save_format)
   }
 
   pub fn update_buffer(&mut self, left_buffer: &mut [f32], right_buffer: &mut [f32]) {
@@ -211,8 +195,7 @@ impl WasmEmulator {
     }
 
     if self.cpu.scheduler.cycles >= 0xfff0_0000  {
-      let to_subtract = self.cpu.scheduler.rebase_cycles();
-      self.cpu.cycles -= to_subtract;
+      self.cpu.rebase_cycles();
     }
 
     self.cpu.gpu.frame_finished = false;
@@ -224,11 +207,35 @@ impl WasmEmulator {
 
   pub fn load(&mut self, rom: &[u8]) {
     self.cpu.load_game(rom.to_vec(), None);
+    self.cpu.set_rtc_time_source(Box::new(JsClock));
     self.cpu.skip_bios();
   }
 
-  pub fn load_bios(&mut self, bios: &[u8]) {
-    self.cpu.load_bios(bios.to_vec());
+  pub fn game_title(&self) -> String {
+    self.cpu.cartridge.title().to_string()
+  }
+
+  pub fn game_code(&self) -> String {
+    self.cpu.cartridge.game_code().to_string()
+  }
+
+  /// the save type found by watching the game when it isn't in the game database, as named in
+  /// game database files
+  pub fn detected_save_type(&self) -> Option<String> {
+    self.cpu.cartridge.detected_save_type().map(|save_type| save_type.name().to_string())
+  }
+
+  pub fn has_solar_sensor(&self) -> bool {
+    self.cpu.cartridge.solar_level().is_some()
+  }
+
+  /// from 0 for darkness up to 10 for full sunlight
+  pub fn set_solar_level(&mut self, level: u8) {
+    self.cpu.cartridge.set_solar_level(level);
+  }
+
+  pub fn load_bios(&mut self, bios: &[u8]) -> Result<(), String> {
+    self.cpu.load_bios(bios.to_vec())
   }
 
   pub fn update_input(&mut self, button_event: ButtonEvent, is_pressed: bool) {